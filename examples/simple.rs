@@ -37,7 +37,7 @@ fn read_all(fd: BorrowedFd, mut cnt: usize) -> nix::Result<Vec<u8>> {
     let mut res = Vec::<u8>::with_capacity(cnt);
 
     let buf = unsafe {
-	core::slice::from_raw_parts_mut(res.as_mut_ptr() as * mut u8, cnt)
+	core::slice::from_raw_parts_mut(res.as_mut_ptr(), cnt)
     };
 
     let mut pos = 0;
@@ -50,7 +50,7 @@ fn read_all(fd: BorrowedFd, mut cnt: usize) -> nix::Result<Vec<u8>> {
 		pos += l;
 		cnt -= l;
 	    }
-	    Err(e) if e == nix::Error::EAGAIN	=> {
+	    Err(nix::Error::EAGAIN)	=> {
 		poll(fd, PollFlags::POLLIN)?;
 	    }
 
@@ -147,7 +147,11 @@ pub struct fh_t(u64);
 impl fh_t {
     pub const fn from_ffi(v: u64) -> Self {
 	assert!(v > 0);
-	Self(0)
+	Self(v)
+    }
+
+    pub const fn as_ffi(self) -> u64 {
+	self.0
     }
 }
 
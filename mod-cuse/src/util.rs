@@ -95,13 +95,6 @@ macro_rules! declare_flags {
 		    res.push(Cow::Borrowed("0"))
 		}
 
-		struct RawString(String);
-		impl std::fmt::Debug for RawString {
-		    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-			f.write_str(&self.0)
-		    }
-		}
-
 		f.write_str(&res.join(&"|"))
 	    }
 	}
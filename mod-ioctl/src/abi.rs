@@ -0,0 +1,703 @@
+//! Numbering of ioctls on the different architecture families
+//!
+//! Every supported ioctl has a stable [`Id`] which is transported over
+//! the network.  The native number is resolved by the table of the
+//! [`Abi`]; ioctls which are built by `_IOC()` are encoded with the
+//! [`Layout`] of the family.
+//!
+//! The termios flags, control characters and modem lines are
+//! transported with the `asm-generic` values of
+//! [`crate::termbits`] and translated from and to the native ones.
+
+#[path = "abi_termios.rs"]
+mod termios;
+#[path = "abi_termbits.rs"]
+mod termbits;
+
+use termbits::Termbits;
+use crate::termbits::{B0, IBSHIFT};
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BitGeo {
+    bits:	u8,
+    pos:	u8,
+}
+
+impl BitGeo {
+    pub const fn new(bits: u8) -> Self {
+	// we do not handle bits == 32 correctly in the bit mask operations
+	// below
+	assert!(bits <= 31);
+
+	Self {
+	    bits:	bits,
+	    pos:	0,
+	}
+    }
+
+    pub const fn next(&self, bits: u8) -> Self {
+	// we do not handle bits == 32 correctly in the bit mask operations
+	// below
+	assert!(bits <= 31);
+	assert!(self.pos + self.bits + bits <= 32);
+
+	Self {
+	    bits:	bits,
+	    pos:	self.pos + self.bits,
+	}
+    }
+
+    const fn mask(&self) -> u32 {
+	(1 << self.bits) - 1
+    }
+
+    pub const fn encode(&self, val: u32) -> u32 {
+	assert!(val < (1 << self.bits));
+
+	val << self.pos
+    }
+
+    pub const fn decode(&self, val: u32) -> u32 {
+	(val >> self.pos) & self.mask()
+    }
+}
+
+/// Bit layout of `_IOC()` numbers
+#[derive(Debug, Clone, Copy)]
+pub struct Layout {
+    pub(crate) nr:		BitGeo,
+    pub(crate) tp:		BitGeo,
+    pub(crate) size:		BitGeo,
+    pub(crate) dir:		BitGeo,
+
+    pub(crate) dir_none:	u32,
+    pub(crate) dir_read:	u32,
+    pub(crate) dir_write:	u32,
+}
+
+impl Layout {
+    const fn new(size_bits: u8, dir_bits: u8, dir_none: u32, dir_read: u32, dir_write: u32) -> Self {
+	let nr = BitGeo::new(8);
+	let tp = nr.next(8);
+	let size = tp.next(size_bits);
+	let dir = size.next(dir_bits);
+
+	Self {
+	    nr:		nr,
+	    tp:		tp,
+	    size:	size,
+	    dir:	dir,
+	    dir_none:	dir_none,
+	    dir_read:	dir_read,
+	    dir_write:	dir_write,
+	}
+    }
+
+    pub const fn ioc(&self, read: bool, write: bool, tp: u8, nr: u32, sz: usize) -> u32 {
+	let dir = match (read, write) {
+	    (false, false)	=> self.dir_none,
+	    (true, false)	=> self.dir_read,
+	    (false, true)	=> self.dir_write,
+	    (true, true)	=> self.dir_read | self.dir_write,
+	};
+
+	self.dir.encode(dir) | self.tp.encode(tp as u32) |
+	    self.nr.encode(nr) | self.size.encode(sz as u32)
+    }
+
+    pub const fn is_read(&self, cmd: u32) -> bool {
+	let dir = self.dir.decode(cmd);
+
+	dir != self.dir_none && (dir & self.dir_read) != 0
+    }
+
+    pub const fn is_write(&self, cmd: u32) -> bool {
+	let dir = self.dir.decode(cmd);
+
+	dir != self.dir_none && (dir & self.dir_write) != 0
+    }
+
+    pub const fn get_type(&self, cmd: u32) -> u8 {
+	self.tp.decode(cmd) as u8
+    }
+
+    pub const fn get_nr(&self, cmd: u32) -> u32 {
+	self.nr.decode(cmd)
+    }
+
+    pub const fn get_size(&self, cmd: u32) -> usize {
+	self.size.decode(cmd) as usize
+    }
+}
+
+/// Definition of an ioctl number in the table of an [`Abi`]
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Spec {
+    /// legacy number which does not follow the `_IOC()` scheme
+    Bad(u32),
+    Io(u8, u32),
+    Ior(u8, u32, usize),
+    Iow(u8, u32, usize),
+    Iowr(u8, u32, usize),
+}
+
+impl Spec {
+    pub const fn encode(self, layout: &Layout) -> u32 {
+	match self {
+	    Self::Bad(v)		=> v,
+	    Self::Io(tp, nr)		=> layout.ioc(false, false, tp, nr, 0),
+	    Self::Ior(tp, nr, sz)	=> layout.ioc(true,  false, tp, nr, sz),
+	    Self::Iow(tp, nr, sz)	=> layout.ioc(false, true,  tp, nr, sz),
+	    Self::Iowr(tp, nr, sz)	=> layout.ioc(true,  true,  tp, nr, sz),
+	}
+    }
+
+    /// Size of the argument; `None` for numbers without size bits
+    pub const fn size(self) -> Option<usize> {
+	match self {
+	    Self::Bad(_) |
+	    Self::Io(..)		=> None,
+	    Self::Ior(_, _, sz) |
+	    Self::Iow(_, _, sz) |
+	    Self::Iowr(_, _, sz)	=> Some(sz),
+	}
+    }
+}
+
+/// Architecture independent identifier of an ioctl
+#[repr(transparent)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Id(pub(crate) u16);
+
+impl Id {
+    pub const fn as_numeric(self) -> u16 {
+	self.0
+    }
+
+    pub fn try_to_string(self) -> Option<&'static str> {
+	for f in [Self::map_termios] {
+	    if let Some(s) = f(self) {
+		return Some(s);
+	    }
+	}
+
+	None
+    }
+}
+
+impl From<u16> for Id {
+    fn from(value: u16) -> Self {
+	Self(value)
+    }
+}
+
+impl std::fmt::Debug for Id {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	match self.try_to_string() {
+	    None	=> f.write_fmt(format_args!("#{}", self.0)),
+	    Some(id)	=> f.write_str(id),
+	}
+    }
+}
+
+/// Flags which are translated between the architecture families
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flags {
+    Iflag,
+    Oflag,
+    Lflag,
+    /// `TIOCM_*` bits
+    Modem,
+}
+
+/// Translates the bits of `v` by the `(wire, native)` pairs of `map`.
+/// Bits which are not in the map are kept unless they are used
+/// otherwise on the other side.
+fn xlat_bits(map: &[(u32, u32)], v: u32, to_wire: bool) -> u32 {
+    let pairs = map.iter()
+	.map(|&(wire, native)| match to_wire {
+	    true	=> (native, wire),
+	    false	=> (wire, native),
+	});
+
+    let mask = pairs.clone().fold(0, |m, (from, to)| m | from | to);
+
+    pairs.fold(v & !mask, |res, (from, to)| match v & from {
+	0	=> res,
+	_	=> res | to,
+    })
+}
+
+/// Family of architectures which share the numbering of ioctls
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Abi {
+    /// `asm-generic` numbering (x86, arm, riscv, s390, ...)
+    Generic,
+    PowerPc,
+    Mips,
+    Sparc,
+}
+
+impl Abi {
+    pub const ALL: [Self; 4] = [ Self::Generic, Self::PowerPc, Self::Mips, Self::Sparc ];
+
+    pub const NATIVE: Self = {
+	if cfg!(any(target_arch = "powerpc", target_arch = "powerpc64")) {
+	    Self::PowerPc
+	} else if cfg!(any(target_arch = "mips", target_arch = "mips64",
+			   target_arch = "mips32r6", target_arch = "mips64r6")) {
+	    Self::Mips
+	} else if cfg!(any(target_arch = "sparc", target_arch = "sparc64")) {
+	    Self::Sparc
+	} else {
+	    Self::Generic
+	}
+    };
+
+    /// Returns the family of an architecture as reported by
+    /// `std::env::consts::ARCH`
+    pub fn from_arch(arch: &str) -> Self {
+	match arch {
+	    a if a.starts_with("powerpc")	=> Self::PowerPc,
+	    a if a.starts_with("mips")		=> Self::Mips,
+	    a if a.starts_with("sparc")		=> Self::Sparc,
+	    _					=> Self::Generic,
+	}
+    }
+
+    pub const fn layout(self) -> Layout {
+	match self {
+	    Self::Generic	=> Layout::new(14, 2, 0, 2, 1),
+	    Self::PowerPc |
+	    Self::Mips |
+	    Self::Sparc		=> Layout::new(13, 3, 1, 2, 4),
+	}
+    }
+
+    pub(crate) const fn table(self) -> &'static [(Id, Spec)] {
+	match self {
+	    Self::Generic	=> termios::GENERIC,
+	    Self::PowerPc	=> termios::POWERPC,
+	    Self::Mips		=> termios::MIPS,
+	    Self::Sparc		=> termios::SPARC,
+	}
+    }
+
+    const fn spec(self, id: Id) -> Option<Spec> {
+	let table = self.table();
+	let mut i = 0;
+
+	while i < table.len() {
+	    if table[i].0.0 == id.0 {
+		return Some(table[i].1);
+	    }
+
+	    i += 1;
+	}
+
+	None
+    }
+
+    /// Returns the number of the ioctl `id`; `None` when it does not
+    /// exist on this architecture
+    pub const fn resolve(self, id: Id) -> Option<u32> {
+	match self.spec(id) {
+	    Some(spec)	=> Some(spec.encode(&self.layout())),
+	    None	=> None,
+	}
+    }
+
+    /// Returns the argument size which is encoded in the number of the
+    /// ioctl `id`; `None` for legacy numbers without size bits
+    pub const fn arg_size(self, id: Id) -> Option<usize> {
+	match self.spec(id) {
+	    Some(spec)	=> spec.size(),
+	    None	=> None,
+	}
+    }
+
+    pub fn lookup(self, cmd: u32) -> Option<Id> {
+	let layout = self.layout();
+
+	self.table().iter()
+	    .find(|(_, spec)| spec.encode(&layout) == cmd)
+	    .map(|(id, _)| *id)
+    }
+
+    const fn termbits(self) -> &'static Termbits {
+	match self {
+	    Self::Generic	=> &termbits::GENERIC,
+	    Self::PowerPc	=> &termbits::POWERPC,
+	    Self::Mips		=> &termbits::MIPS,
+	    Self::Sparc		=> &termbits::SPARC,
+	}
+    }
+
+    fn flags_map(self, flags: Flags) -> &'static [(u32, u32)] {
+	let tb = self.termbits();
+
+	match flags {
+	    Flags::Iflag	=> tb.iflag,
+	    Flags::Oflag	=> tb.oflag,
+	    Flags::Lflag	=> tb.lflag,
+	    Flags::Modem	=> tb.modem,
+	}
+    }
+
+    pub fn flags_to_wire(self, flags: Flags, v: u32) -> u32 {
+	xlat_bits(self.flags_map(flags), v, true)
+    }
+
+    pub fn flags_from_wire(self, flags: Flags, v: u32) -> u32 {
+	xlat_bits(self.flags_map(flags), v, false)
+    }
+
+    /// Returns the baudrate of a `Bxxx` constant
+    pub fn cbaud_to_rate(self, cbaud: u32) -> Option<u32> {
+	self.termbits().bauds.iter()
+	    .find(|(b, _)| *b == cbaud)
+	    .map(|(_, rate)| *rate)
+    }
+
+    /// Returns the `Bxxx` constant for a baudrate
+    pub fn rate_to_cbaud(self, rate: u32) -> Option<u32> {
+	self.termbits().bauds.iter()
+	    .find(|(_, r)| *r == rate)
+	    .map(|(b, _)| *b)
+    }
+
+    /// Translates a `Bxxx` constant and the corresponding speed field of
+    /// `termios2`.  Rates without a constant on `to` are converted into
+    /// `BOTHER` and the rate.
+    fn xlat_cbaud(from: Self, to: Self, cbaud: u32, speed: u32) -> (u32, u32) {
+	if cbaud == from.termbits().bother {
+	    return (to.termbits().bother, speed);
+	}
+
+	match from.cbaud_to_rate(cbaud) {
+	    None	=> (B0, speed),
+	    Some(rate)	=> match to.rate_to_cbaud(rate) {
+		Some(cbaud)	=> (cbaud, speed),
+		None		=> (to.termbits().bother, rate),
+	    },
+	}
+    }
+
+    fn xlat_cflag(from: Self, to: Self, cflag: u32, ispeed: u32, ospeed: u32) -> (u32, u32, u32) {
+	if from == to {
+	    return (cflag, ispeed, ospeed);
+	}
+
+	let native = match from == Self::Generic {
+	    true	=> to,
+	    false	=> from,
+	};
+
+	let cbaud = from.termbits().cbaud;
+	let flags = cflag & !(cbaud | (cbaud << IBSHIFT));
+
+	let (ocode, ospeed) = Self::xlat_cbaud(from, to, cflag & cbaud, ospeed);
+	let (icode, ispeed) = Self::xlat_cbaud(from, to, (cflag >> IBSHIFT) & cbaud, ispeed);
+
+	let res = xlat_bits(native.termbits().cflag, flags, to == Self::Generic);
+
+	(res | ocode | (icode << IBSHIFT), ispeed, ospeed)
+    }
+
+    /// Translates the `c_cflag` of a termios and its input and output
+    /// speed into the wire format
+    pub fn cflag_to_wire(self, cflag: u32, ispeed: u32, ospeed: u32) -> (u32, u32, u32) {
+	Self::xlat_cflag(self, Self::Generic, cflag, ispeed, ospeed)
+    }
+
+    pub fn cflag_from_wire(self, cflag: u32, ispeed: u32, ospeed: u32) -> (u32, u32, u32) {
+	Self::xlat_cflag(Self::Generic, self, cflag, ispeed, ospeed)
+    }
+
+    /// Fills the control characters in wire format from the native
+    /// `cc`
+    pub fn cc_to_wire(self, cc: &[u8], wire: &mut [u8]) {
+	let tb = self.termbits();
+
+	let Some(map) = tb.cc else {
+	    let len = cc.len().min(wire.len());
+
+	    wire[..len].copy_from_slice(&cc[..len]);
+	    return;
+	};
+
+	for (w, n) in map.iter().chain(tb.cc_noncanon) {
+	    if let (Some(dst), Some(src)) = (wire.get_mut(*w), cc.get(*n)) {
+		*dst = *src;
+	    }
+	}
+    }
+
+    /// Fills the native control characters from the wire format; `icanon`
+    /// selects the characters which share a slot
+    pub fn cc_from_wire(self, wire: &[u8], icanon: bool, cc: &mut [u8]) {
+	let tb = self.termbits();
+
+	let Some(map) = tb.cc else {
+	    let len = cc.len().min(wire.len());
+
+	    cc[..len].copy_from_slice(&wire[..len]);
+	    return;
+	};
+
+	let noncanon = match icanon {
+	    true	=> &[][..],
+	    false	=> tb.cc_noncanon,
+	};
+
+	for (w, n) in map.iter().chain(noncanon) {
+	    if let (Some(src), Some(dst)) = (wire.get(*w), cc.get_mut(*n)) {
+		*dst = *src;
+	    }
+	}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::termbits as wire;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_tables() {
+	for abi in Abi::ALL {
+	    let layout = abi.layout();
+	    let mut ids = HashSet::new();
+	    let mut nums = HashSet::new();
+
+	    for (id, spec) in abi.table() {
+		let num = spec.encode(&layout);
+
+		assert!(id.try_to_string().is_some(), "{abi:?}: unknown id {id:?}");
+		assert!(ids.insert(*id), "{abi:?}: duplicate {id:?}");
+		assert!(nums.insert(num), "{abi:?}: duplicate number {num:x} of {id:?}");
+
+		assert_eq!(abi.resolve(*id), Some(num));
+		assert_eq!(abi.lookup(num), Some(*id));
+		assert_eq!(abi.arg_size(*id), spec.size());
+
+		let (read, write, tp, nr, sz) = match *spec {
+		    Spec::Bad(_)		=> continue,
+		    Spec::Io(tp, nr)		=> (false, false, tp, nr, 0),
+		    Spec::Ior(tp, nr, sz)	=> (true,  false, tp, nr, sz),
+		    Spec::Iow(tp, nr, sz)	=> (false, true,  tp, nr, sz),
+		    Spec::Iowr(tp, nr, sz)	=> (true,  true,  tp, nr, sz),
+		};
+
+		assert_eq!(layout.is_read(num), read, "{abi:?}: {id:?}");
+		assert_eq!(layout.is_write(num), write, "{abi:?}: {id:?}");
+		assert_eq!(layout.get_type(num), tp, "{abi:?}: {id:?}");
+		assert_eq!(layout.get_nr(num), nr, "{abi:?}: {id:?}");
+		assert_eq!(layout.get_size(num), sz, "{abi:?}: {id:?}");
+	    }
+
+	    // ioctls which are used by the programs must exist everywhere
+	    for id in [Id::TCGETS, Id::TCSETS, Id::TCSETSW, Id::TCSETSF, Id::TCFLSH,
+		       Id::TIOCMGET, Id::TIOCMSET, Id::TIOCMBIS, Id::TIOCMBIC,
+		       Id::TIOCGWINSZ, Id::TIOCSWINSZ, Id::TIOCINQ, Id::TIOCOUTQ,
+		       Id::TCSBRK, Id::TIOCSBRK, Id::TIOCCBRK, Id::TIOCEXCL, Id::TIOCNXCL] {
+		assert!(ids.contains(&id), "{abi:?}: missing {id:?}");
+	    }
+	}
+    }
+
+    #[test]
+    fn test_generic_complete() {
+	let mut id = 1;
+
+	while let Some(name) = Id(id).try_to_string() {
+	    assert!(Abi::Generic.resolve(Id(id)).is_some(), "missing {name}");
+	    id += 1;
+	}
+
+	assert!(id > 1);
+    }
+
+    #[test]
+    fn test_known() {
+	let num = |abi: Abi, id| abi.resolve(id).unwrap();
+
+	assert_eq!(num(Abi::Generic, Id::TCGETS),	0x5401);
+	assert_eq!(num(Abi::Generic, Id::TCGETS2),	0x802c542a);
+	assert_eq!(num(Abi::Generic, Id::TCSETS2),	0x402c542b);
+	assert_eq!(num(Abi::Generic, Id::TIOCGEXCL),	0x80045440);
+
+	assert_eq!(num(Abi::PowerPc, Id::TCGETS),	0x402c7413);
+	assert_eq!(num(Abi::PowerPc, Id::TCSETS),	0x802c7414);
+	assert_eq!(num(Abi::PowerPc, Id::TIOCGWINSZ),	0x40087468);
+	assert_eq!(num(Abi::PowerPc, Id::FIONREAD),	0x4004667f);
+	assert_eq!(Abi::PowerPc.resolve(Id::TCGETS2),	None);
+	assert_eq!(Abi::PowerPc.arg_size(Id::TCGETS),	Some(44));
+	assert_eq!(Abi::Generic.arg_size(Id::TCGETS),	None);
+
+	assert_eq!(num(Abi::Mips, Id::TCGETS),		0x540d);
+	assert_eq!(num(Abi::Mips, Id::TIOCGWINSZ),	0x40087468);
+	assert_eq!(num(Abi::Mips, Id::TIOCSWINSZ),	0x80087467);
+
+	assert_eq!(num(Abi::Sparc, Id::TCGETS),		0x40245408);
+	assert_eq!(num(Abi::Sparc, Id::TIOCMGET),	0x4004746a);
+    }
+
+    // the type of the constants depends on the libc
+    #[allow(clippy::unnecessary_cast)]
+    #[test]
+    fn test_native() {
+	use nix::libc;
+
+	let num = |id| Abi::NATIVE.resolve(id).unwrap() as u64;
+
+	assert_eq!(num(Id::TCGETS),	libc::TCGETS as u64);
+	assert_eq!(num(Id::TCSETS),	libc::TCSETS as u64);
+	assert_eq!(num(Id::TCSETSW),	libc::TCSETSW as u64);
+	assert_eq!(num(Id::TCFLSH),	libc::TCFLSH as u64);
+	assert_eq!(num(Id::TIOCGWINSZ),	libc::TIOCGWINSZ as u64);
+	assert_eq!(num(Id::TIOCSWINSZ),	libc::TIOCSWINSZ as u64);
+	assert_eq!(num(Id::TIOCMGET),	libc::TIOCMGET as u64);
+	assert_eq!(num(Id::TIOCINQ),	libc::FIONREAD as u64);
+	assert_eq!(num(Id::TIOCOUTQ),	libc::TIOCOUTQ as u64);
+	assert_eq!(num(Id::TIOCEXCL),	libc::TIOCEXCL as u64);
+	assert_eq!(num(Id::TIOCSBRK),	libc::TIOCSBRK as u64);
+	assert_eq!(num(Id::FIONBIO),	libc::FIONBIO as u64);
+	assert_eq!(num(Id::TIOCSETD),	libc::TIOCSETD as u64);
+	assert_eq!(num(Id::TIOCGSERIAL), libc::TIOCGSERIAL as u64);
+	assert_eq!(num(Id::TIOCGRS485),	libc::TIOCGRS485 as u64);
+	assert_eq!(num(Id::TIOCSRS485),	libc::TIOCSRS485 as u64);
+	assert_eq!(num(Id::TIOCMIWAIT),	libc::TIOCMIWAIT as u64);
+	assert_eq!(num(Id::TCGETS2),	libc::TCGETS2 as u64);
+	assert_eq!(num(Id::TCSETSF2),	libc::TCSETSF2 as u64);
+    }
+
+    #[test]
+    fn test_termbits_native() {
+	use nix::libc;
+
+	let abi = Abi::NATIVE;
+
+	for (native, w) in [(libc::IXON, wire::IXON), (libc::IXOFF, wire::IXOFF),
+			    (libc::IUCLC, wire::IUCLC), (libc::ICRNL, wire::ICRNL)] {
+	    assert_eq!(abi.flags_to_wire(Flags::Iflag, native), w);
+	    assert_eq!(abi.flags_from_wire(Flags::Iflag, w), native);
+	}
+
+	for (native, w) in [(libc::ONLCR, wire::ONLCR), (libc::OLCUC, wire::OLCUC),
+			    (libc::CR2, wire::CR2), (libc::TAB3, wire::TAB3),
+			    (libc::VT1, wire::VT1), (libc::FF1, wire::FF1)] {
+	    assert_eq!(abi.flags_to_wire(Flags::Oflag, native), w);
+	    assert_eq!(abi.flags_from_wire(Flags::Oflag, w), native);
+	}
+
+	for (native, w) in [(libc::ICANON, wire::ICANON), (libc::ECHO, wire::ECHO),
+			    (libc::ISIG, wire::ISIG), (libc::IEXTEN, wire::IEXTEN),
+			    (libc::TOSTOP, wire::TOSTOP), (libc::NOFLSH, wire::NOFLSH)] {
+	    assert_eq!(abi.flags_to_wire(Flags::Lflag, native), w);
+	    assert_eq!(abi.flags_from_wire(Flags::Lflag, w), native);
+	}
+
+	for (native, w) in [(libc::TIOCM_DTR, wire::TIOCM_DTR), (libc::TIOCM_CTS, wire::TIOCM_CTS),
+			    (libc::TIOCM_CAR, wire::TIOCM_CAR), (libc::TIOCM_DSR, wire::TIOCM_DSR)] {
+	    assert_eq!(abi.flags_to_wire(Flags::Modem, native as u32), w as u32);
+	    assert_eq!(abi.flags_from_wire(Flags::Modem, w as u32), native as u32);
+	}
+
+	let cflag = libc::CS7 | libc::PARENB | libc::CREAD | libc::CRTSCTS;
+
+	assert_eq!(abi.cflag_to_wire(cflag | libc::B115200, 0, 0),
+		   (wire::CS7 | wire::PARENB | wire::CREAD | wire::CRTSCTS | wire::B115200, 0, 0));
+	assert_eq!(abi.cflag_from_wire(wire::CLOCAL | wire::BOTHER, 0, 1234),
+		   (libc::CLOCAL | libc::BOTHER, 0, 1234));
+
+	let mut cc = [0; 32];
+	let mut native = [0; 32];
+
+	native[libc::VMIN] = 1;
+	native[libc::VTIME] = 2;
+	native[libc::VSTOP] = 19;
+	native[libc::VEOL2] = 3;
+
+	abi.cc_to_wire(&native, &mut cc);
+
+	assert_eq!(cc[wire::VMIN], 1);
+	assert_eq!(cc[wire::VTIME], 2);
+	assert_eq!(cc[wire::VSTOP], 19);
+	assert_eq!(cc[wire::VEOL2], 3);
+    }
+
+    #[test]
+    fn test_termbits_xlat() {
+	assert_eq!(Abi::PowerPc.flags_from_wire(Flags::Lflag, wire::ICANON | wire::ECHO | wire::ISIG),
+		   0x100 | 0x08 | 0x80);
+	assert_eq!(Abi::PowerPc.flags_to_wire(Flags::Iflag, 0x200 | 0x400),
+		   wire::IXON | wire::IXOFF);
+	assert_eq!(Abi::PowerPc.cflag_from_wire(wire::B115200 | wire::CS8 | wire::CREAD, 0, 0),
+		   (0x11 | 0x300 | 0x800, 0, 0));
+	assert_eq!(Abi::PowerPc.cflag_to_wire(0x1f | (0x0d << IBSHIFT), 1234, 0),
+		   (wire::BOTHER | (wire::B9600 << IBSHIFT), 1234, 0));
+
+	// there is no B2500000 on sparc
+	assert_eq!(Abi::Sparc.cflag_from_wire(wire::B2500000 | wire::HUPCL, 0, 0),
+		   (wire::BOTHER | wire::HUPCL, 0, 2500000));
+	assert_eq!(Abi::Sparc.cflag_to_wire(0x1005, 0, 0),
+		   (wire::BOTHER, 0, 76800));
+
+	assert_eq!(Abi::Mips.flags_from_wire(Flags::Modem, (wire::TIOCM_CTS | wire::TIOCM_DTR) as u32),
+		   0x040 | 0x002);
+	assert_eq!(Abi::Mips.flags_to_wire(Flags::Lflag, 0x100 | 0x8000),
+		   wire::IEXTEN | wire::TOSTOP);
+
+	let mut cc = [0; 32];
+
+	cc[wire::VEOF] = 4;
+	cc[wire::VMIN] = 1;
+	cc[wire::VTIME] = 10;
+
+	// VMIN and VTIME share their slots with VEOF and VEOL on sparc
+	let mut native = [0; 17];
+
+	Abi::Sparc.cc_from_wire(&cc, false, &mut native);
+	assert_eq!(native[4], 1);
+	assert_eq!(native[5], 10);
+
+	Abi::Sparc.cc_from_wire(&cc, true, &mut native);
+	assert_eq!(native[4], 4);
+
+	let mut native = [0; 19];
+
+	Abi::PowerPc.cc_from_wire(&cc, false, &mut native);
+	assert_eq!(native[4], 4);
+	assert_eq!(native[5], 1);
+	assert_eq!(native[7], 10);
+
+	// all bits survive the round trip
+	for abi in Abi::ALL {
+	    for flags in [Flags::Iflag, Flags::Oflag, Flags::Lflag, Flags::Modem] {
+		for bit in 0..32 {
+		    let v = abi.flags_to_wire(flags, 1 << bit);
+
+		    if v != 0 {
+			assert_eq!(abi.flags_from_wire(flags, v), 1 << bit, "{abi:?}: {flags:?} {bit}");
+		    }
+		}
+	    }
+
+	    for rate in [0, 50, 9600, 38400, 57600, 115200, 921600, 2000000, 4000000] {
+		let cbaud = Abi::Generic.rate_to_cbaud(rate).unwrap();
+		let (native, _, speed) = abi.cflag_from_wire(cbaud | wire::CSTOPB, 0, 0);
+
+		// rates without constant are transported as BOTHER
+		let cbaud = match abi.rate_to_cbaud(rate) {
+		    Some(_)	=> cbaud,
+		    None	=> wire::BOTHER,
+		};
+
+		assert_eq!(abi.cflag_to_wire(native, 0, speed), (cbaud | wire::CSTOPB, 0, speed),
+			   "{abi:?}: {rate}");
+	    }
+	}
+    }
+}
@@ -0,0 +1,284 @@
+//! Values of the termios flags, control characters and modem lines on
+//! the different architecture families; see
+//! `arch/*/include/uapi/asm/termbits.h` of the kernel.  The maps contain
+//! `(wire, native)` pairs of the bits and indices which differ from the
+//! wire format.
+
+use crate::termbits::*;
+
+pub(crate) struct Termbits {
+    pub iflag:		&'static [(u32, u32)],
+    pub oflag:		&'static [(u32, u32)],
+    /// without the `CBAUD` and `CIBAUD` bits
+    pub cflag:		&'static [(u32, u32)],
+    pub lflag:		&'static [(u32, u32)],
+    pub modem:		&'static [(u32, u32)],
+
+    pub cbaud:		u32,
+    pub bother:		u32,
+    /// `Bxxx` constants and their baudrates
+    pub bauds:		&'static [(u32, u32)],
+
+    /// indices of the control characters; `None` when they are the same
+    /// as on the wire
+    pub cc:		Option<&'static [(usize, usize)]>,
+    /// control characters which share their slot with others and are
+    /// used only in non-canonical mode
+    pub cc_noncanon:	&'static [(usize, usize)],
+}
+
+const GENERIC_BAUDS: &[(u32, u32)] = &[
+    (B0,	0),
+    (B50,	50),
+    (B75,	75),
+    (B110,	110),
+    (B134,	134),
+    (B150,	150),
+    (B200,	200),
+    (B300,	300),
+    (B600,	600),
+    (B1200,	1200),
+    (B1800,	1800),
+    (B2400,	2400),
+    (B4800,	4800),
+    (B9600,	9600),
+    (B19200,	19200),
+    (B38400,	38400),
+    (B57600,	57600),
+    (B115200,	115200),
+    (B230400,	230400),
+    (B460800,	460800),
+    (B500000,	500000),
+    (B576000,	576000),
+    (B921600,	921600),
+    (B1000000,	1000000),
+    (B1152000,	1152000),
+    (B1500000,	1500000),
+    (B2000000,	2000000),
+    (B2500000,	2500000),
+    (B3000000,	3000000),
+    (B3500000,	3500000),
+    (B4000000,	4000000),
+];
+
+pub(crate) const GENERIC: Termbits = Termbits {
+    iflag:		&[],
+    oflag:		&[],
+    cflag:		&[],
+    lflag:		&[],
+    modem:		&[],
+    cbaud:		CBAUD,
+    bother:		BOTHER,
+    bauds:		GENERIC_BAUDS,
+    cc:			None,
+    cc_noncanon:	&[],
+};
+
+pub(crate) const POWERPC: Termbits = Termbits {
+    iflag:		&[
+	(IUCLC,		0o010000),
+	(IXON,		0o001000),
+	(IXOFF,		0o002000),
+    ],
+    oflag:		&[
+	(OLCUC,		0o000004),
+	(ONLCR,		0o000002),
+	(CR1,		0o010000),
+	(CR2,		0o020000),
+	(TAB1,		0o002000),
+	(TAB2,		0o004000),
+	(BS1,		0o100000),
+	(VT1,		0o200000),
+	(FF1,		0o040000),
+    ],
+    cflag:		&[
+	(CS6,		0o000400),
+	(CS7,		0o001000),
+	(CSTOPB,	0o002000),
+	(CREAD,		0o004000),
+	(PARENB,	0o010000),
+	(PARODD,	0o020000),
+	(HUPCL,		0o040000),
+	(CLOCAL,	0o100000),
+    ],
+    lflag:		&[
+	(ISIG,		0x00000080),
+	(ICANON,	0x00000100),
+	(XCASE,		0x00004000),
+	(ECHOE,		0x00000002),
+	(ECHOK,		0x00000004),
+	(ECHONL,	0x00000010),
+	(NOFLSH,	0x80000000),
+	(TOSTOP,	0x00400000),
+	(ECHOCTL,	0x00000040),
+	(ECHOPRT,	0x00000020),
+	(ECHOKE,	0x00000001),
+	(FLUSHO,	0x00800000),
+	(PENDIN,	0x20000000),
+	(IEXTEN,	0x00000400),
+	(EXTPROC,	0x10000000),
+    ],
+    modem:		&[],
+    cbaud:		0o000377,
+    bother:		0o000037,
+    bauds:		&[
+	(0o000000,	0),
+	(0o000001,	50),
+	(0o000002,	75),
+	(0o000003,	110),
+	(0o000004,	134),
+	(0o000005,	150),
+	(0o000006,	200),
+	(0o000007,	300),
+	(0o000010,	600),
+	(0o000011,	1200),
+	(0o000012,	1800),
+	(0o000013,	2400),
+	(0o000014,	4800),
+	(0o000015,	9600),
+	(0o000016,	19200),
+	(0o000017,	38400),
+	(0o000020,	57600),
+	(0o000021,	115200),
+	(0o000022,	230400),
+	(0o000023,	460800),
+	(0o000024,	500000),
+	(0o000025,	576000),
+	(0o000026,	921600),
+	(0o000027,	1000000),
+	(0o000030,	1152000),
+	(0o000031,	1500000),
+	(0o000032,	2000000),
+	(0o000033,	2500000),
+	(0o000034,	3000000),
+	(0o000035,	3500000),
+	(0o000036,	4000000),
+    ],
+    cc:			Some(&[
+	(VINTR,		0),
+	(VQUIT,		1),
+	(VERASE,	2),
+	(VKILL,		3),
+	(VEOF,		4),
+	(VMIN,		5),
+	(VEOL,		6),
+	(VTIME,		7),
+	(VEOL2,		8),
+	(VSWTC,		9),
+	(VWERASE,	10),
+	(VREPRINT,	11),
+	(VSUSP,		12),
+	(VSTART,	13),
+	(VSTOP,		14),
+	(VLNEXT,	15),
+	(VDISCARD,	16),
+    ]),
+    cc_noncanon:	&[],
+};
+
+pub(crate) const MIPS: Termbits = Termbits {
+    iflag:		&[],
+    oflag:		&[],
+    cflag:		&[],
+    lflag:		&[
+	(TOSTOP,	0o100000),
+	(FLUSHO,	0o020000),
+	(IEXTEN,	0o000400),
+    ],
+    modem:		&[
+	(TIOCM_ST as u32,	0x010),
+	(TIOCM_SR as u32,	0x020),
+	(TIOCM_CTS as u32,	0x040),
+	(TIOCM_CAR as u32,	0x100),
+	(TIOCM_RNG as u32,	0x200),
+	(TIOCM_DSR as u32,	0x400),
+    ],
+    cbaud:		CBAUD,
+    bother:		BOTHER,
+    bauds:		GENERIC_BAUDS,
+    cc:			Some(&[
+	(VINTR,		0),
+	(VQUIT,		1),
+	(VERASE,	2),
+	(VKILL,		3),
+	(VMIN,		4),
+	(VTIME,		5),
+	(VEOL2,		6),
+	(VSWTC,		7),
+	(VSTART,	8),
+	(VSTOP,		9),
+	(VSUSP,		10),
+	(VREPRINT,	12),
+	(VDISCARD,	13),
+	(VWERASE,	14),
+	(VLNEXT,	15),
+	(VEOF,		16),
+	(VEOL,		17),
+    ]),
+    cc_noncanon:	&[],
+};
+
+pub(crate) const SPARC: Termbits = Termbits {
+    iflag:		&[],
+    oflag:		&[],
+    cflag:		&[],
+    lflag:		&[],
+    modem:		&[],
+    cbaud:		CBAUD,
+    bother:		BOTHER,
+    bauds:		&[
+	(0o000000,	0),
+	(0o000001,	50),
+	(0o000002,	75),
+	(0o000003,	110),
+	(0o000004,	134),
+	(0o000005,	150),
+	(0o000006,	200),
+	(0o000007,	300),
+	(0o000010,	600),
+	(0o000011,	1200),
+	(0o000012,	1800),
+	(0o000013,	2400),
+	(0o000014,	4800),
+	(0o000015,	9600),
+	(0o000016,	19200),
+	(0o000017,	38400),
+	(0x00001001,	57600),
+	(0x00001002,	115200),
+	(0x00001003,	230400),
+	(0x00001004,	460800),
+	(0x00001005,	76800),
+	(0x00001006,	153600),
+	(0x00001007,	307200),
+	(0x00001008,	614400),
+	(0x00001009,	921600),
+	(0x0000100a,	500000),
+	(0x0000100b,	576000),
+	(0x0000100c,	1000000),
+	(0x0000100d,	1152000),
+	(0x0000100e,	1500000),
+	(0x0000100f,	2000000),
+    ],
+    cc:			Some(&[
+	(VINTR,		0),
+	(VQUIT,		1),
+	(VERASE,	2),
+	(VKILL,		3),
+	(VEOF,		4),
+	(VEOL,		5),
+	(VEOL2,		6),
+	(VSWTC,		7),
+	(VSTART,	8),
+	(VSTOP,		9),
+	(VSUSP,		10),
+	(VREPRINT,	12),
+	(VDISCARD,	13),
+	(VWERASE,	14),
+	(VLNEXT,	15),
+    ]),
+    // VMIN and VTIME share their slots with VEOF and VEOL
+    cc_noncanon:	&[
+	(VMIN,		4),
+	(VTIME,		5),
+    ],
+};
@@ -0,0 +1,256 @@
+//! Numbers of the tty ioctls on the different architecture families;
+//! see `arch/*/include/uapi/asm/ioctls.h` of the kernel.  Sizes are
+//! given explicitly because the structures differ between the families.
+
+use super::{Id, Spec};
+use Spec::*;
+
+const INT: usize = 4;
+const CHAR: usize = 1;
+const WINSIZE: usize = 8;
+const SERIAL_RS485: usize = 32;
+
+/// 'struct termios2' with NCCS = 19
+const TERMIOS2: usize = 44;
+
+pub(crate) const GENERIC: &[(Id, Spec)] = &[
+    (Id::TCGETS,		Bad(0x5401)),
+    (Id::TCSETS,		Bad(0x5402)),
+    (Id::TCSETSW,		Bad(0x5403)),
+    (Id::TCSETSF,		Bad(0x5404)),
+    (Id::TCGETA,		Bad(0x5405)),
+    (Id::TCSETA,		Bad(0x5406)),
+    (Id::TCSETAW,		Bad(0x5407)),
+    (Id::TCSETAF,		Bad(0x5408)),
+    (Id::TCSBRK,		Bad(0x5409)),
+    (Id::TCXONC,		Bad(0x540A)),
+    (Id::TCFLSH,		Bad(0x540B)),
+    (Id::TIOCEXCL,		Bad(0x540C)),
+    (Id::TIOCNXCL,		Bad(0x540D)),
+    (Id::TIOCSCTTY,		Bad(0x540E)),
+    (Id::TIOCGPGRP,		Bad(0x540F)),
+    (Id::TIOCSPGRP,		Bad(0x5410)),
+    (Id::TIOCOUTQ,		Bad(0x5411)),
+    (Id::TIOCSTI,		Bad(0x5412)),
+    (Id::TIOCGWINSZ,		Bad(0x5413)),
+    (Id::TIOCSWINSZ,		Bad(0x5414)),
+    (Id::TIOCMGET,		Bad(0x5415)),
+    (Id::TIOCMBIS,		Bad(0x5416)),
+    (Id::TIOCMBIC,		Bad(0x5417)),
+    (Id::TIOCMSET,		Bad(0x5418)),
+    (Id::TIOCGSOFTCAR,		Bad(0x5419)),
+    (Id::TIOCSSOFTCAR,		Bad(0x541A)),
+    (Id::TIOCINQ,		Bad(0x541B)),
+    (Id::TIOCLINUX,		Bad(0x541C)),
+    (Id::TIOCCONS,		Bad(0x541D)),
+    (Id::TIOCGSERIAL,		Bad(0x541E)),
+    (Id::TIOCSSERIAL,		Bad(0x541F)),
+    (Id::TIOCPKT,		Bad(0x5420)),
+    (Id::FIONBIO,		Bad(0x5421)),
+    (Id::TIOCNOTTY,		Bad(0x5422)),
+    (Id::TIOCSETD,		Bad(0x5423)),
+    (Id::TIOCGETD,		Bad(0x5424)),
+    (Id::TCSBRKP,		Bad(0x5425)),
+    (Id::TIOCSBRK,		Bad(0x5427)),
+    (Id::TIOCCBRK,		Bad(0x5428)),
+    (Id::TIOCGSID,		Bad(0x5429)),
+    (Id::TCGETS2,		Ior(b'T', 0x2A, TERMIOS2)),
+    (Id::TCSETS2,		Iow(b'T', 0x2B, TERMIOS2)),
+    (Id::TCSETSW2,		Iow(b'T', 0x2C, TERMIOS2)),
+    (Id::TCSETSF2,		Iow(b'T', 0x2D, TERMIOS2)),
+    (Id::TIOCGRS485,		Bad(0x542E)),
+    (Id::TIOCSRS485,		Bad(0x542F)),
+    (Id::TIOCGEXCL,		Ior(b'T', 0x40, INT)),
+    (Id::TIOCGLCKTRMIOS,	Bad(0x5456)),
+    (Id::TIOCSLCKTRMIOS,	Bad(0x5457)),
+    (Id::TIOCSERGSTRUCT,	Bad(0x5458)),
+    (Id::TIOCSERGETLSR,		Bad(0x5459)),
+    (Id::TIOCSERGETMULTI,	Bad(0x545A)),
+    (Id::TIOCSERSETMULTI,	Bad(0x545B)),
+    (Id::TIOCMIWAIT,		Bad(0x545C)),
+    (Id::TIOCGICOUNT,		Bad(0x545D)),
+];
+
+/// 'struct termios' contains the speeds; there is no 'termios2'
+const PPC_TERMIOS: usize = 44;
+/// 'struct termio' with NCC = 10
+const PPC_TERMIO: usize = 20;
+
+pub(crate) const POWERPC: &[(Id, Spec)] = &[
+    (Id::TCGETS,		Ior(b't', 19, PPC_TERMIOS)),
+    (Id::TCSETS,		Iow(b't', 20, PPC_TERMIOS)),
+    (Id::TCSETSW,		Iow(b't', 21, PPC_TERMIOS)),
+    (Id::TCSETSF,		Iow(b't', 22, PPC_TERMIOS)),
+    (Id::TCGETA,		Ior(b't', 23, PPC_TERMIO)),
+    (Id::TCSETA,		Iow(b't', 24, PPC_TERMIO)),
+    (Id::TCSETAW,		Iow(b't', 25, PPC_TERMIO)),
+    (Id::TCSETAF,		Iow(b't', 28, PPC_TERMIO)),
+    (Id::TCSBRK,		Io(b't', 29)),
+    (Id::TCXONC,		Io(b't', 30)),
+    (Id::TCFLSH,		Io(b't', 31)),
+    (Id::TIOCEXCL,		Bad(0x540C)),
+    (Id::TIOCNXCL,		Bad(0x540D)),
+    (Id::TIOCSCTTY,		Bad(0x540E)),
+    (Id::TIOCGPGRP,		Ior(b't', 119, INT)),
+    (Id::TIOCSPGRP,		Iow(b't', 118, INT)),
+    (Id::TIOCOUTQ,		Ior(b't', 115, INT)),
+    (Id::TIOCSTI,		Bad(0x5412)),
+    (Id::TIOCGWINSZ,		Ior(b't', 104, WINSIZE)),
+    (Id::TIOCSWINSZ,		Iow(b't', 103, WINSIZE)),
+    (Id::TIOCMGET,		Bad(0x5415)),
+    (Id::TIOCMBIS,		Bad(0x5416)),
+    (Id::TIOCMBIC,		Bad(0x5417)),
+    (Id::TIOCMSET,		Bad(0x5418)),
+    (Id::TIOCGSOFTCAR,		Bad(0x5419)),
+    (Id::TIOCSSOFTCAR,		Bad(0x541A)),
+    (Id::TIOCINQ,		Ior(b'f', 127, INT)),
+    (Id::TIOCLINUX,		Bad(0x541C)),
+    (Id::TIOCCONS,		Bad(0x541D)),
+    (Id::TIOCGSERIAL,		Bad(0x541E)),
+    (Id::TIOCSSERIAL,		Bad(0x541F)),
+    (Id::TIOCPKT,		Bad(0x5420)),
+    (Id::FIONBIO,		Iow(b'f', 126, INT)),
+    (Id::TIOCNOTTY,		Bad(0x5422)),
+    (Id::TIOCSETD,		Bad(0x5423)),
+    (Id::TIOCGETD,		Bad(0x5424)),
+    (Id::TCSBRKP,		Bad(0x5425)),
+    (Id::TIOCSBRK,		Bad(0x5427)),
+    (Id::TIOCCBRK,		Bad(0x5428)),
+    (Id::TIOCGSID,		Bad(0x5429)),
+    (Id::TIOCGRS485,		Bad(0x542E)),
+    (Id::TIOCSRS485,		Bad(0x542F)),
+    (Id::TIOCGEXCL,		Ior(b'T', 0x40, INT)),
+    (Id::TIOCGLCKTRMIOS,	Bad(0x5456)),
+    (Id::TIOCSLCKTRMIOS,	Bad(0x5457)),
+    (Id::TIOCSERGSTRUCT,	Bad(0x5458)),
+    (Id::TIOCSERGETLSR,		Bad(0x5459)),
+    (Id::TIOCSERGETMULTI,	Bad(0x545A)),
+    (Id::TIOCSERSETMULTI,	Bad(0x545B)),
+    (Id::TIOCMIWAIT,		Bad(0x545C)),
+    (Id::TIOCGICOUNT,		Bad(0x545D)),
+];
+
+/// 'struct termios2' with NCCS = 23
+const MIPS_TERMIOS2: usize = 48;
+
+pub(crate) const MIPS: &[(Id, Spec)] = &[
+    (Id::TCGETA,		Bad(0x5401)),
+    (Id::TCSETA,		Bad(0x5402)),
+    (Id::TCSETAW,		Bad(0x5403)),
+    (Id::TCSETAF,		Bad(0x5404)),
+    (Id::TCSBRK,		Bad(0x5405)),
+    (Id::TCXONC,		Bad(0x5406)),
+    (Id::TCFLSH,		Bad(0x5407)),
+    (Id::TCGETS,		Bad(0x540D)),
+    (Id::TCSETS,		Bad(0x540E)),
+    (Id::TCSETSW,		Bad(0x540F)),
+    (Id::TCSETSF,		Bad(0x5410)),
+    (Id::TIOCEXCL,		Bad(0x740D)),
+    (Id::TIOCNXCL,		Bad(0x740E)),
+    (Id::TIOCOUTQ,		Bad(0x7472)),
+    (Id::TIOCSTI,		Bad(0x5472)),
+    (Id::TIOCMGET,		Bad(0x741D)),
+    (Id::TIOCMBIS,		Bad(0x741B)),
+    (Id::TIOCMBIC,		Bad(0x741C)),
+    (Id::TIOCMSET,		Bad(0x741A)),
+    (Id::TIOCPKT,		Bad(0x5470)),
+    (Id::TIOCSWINSZ,		Iow(b't', 103, WINSIZE)),
+    (Id::TIOCGWINSZ,		Ior(b't', 104, WINSIZE)),
+    (Id::TIOCNOTTY,		Bad(0x5471)),
+    (Id::TIOCSETD,		Bad(0x7401)),
+    (Id::TIOCGETD,		Bad(0x7400)),
+    (Id::FIONBIO,		Bad(0x667E)),
+    (Id::TIOCSPGRP,		Iow(b't', 118, INT)),
+    (Id::TIOCGPGRP,		Ior(b't', 119, INT)),
+    (Id::TIOCCONS,		Iow(b't', 120, INT)),
+    (Id::TIOCINQ,		Bad(0x467F)),
+    (Id::TIOCSCTTY,		Bad(0x5480)),
+    (Id::TIOCGSOFTCAR,		Bad(0x5481)),
+    (Id::TIOCSSOFTCAR,		Bad(0x5482)),
+    (Id::TIOCLINUX,		Bad(0x5483)),
+    (Id::TIOCGSERIAL,		Bad(0x5484)),
+    (Id::TIOCSSERIAL,		Bad(0x5485)),
+    (Id::TCSBRKP,		Bad(0x5486)),
+    (Id::TIOCGLCKTRMIOS,	Bad(0x548B)),
+    (Id::TIOCSLCKTRMIOS,	Bad(0x548C)),
+    (Id::TIOCSERGSTRUCT,	Bad(0x548D)),
+    (Id::TIOCSERGETLSR,		Bad(0x548E)),
+    (Id::TIOCSERGETMULTI,	Bad(0x548F)),
+    (Id::TIOCSERSETMULTI,	Bad(0x5490)),
+    (Id::TIOCMIWAIT,		Bad(0x5491)),
+    (Id::TIOCGICOUNT,		Bad(0x5492)),
+    (Id::TIOCGSID,		Bad(0x7416)),
+    (Id::TIOCSBRK,		Bad(0x5427)),
+    (Id::TIOCCBRK,		Bad(0x5428)),
+    (Id::TCGETS2,		Ior(b'T', 0x2A, MIPS_TERMIOS2)),
+    (Id::TCSETS2,		Iow(b'T', 0x2B, MIPS_TERMIOS2)),
+    (Id::TCSETSW2,		Iow(b'T', 0x2C, MIPS_TERMIOS2)),
+    (Id::TCSETSF2,		Iow(b'T', 0x2D, MIPS_TERMIOS2)),
+    (Id::TIOCGRS485,		Ior(b'T', 0x2E, SERIAL_RS485)),
+    (Id::TIOCSRS485,		Iowr(b'T', 0x2F, SERIAL_RS485)),
+    (Id::TIOCGEXCL,		Ior(b'T', 0x40, INT)),
+];
+
+/// 'struct termios' with NCCS = 17
+const SPARC_TERMIOS: usize = 36;
+const SPARC_TERMIOS2: usize = 44;
+/// 'struct termio' with NCC = 8
+const SPARC_TERMIO: usize = 18;
+
+pub(crate) const SPARC: &[(Id, Spec)] = &[
+    (Id::TCGETA,		Ior(b'T', 1, SPARC_TERMIO)),
+    (Id::TCSETA,		Iow(b'T', 2, SPARC_TERMIO)),
+    (Id::TCSETAW,		Iow(b'T', 3, SPARC_TERMIO)),
+    (Id::TCSETAF,		Iow(b'T', 4, SPARC_TERMIO)),
+    (Id::TCSBRK,		Io(b'T', 5)),
+    (Id::TCXONC,		Io(b'T', 6)),
+    (Id::TCFLSH,		Io(b'T', 7)),
+    (Id::TCGETS,		Ior(b'T', 8, SPARC_TERMIOS)),
+    (Id::TCSETS,		Iow(b'T', 9, SPARC_TERMIOS)),
+    (Id::TCSETSW,		Iow(b'T', 10, SPARC_TERMIOS)),
+    (Id::TCSETSF,		Iow(b'T', 11, SPARC_TERMIOS)),
+    (Id::TCGETS2,		Ior(b'T', 12, SPARC_TERMIOS2)),
+    (Id::TCSETS2,		Iow(b'T', 13, SPARC_TERMIOS2)),
+    (Id::TCSETSW2,		Iow(b'T', 14, SPARC_TERMIOS2)),
+    (Id::TCSETSF2,		Iow(b'T', 15, SPARC_TERMIOS2)),
+    (Id::TIOCGEXCL,		Ior(b'T', 0x40, INT)),
+    (Id::TIOCGRS485,		Ior(b'T', 0x41, SERIAL_RS485)),
+    (Id::TIOCSRS485,		Iowr(b'T', 0x42, SERIAL_RS485)),
+    (Id::TIOCGETD,		Ior(b't', 0, INT)),
+    (Id::TIOCSETD,		Iow(b't', 1, INT)),
+    (Id::TIOCEXCL,		Io(b't', 13)),
+    (Id::TIOCNXCL,		Io(b't', 14)),
+    (Id::TIOCCONS,		Io(b't', 36)),
+    (Id::TIOCGSOFTCAR,		Ior(b't', 100, INT)),
+    (Id::TIOCSSOFTCAR,		Iow(b't', 101, INT)),
+    (Id::TIOCSWINSZ,		Iow(b't', 103, WINSIZE)),
+    (Id::TIOCGWINSZ,		Ior(b't', 104, WINSIZE)),
+    (Id::TIOCMGET,		Ior(b't', 106, INT)),
+    (Id::TIOCMBIC,		Iow(b't', 107, INT)),
+    (Id::TIOCMBIS,		Iow(b't', 108, INT)),
+    (Id::TIOCMSET,		Iow(b't', 109, INT)),
+    (Id::TIOCPKT,		Iow(b't', 112, INT)),
+    (Id::TIOCNOTTY,		Io(b't', 113)),
+    (Id::TIOCSTI,		Iow(b't', 114, CHAR)),
+    (Id::TIOCOUTQ,		Ior(b't', 115, INT)),
+    (Id::TIOCCBRK,		Io(b't', 122)),
+    (Id::TIOCSBRK,		Io(b't', 123)),
+    (Id::TIOCSPGRP,		Iow(b't', 130, INT)),
+    (Id::TIOCGPGRP,		Ior(b't', 131, INT)),
+    (Id::TIOCSCTTY,		Io(b't', 132)),
+    (Id::TIOCGSID,		Ior(b't', 133, INT)),
+    (Id::FIONBIO,		Iow(b'f', 126, INT)),
+    (Id::TIOCINQ,		Ior(b'f', 127, INT)),
+    (Id::TIOCLINUX,		Bad(0x541C)),
+    (Id::TIOCGSERIAL,		Bad(0x541E)),
+    (Id::TIOCSSERIAL,		Bad(0x541F)),
+    (Id::TCSBRKP,		Bad(0x5425)),
+    (Id::TIOCGLCKTRMIOS,	Bad(0x5456)),
+    (Id::TIOCSLCKTRMIOS,	Bad(0x5457)),
+    (Id::TIOCSERGSTRUCT,	Bad(0x5458)),
+    (Id::TIOCSERGETLSR,		Bad(0x5459)),
+    (Id::TIOCSERGETMULTI,	Bad(0x545A)),
+    (Id::TIOCSERSETMULTI,	Bad(0x545B)),
+    (Id::TIOCMIWAIT,		Bad(0x545C)),
+    (Id::TIOCGICOUNT,		Bad(0x545D)),
+];
@@ -2,8 +2,8 @@
 
 macro_rules! declare_ioctls {
     ($ns:expr, $map_fn:ident, { $( $vis:vis $ident:ident => $op:tt $data:tt ,)* })	=> {
-	impl $crate::ffi::ioctl {
-	    $( $vis const $ident: Self = declare_ioctls!(op => spec, $op $data); )*
+	impl $crate::abi::Id {
+	    $( $vis const $ident: Self = declare_ioctls!(op => id, $op $data); )*
 
 	    pub(crate) const fn $map_fn(self) -> Option<&'static str> {
 		#[allow(unreachable_patterns)]
@@ -13,60 +13,18 @@ macro_rules! declare_ioctls {
 		}
 	    }
 	}
-    };
-
-    (op => spec, BAD( $d: expr ))			=> { Self($d) };
-    (op => spec, ALIAS( $d: expr ))			=> { $d };
-    (op => spec, IOW( $id:expr, $nr:expr, $tp:ty ))	=> { Self::IOW::<$tp>($id, $nr) };
-    (op => spec, IOR( $id:expr, $nr:expr, $tp:ty ))	=> { Self::IOR::<$tp>($id, $nr) };
-    (op => spec, IORW( $id:expr, $nr:expr, $tp:ty ))	=> { Self::IORW::<$tp>($id, $nr) };
-}
 
-#[derive(Debug, Clone, Copy)]
-struct BitGeo {
-    bits:	u8,
-    pos:	u8,
-}
-
-impl BitGeo {
-    pub const fn new(bits: u8) -> Self {
-	// we do not handle bits == 32 correctly in the bit mask operations
-	// below
-	assert!(bits <= 31);
-
-	Self {
-	    bits:	bits,
-	    pos:	0,
-	}
-    }
-
-    pub const fn next(&self, bits: u8) -> Self {
-	// we do not handle bits == 32 correctly in the bit mask operations
-	// below
-	assert!(bits <= 31);
-	assert!(self.pos + self.bits + bits <= 32);
-
-	Self {
-	    bits:	bits,
-	    pos:	self.pos + self.bits,
+	impl $crate::ffi::ioctl {
+	    $( $vis const $ident: Self = Self::native($crate::abi::Id::$ident); )*
 	}
-    }
-
-    const fn mask(&self) -> u32 {
-	(1 << self.bits) - 1
-    }
-
-    pub const fn encode(&self, val: u32) -> u32 {
-	assert!(val < (1 << self.bits));
-
-	val << self.pos
-    }
+    };
 
-    pub const fn decode(&self, val: u32) -> u32 {
-	(val >> self.pos) & self.mask()
-    }
+    (op => id, ID( $d: expr ))				=> { Self($d) };
+    (op => id, ALIAS( $d: ident ))			=> { Self::$d };
 }
 
+use crate::abi::{Abi, Id, Layout};
+
 #[repr(transparent)]
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub struct ioctl(u32);
@@ -74,50 +32,54 @@ pub struct ioctl(u32);
 #[allow(non_snake_case)]
 #[allow(dead_code)]
 impl ioctl {
-    const NR:   BitGeo = BitGeo::new(8);
-    const TYPE: BitGeo = Self::NR.next(8);
+    const LAYOUT: Layout = Abi::NATIVE.layout();
+
+    /// Type of the numbers which are used for ioctls that do not exist
+    /// on this architecture
+    const TYPE_UNSUPPORTED: u8 = 0xff;
+
+    /// Returns the native number of the ioctl `id`; when it does not exist
+    /// on this architecture, a number which is unknown to the kernel is
+    /// used
+    pub(crate) const fn native(id: Id) -> Self {
+	match Abi::NATIVE.resolve(id) {
+	    Some(cmd)	=> Self(cmd),
+	    None	=> Self::IO(Self::TYPE_UNSUPPORTED, id.as_numeric() as u32),
+	}
+    }
 
-    // TODO: size depends on arch!
-    const SIZE: BitGeo = Self::TYPE.next(14);
-    const DIR:  BitGeo = Self::SIZE.next(2);
+    /// Returns the ioctl for the architecture independent `id`
+    pub fn from_id(id: Id) -> Option<Self> {
+	Abi::NATIVE.resolve(id).map(Self)
+    }
 
-    const DIR_NONE:  u32 = 0;
-    const DIR_WRITE: u32 = 1;
-    const DIR_READ:  u32 = 2;
+    /// Returns the architecture independent identifier
+    pub fn id(self) -> Option<Id> {
+	Abi::NATIVE.lookup(self.0)
+    }
 
     pub fn try_to_string(self) -> Option<&'static str> {
-	for f in [Self::map_termios] {
-	    if let Some(s) = f(self) {
-		return Some(s);
-	    }
-	}
-
-	None
+	self.id().and_then(Id::try_to_string)
     }
 
-    pub(crate) const fn IOC(dir: u32, tp: u8, nr: u32, sz: usize) -> Self {
-	Self(Self::DIR.encode(dir) | Self::TYPE.encode(tp as u32) |
-	     Self::NR.encode(nr) | Self::SIZE.encode(sz as u32))
+    pub(crate) const fn IOC(read: bool, write: bool, tp: u8, nr: u32, sz: usize) -> Self {
+	Self(Self::LAYOUT.ioc(read, write, tp, nr, sz))
     }
 
     pub(crate) const fn IO(tp: u8, nr: u32) -> Self {
-	Self::IOC(Self::DIR_NONE, tp, nr, 0)
-    }
-
-    pub(crate) const fn IOARG<T: Sized>(tp: u8, nr: u32) -> Self {
-	Self::IOC(Self::DIR_NONE, tp, nr, core::mem::size_of::<T>())
+	Self::IOC(false, false, tp, nr, 0)
     }
 
     pub(crate) const fn IOR<T: Sized>(tp: u8, nr: u32) -> Self {
-	Self::IOC(Self::DIR_READ, tp, nr, core::mem::size_of::<T>())
+	Self::IOC(true, false, tp, nr, core::mem::size_of::<T>())
     }
 
     pub(crate) const fn IOW<T: Sized>(tp: u8, nr: u32) -> Self {
-	Self::IOC(Self::DIR_WRITE, tp, nr, core::mem::size_of::<T>())
+	Self::IOC(false, true, tp, nr, core::mem::size_of::<T>())
     }
 
     pub(crate) const fn IOWR<T: Sized>(tp: u8, nr: u32) -> Self {
-	Self::IOC(Self::DIR_WRITE | Self::DIR_READ, tp, nr, core::mem::size_of::<T>())
+	Self::IOC(true, true, tp, nr, core::mem::size_of::<T>())
     }
 
     pub const fn as_numeric(self) -> u32 {
@@ -125,27 +87,27 @@ impl ioctl {
     }
 
     pub const fn is_io(self) -> bool {
-	Self::DIR.decode(self.0) != Self::DIR_NONE
+	self.is_read() || self.is_write()
     }
 
     pub const fn is_read(self) -> bool {
-	(Self::DIR.decode(self.0) & Self::DIR_READ) != 0
+	Self::LAYOUT.is_read(self.0)
     }
 
     pub const fn is_write(self) -> bool {
-	(Self::DIR.decode(self.0) & Self::DIR_WRITE) != 0
+	Self::LAYOUT.is_write(self.0)
     }
 
     pub const fn get_type(self) -> u8 {
-	Self::TYPE.decode(self.0) as u8
+	Self::LAYOUT.get_type(self.0)
     }
 
     pub const fn get_nr(self) -> u32 {
-	Self::NR.decode(self.0)
+	Self::LAYOUT.get_nr(self.0)
     }
 
     pub const fn get_size(self) -> usize {
-	Self::SIZE.decode(self.0) as usize
+	Self::LAYOUT.get_size(self.0)
     }
 }
 
@@ -160,7 +122,6 @@ impl std::fmt::Debug for ioctl {
 
 impl From<u32> for ioctl {
     fn from(value: u32) -> Self {
-	Self::IOR::<u32>(1,2);
         Self(value)
     }
 }
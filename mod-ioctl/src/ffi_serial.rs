@@ -1,59 +1,63 @@
+// the identifiers are transported over the network and must not be
+// changed; the native numbers are in 'abi_termios.rs'
 declare_ioctls!("termios", map_termios, {
-    pub TCGETS		=> BAD(0x5401),
-    pub TCSETS		=> BAD(0x5402),
-    pub TCSETSW		=> BAD(0x5403),
-    pub TCSETSF		=> BAD(0x5404),
-    pub TCGETA		=> BAD(0x5405),
-    pub TCSETA		=> BAD(0x5406),
-    pub TCSETAW		=> BAD(0x5407),
-    pub TCSETAF		=> BAD(0x5408),
-    pub TCSBRK		=> BAD(0x5409),
-    pub TCXONC		=> BAD(0x540A),
-    pub TCFLSH		=> BAD(0x540B),
-    pub TIOCEXCL	=> BAD(0x540C),
-    pub TIOCNXCL	=> BAD(0x540D),
-    pub TIOCSCTTY	=> BAD(0x540E),
-    pub TIOCGPGRP	=> BAD(0x540F),
-    pub TIOCSPGRP	=> BAD(0x5410),
-    pub TIOCOUTQ	=> BAD(0x5411),
-    pub TIOCSTI		=> BAD(0x5412),
-    pub TIOCGWINSZ	=> BAD(0x5413),
-    pub TIOCSWINSZ	=> BAD(0x5414),
-    pub TIOCMGET	=> BAD(0x5415),
-    pub TIOCMBIS	=> BAD(0x5416),
-    pub TIOCMBIC	=> BAD(0x5417),
-    pub TIOCMSET	=> BAD(0x5418),
-    pub TIOCGSOFTCAR	=> BAD(0x5419),
-    pub TIOCSSOFTCAR	=> BAD(0x541A),
-    pub TIOCINQ		=> BAD(0x541B),
-    pub FIONREAD	=> ALIAS(Self::TIOCINQ),
-    pub TIOCLINUX	=> BAD(0x541C),
-    pub TIOCCONS	=> BAD(0x541D),
-    pub TIOCGSERIAL	=> BAD(0x541E),
-    pub TIOCSSERIAL	=> BAD(0x541F),
-    pub TIOCPKT		=> BAD(0x5420),
-    pub FIONBIO		=> BAD(0x5421),
-    pub TIOCNOTTY	=> BAD(0x5422),
-    pub TIOCSETD	=> BAD(0x5423),
-    pub TIOCGETD	=> BAD(0x5424),
-    pub TCSBRKP		=> BAD(0x5425),
-    pub TIOCSBRK	=> BAD(0x5427),
-    pub TIOCCBRK	=> BAD(0x5428),
-    pub TIOCGSID	=> BAD(0x5429),
-    pub TIOCGLCKTRMIOS	=> BAD(0x5456),
-    pub TIOCSLCKTRMIOS	=> BAD(0x5457),
-    pub TIOCSERGSTRUCT	=> BAD(0x5458),
-    pub TIOCSERGETLSR	=> BAD(0x5459),
-    pub TIOCSERGETMULTI	=> BAD(0x545A),
-    pub TIOCSERSETMULTI	=> BAD(0x545B),
-    pub TIOCMIWAIT	=> BAD(0x545C),
-    pub TIOCGICOUNT	=> BAD(0x545D),
-
-    pub TCGETS2		=> IOR(b'T', 0x2a, termios2),
-    pub TCSETS2		=> IOW(b'T', 0x2b, termios2),
-    pub TCSETSW2	=> IOW(b'T', 0x2c, termios2),
-    pub TCSETSF2	=> IOW(b'T', 0x2d, termios2),
-    pub TIOCGEXCL	=> IOR(b'T', 0x40, nix::libc::c_int),
+    pub TCGETS		=> ID(0x01),
+    pub TCSETS		=> ID(0x02),
+    pub TCSETSW		=> ID(0x03),
+    pub TCSETSF		=> ID(0x04),
+    pub TCGETA		=> ID(0x05),
+    pub TCSETA		=> ID(0x06),
+    pub TCSETAW		=> ID(0x07),
+    pub TCSETAF		=> ID(0x08),
+    pub TCSBRK		=> ID(0x09),
+    pub TCXONC		=> ID(0x0a),
+    pub TCFLSH		=> ID(0x0b),
+    pub TIOCEXCL	=> ID(0x0c),
+    pub TIOCNXCL	=> ID(0x0d),
+    pub TIOCSCTTY	=> ID(0x0e),
+    pub TIOCGPGRP	=> ID(0x0f),
+    pub TIOCSPGRP	=> ID(0x10),
+    pub TIOCOUTQ	=> ID(0x11),
+    pub TIOCSTI		=> ID(0x12),
+    pub TIOCGWINSZ	=> ID(0x13),
+    pub TIOCSWINSZ	=> ID(0x14),
+    pub TIOCMGET	=> ID(0x15),
+    pub TIOCMBIS	=> ID(0x16),
+    pub TIOCMBIC	=> ID(0x17),
+    pub TIOCMSET	=> ID(0x18),
+    pub TIOCGSOFTCAR	=> ID(0x19),
+    pub TIOCSSOFTCAR	=> ID(0x1a),
+    pub TIOCINQ		=> ID(0x1b),
+    pub FIONREAD	=> ALIAS(TIOCINQ),
+    pub TIOCLINUX	=> ID(0x1c),
+    pub TIOCCONS	=> ID(0x1d),
+    pub TIOCGSERIAL	=> ID(0x1e),
+    pub TIOCSSERIAL	=> ID(0x1f),
+    pub TIOCPKT		=> ID(0x20),
+    pub FIONBIO		=> ID(0x21),
+    pub TIOCNOTTY	=> ID(0x22),
+    pub TIOCSETD	=> ID(0x23),
+    pub TIOCGETD	=> ID(0x24),
+    pub TCSBRKP		=> ID(0x25),
+    pub TIOCSBRK	=> ID(0x26),
+    pub TIOCCBRK	=> ID(0x27),
+    pub TIOCGSID	=> ID(0x28),
+    pub TIOCGRS485	=> ID(0x29),
+    pub TIOCSRS485	=> ID(0x2a),
+    pub TIOCGLCKTRMIOS	=> ID(0x2b),
+    pub TIOCSLCKTRMIOS	=> ID(0x2c),
+    pub TIOCSERGSTRUCT	=> ID(0x2d),
+    pub TIOCSERGETLSR	=> ID(0x2e),
+    pub TIOCSERGETMULTI	=> ID(0x2f),
+    pub TIOCSERSETMULTI	=> ID(0x30),
+    pub TIOCMIWAIT	=> ID(0x31),
+    pub TIOCGICOUNT	=> ID(0x32),
+
+    pub TCGETS2		=> ID(0x33),
+    pub TCSETS2		=> ID(0x34),
+    pub TCSETSW2	=> ID(0x35),
+    pub TCSETSF2	=> ID(0x36),
+    pub TIOCGEXCL	=> ID(0x37),
 });
 
 pub type tcflag_t = nix::libc::c_uint;
@@ -96,8 +100,17 @@ impl From<tcflag_t> for c_lflag {
     }
 }
 
+#[cfg(any(target_arch = "mips", target_arch = "mips64",
+	  target_arch = "mips32r6", target_arch = "mips64r6"))]
+pub const NCCS: usize = 23;
+#[cfg(any(target_arch = "sparc", target_arch = "sparc64"))]
+pub const NCCS: usize = 17;
+#[cfg(not(any(target_arch = "mips", target_arch = "mips64",
+	      target_arch = "mips32r6", target_arch = "mips64r6",
+	      target_arch = "sparc", target_arch = "sparc64")))]
 pub const NCCS: usize = 19;
 
+#[cfg(not(any(target_arch = "powerpc", target_arch = "powerpc64")))]
 #[repr(C)]
 pub struct termios {
     pub c_iflag:	c_iflag,
@@ -108,14 +121,45 @@ pub struct termios {
     pub c_cc:		[cc_t;NCCS],
 }
 
+/// powerpc has no 'termios2'; the speeds are part of 'termios'
+#[cfg(any(target_arch = "powerpc", target_arch = "powerpc64"))]
 #[repr(C)]
-pub struct termio {
+pub struct termios {
     pub c_iflag:	c_iflag,
     pub c_oflag:	c_oflag,
     pub c_cflag:	c_cflag,
     pub c_lflag:	c_lflag,
-    pub c_line:		cc_t,
     pub c_cc:		[cc_t;NCCS],
+    pub c_line:		cc_t,
+    pub c_ispeed:	speed_t,
+    pub c_ospeed:	speed_t,
+}
+
+#[cfg(any(target_arch = "powerpc", target_arch = "powerpc64"))]
+const _: () = {
+    use core::mem::offset_of;
+
+    assert!(offset_of!(termios, c_cc) == 16);
+    assert!(offset_of!(termios, c_line) == 16 + NCCS);
+    assert!(offset_of!(termios, c_ospeed) == core::mem::size_of::<termios>() - 4);
+};
+
+#[cfg(any(target_arch = "powerpc", target_arch = "powerpc64"))]
+pub const NCC: usize = 10;
+#[cfg(not(any(target_arch = "powerpc", target_arch = "powerpc64")))]
+pub const NCC: usize = 8;
+
+/// SysV 'struct termio'; the flags are the lower 16 bits of the termios
+/// ones
+#[repr(C)]
+#[derive(Default)]
+pub struct termio {
+    pub c_iflag:	nix::libc::c_ushort,
+    pub c_oflag:	nix::libc::c_ushort,
+    pub c_cflag:	nix::libc::c_ushort,
+    pub c_lflag:	nix::libc::c_ushort,
+    pub c_line:		cc_t,
+    pub c_cc:		[cc_t;NCC],
 }
 
 #[repr(C)]
@@ -132,6 +176,28 @@ pub struct termios2 {
     pub c_ospeed:	speed_t,
 }
 
+/// Checks the structures against the argument sizes in the ioctl
+/// numbers of the native [`Abi`](crate::abi::Abi)
+const _: () = {
+    use core::mem::size_of;
+    use crate::abi::{Abi, Id};
+
+    const fn check(id: Id, size: usize) -> bool {
+	match Abi::NATIVE.arg_size(id) {
+	    Some(sz)	=> sz == size,
+	    None	=> true,
+	}
+    }
+
+    assert!(check(Id::TCGETS, size_of::<termios>()));
+    assert!(check(Id::TCSETS, size_of::<termios>()));
+    assert!(check(Id::TCGETA, size_of::<termio>()));
+    assert!(check(Id::TCSETA, size_of::<termio>()));
+    assert!(check(Id::TCGETS2, size_of::<termios2>()));
+    assert!(check(Id::TCSETS2, size_of::<termios2>()));
+    assert!(check(Id::TIOCGWINSZ, size_of::<winsize>()));
+};
+
 #[repr(C)]
 pub struct winsize {
     pub ws_row:		nix::libc::c_ushort,
@@ -139,3 +205,95 @@ pub struct winsize {
     pub ws_xpixel:	nix::libc::c_ushort,
     pub ws_ypixel:	nix::libc::c_ushort,
 }
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct serial_icounter_struct {
+    pub cts:		nix::libc::c_int,
+    pub dsr:		nix::libc::c_int,
+    pub rng:		nix::libc::c_int,
+    pub dcd:		nix::libc::c_int,
+    pub rx:		nix::libc::c_int,
+    pub tx:		nix::libc::c_int,
+    pub frame:		nix::libc::c_int,
+    pub overrun:	nix::libc::c_int,
+    pub parity:		nix::libc::c_int,
+    pub brk:		nix::libc::c_int,
+    pub buf_overrun:	nix::libc::c_int,
+    pub reserved:	[nix::libc::c_int; 9],
+}
+
+/// Transmitter of `TIOCSERGETLSR` is empty
+pub const TIOCSER_TEMT: nix::libc::c_int = 0x01;
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct serial_rs485 {
+    pub flags:			u32,
+    pub delay_rts_before_send:	u32,
+    pub delay_rts_after_send:	u32,
+    pub addr_recv:		u8,
+    pub addr_dest:		u8,
+    pub padding0:		[u8; 2],
+    pub padding1:		[u32; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct serial_struct {
+    pub type_:			nix::libc::c_int,
+    pub line:			nix::libc::c_int,
+    pub port:			nix::libc::c_uint,
+    pub irq:			nix::libc::c_int,
+    pub flags:			nix::libc::c_int,
+    pub xmit_fifo_size:		nix::libc::c_int,
+    pub custom_divisor:		nix::libc::c_int,
+    pub baud_base:		nix::libc::c_int,
+    pub close_delay:		nix::libc::c_ushort,
+    pub io_type:		nix::libc::c_char,
+    pub reserved_char:		nix::libc::c_char,
+    pub hub6:			nix::libc::c_int,
+    pub closing_wait:		nix::libc::c_ushort,
+    pub closing_wait2:		nix::libc::c_ushort,
+    pub iomem_base:		*mut nix::libc::c_uchar,
+    pub iomem_reg_shift:	nix::libc::c_ushort,
+    pub port_high:		nix::libc::c_uint,
+    pub iomap_base:		nix::libc::c_ulong,
+}
+
+impl Default for serial_struct {
+    fn default() -> Self {
+	Self {
+	    type_:		0,
+	    line:		0,
+	    port:		0,
+	    irq:		0,
+	    flags:		0,
+	    xmit_fifo_size:	0,
+	    custom_divisor:	0,
+	    baud_base:		0,
+	    close_delay:	0,
+	    io_type:		0,
+	    reserved_char:	0,
+	    hub6:		0,
+	    closing_wait:	0,
+	    closing_wait2:	0,
+	    iomem_base:		core::ptr::null_mut(),
+	    iomem_reg_shift:	0,
+	    port_high:		0,
+	    iomap_base:		0,
+	}
+    }
+}
+
+/// `flags` of `serial_struct`
+pub const ASYNC_SPD_HI: nix::libc::c_int = 0x0010;
+pub const ASYNC_SPD_VHI: nix::libc::c_int = 0x0020;
+pub const ASYNC_SPD_CUST: nix::libc::c_int = 0x0030;
+pub const ASYNC_SPD_SHI: nix::libc::c_int = 0x1000;
+pub const ASYNC_SPD_WARP: nix::libc::c_int = 0x1010;
+pub const ASYNC_SPD_MASK: nix::libc::c_int = 0x1030;
+pub const ASYNC_LOW_LATENCY: nix::libc::c_int = 0x2000;
+
+/// default line discipline of `TIOCSETD`
+pub const N_TTY: nix::libc::c_int = 0;
@@ -4,7 +4,8 @@
 //extern crate tracing;
 
 pub mod ffi;
+pub mod abi;
+pub mod termbits;
 mod error;
-mod bad;
 
-pub use bad::BadIoctl;
+pub use error::Error;
@@ -0,0 +1,163 @@
+//! Values of the termios flags, control characters and modem lines in
+//! the wire format
+//!
+//! The wire format uses the `asm-generic` values; other architecture
+//! families are translated by [`Abi`](crate::abi::Abi).
+
+use crate::ffi::tcflag_t;
+use nix::libc::c_int;
+
+// c_iflag
+pub const IGNBRK:	tcflag_t = 0o000001;
+pub const BRKINT:	tcflag_t = 0o000002;
+pub const IGNPAR:	tcflag_t = 0o000004;
+pub const PARMRK:	tcflag_t = 0o000010;
+pub const INPCK:	tcflag_t = 0o000020;
+pub const ISTRIP:	tcflag_t = 0o000040;
+pub const INLCR:	tcflag_t = 0o000100;
+pub const IGNCR:	tcflag_t = 0o000200;
+pub const ICRNL:	tcflag_t = 0o000400;
+pub const IUCLC:	tcflag_t = 0o001000;
+pub const IXON:		tcflag_t = 0o002000;
+pub const IXANY:	tcflag_t = 0o004000;
+pub const IXOFF:	tcflag_t = 0o010000;
+pub const IMAXBEL:	tcflag_t = 0o020000;
+pub const IUTF8:	tcflag_t = 0o040000;
+
+// c_oflag
+pub const OPOST:	tcflag_t = 0o000001;
+pub const OLCUC:	tcflag_t = 0o000002;
+pub const ONLCR:	tcflag_t = 0o000004;
+pub const OCRNL:	tcflag_t = 0o000010;
+pub const ONOCR:	tcflag_t = 0o000020;
+pub const ONLRET:	tcflag_t = 0o000040;
+pub const OFILL:	tcflag_t = 0o000100;
+pub const OFDEL:	tcflag_t = 0o000200;
+pub const NLDLY:	tcflag_t = 0o000400;
+pub const NL0:		tcflag_t = 0o000000;
+pub const NL1:		tcflag_t = 0o000400;
+pub const CRDLY:	tcflag_t = 0o003000;
+pub const CR0:		tcflag_t = 0o000000;
+pub const CR1:		tcflag_t = 0o001000;
+pub const CR2:		tcflag_t = 0o002000;
+pub const CR3:		tcflag_t = 0o003000;
+pub const TABDLY:	tcflag_t = 0o014000;
+pub const TAB0:		tcflag_t = 0o000000;
+pub const TAB1:		tcflag_t = 0o004000;
+pub const TAB2:		tcflag_t = 0o010000;
+pub const TAB3:		tcflag_t = 0o014000;
+pub const BSDLY:	tcflag_t = 0o020000;
+pub const BS0:		tcflag_t = 0o000000;
+pub const BS1:		tcflag_t = 0o020000;
+pub const VTDLY:	tcflag_t = 0o040000;
+pub const VT0:		tcflag_t = 0o000000;
+pub const VT1:		tcflag_t = 0o040000;
+pub const FFDLY:	tcflag_t = 0o100000;
+pub const FF0:		tcflag_t = 0o000000;
+pub const FF1:		tcflag_t = 0o100000;
+
+// c_cflag
+pub const CBAUD:	tcflag_t = 0o010017;
+pub const B0:		tcflag_t = 0o000000;
+pub const B50:		tcflag_t = 0o000001;
+pub const B75:		tcflag_t = 0o000002;
+pub const B110:		tcflag_t = 0o000003;
+pub const B134:		tcflag_t = 0o000004;
+pub const B150:		tcflag_t = 0o000005;
+pub const B200:		tcflag_t = 0o000006;
+pub const B300:		tcflag_t = 0o000007;
+pub const B600:		tcflag_t = 0o000010;
+pub const B1200:	tcflag_t = 0o000011;
+pub const B1800:	tcflag_t = 0o000012;
+pub const B2400:	tcflag_t = 0o000013;
+pub const B4800:	tcflag_t = 0o000014;
+pub const B9600:	tcflag_t = 0o000015;
+pub const B19200:	tcflag_t = 0o000016;
+pub const B38400:	tcflag_t = 0o000017;
+pub const CSIZE:	tcflag_t = 0o000060;
+pub const CS5:		tcflag_t = 0o000000;
+pub const CS6:		tcflag_t = 0o000020;
+pub const CS7:		tcflag_t = 0o000040;
+pub const CS8:		tcflag_t = 0o000060;
+pub const CSTOPB:	tcflag_t = 0o000100;
+pub const CREAD:	tcflag_t = 0o000200;
+pub const PARENB:	tcflag_t = 0o000400;
+pub const PARODD:	tcflag_t = 0o001000;
+pub const HUPCL:	tcflag_t = 0o002000;
+pub const CLOCAL:	tcflag_t = 0o004000;
+pub const CBAUDEX:	tcflag_t = 0o010000;
+pub const BOTHER:	tcflag_t = 0o010000;
+pub const B57600:	tcflag_t = 0o010001;
+pub const B115200:	tcflag_t = 0o010002;
+pub const B230400:	tcflag_t = 0o010003;
+pub const B460800:	tcflag_t = 0o010004;
+pub const B500000:	tcflag_t = 0o010005;
+pub const B576000:	tcflag_t = 0o010006;
+pub const B921600:	tcflag_t = 0o010007;
+pub const B1000000:	tcflag_t = 0o010010;
+pub const B1152000:	tcflag_t = 0o010011;
+pub const B1500000:	tcflag_t = 0o010012;
+pub const B2000000:	tcflag_t = 0o010013;
+pub const B2500000:	tcflag_t = 0o010014;
+pub const B3000000:	tcflag_t = 0o010015;
+pub const B3500000:	tcflag_t = 0o010016;
+pub const B4000000:	tcflag_t = 0o010017;
+pub const CIBAUD:	tcflag_t = 0o02003600000;
+pub const CMSPAR:	tcflag_t = 0o10000000000;
+pub const CRTSCTS:	tcflag_t = 0o20000000000;
+
+/// shift from `CBAUD` to `CIBAUD`; the same on all architectures
+pub const IBSHIFT:	tcflag_t = 16;
+
+// c_lflag
+pub const ISIG:		tcflag_t = 0o000001;
+pub const ICANON:	tcflag_t = 0o000002;
+pub const XCASE:	tcflag_t = 0o000004;
+pub const ECHO:		tcflag_t = 0o000010;
+pub const ECHOE:	tcflag_t = 0o000020;
+pub const ECHOK:	tcflag_t = 0o000040;
+pub const ECHONL:	tcflag_t = 0o000100;
+pub const NOFLSH:	tcflag_t = 0o000200;
+pub const TOSTOP:	tcflag_t = 0o000400;
+pub const ECHOCTL:	tcflag_t = 0o001000;
+pub const ECHOPRT:	tcflag_t = 0o002000;
+pub const ECHOKE:	tcflag_t = 0o004000;
+pub const FLUSHO:	tcflag_t = 0o010000;
+pub const PENDIN:	tcflag_t = 0o040000;
+pub const IEXTEN:	tcflag_t = 0o100000;
+pub const EXTPROC:	tcflag_t = 0o200000;
+
+// indices of c_cc
+pub const VINTR:	usize = 0;
+pub const VQUIT:	usize = 1;
+pub const VERASE:	usize = 2;
+pub const VKILL:	usize = 3;
+pub const VEOF:		usize = 4;
+pub const VTIME:	usize = 5;
+pub const VMIN:		usize = 6;
+pub const VSWTC:	usize = 7;
+pub const VSTART:	usize = 8;
+pub const VSTOP:	usize = 9;
+pub const VSUSP:	usize = 10;
+pub const VEOL:		usize = 11;
+pub const VREPRINT:	usize = 12;
+pub const VDISCARD:	usize = 13;
+pub const VWERASE:	usize = 14;
+pub const VLNEXT:	usize = 15;
+pub const VEOL2:	usize = 16;
+
+// modem lines of TIOCMGET and friends
+pub const TIOCM_LE:	c_int = 0x001;
+pub const TIOCM_DTR:	c_int = 0x002;
+pub const TIOCM_RTS:	c_int = 0x004;
+pub const TIOCM_ST:	c_int = 0x008;
+pub const TIOCM_SR:	c_int = 0x010;
+pub const TIOCM_CTS:	c_int = 0x020;
+pub const TIOCM_CAR:	c_int = 0x040;
+pub const TIOCM_RNG:	c_int = 0x080;
+pub const TIOCM_DSR:	c_int = 0x100;
+pub const TIOCM_CD:	c_int = TIOCM_CAR;
+pub const TIOCM_RI:	c_int = TIOCM_RNG;
+pub const TIOCM_OUT1:	c_int = 0x2000;
+pub const TIOCM_OUT2:	c_int = 0x4000;
+pub const TIOCM_LOOP:	c_int = 0x8000;
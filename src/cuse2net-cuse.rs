@@ -4,11 +4,13 @@
 extern crate tracing;
 
 use std::sync::Arc;
-use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
 use ensc_cuse_ffi::{OpIn, KernelVersion};
 
-use r_cuse2net::{ Result, CuseFileDevice, virtdev };
+use r_cuse2net::{ Result, CuseFileDevice, proto, virtdev };
 use r_cuse2net::virtdev::DeviceRegistry;
+use r_cuse2net::transport::{Address, Connector, Keepalive, tls};
 
 #[derive(clap::ValueEnum)]
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -19,6 +21,48 @@ enum LogFormat {
     Json,
 }
 
+/// Server given by `--server`
+#[derive(Clone, Debug)]
+enum Server {
+    Cuse2net(Address),
+    Rfc2217(Address),
+    Raw(Address),
+}
+
+impl Server {
+    fn addr(&self) -> &Address {
+	match self {
+	    Self::Cuse2net(addr) |
+	    Self::Rfc2217(addr) |
+	    Self::Raw(addr)		=> addr,
+	}
+    }
+}
+
+impl std::str::FromStr for Server {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+	use std::net::ToSocketAddrs;
+
+	fn resolve(addr: &str) -> std::result::Result<Address, String> {
+	    addr.to_socket_addrs()
+		.map_err(|e| format!("bad address '{addr}': {e}"))?
+		.next()
+		.map(Address::Tcp)
+		.ok_or_else(|| format!("no address for '{addr}'"))
+	}
+
+	if let Some(addr) = s.strip_prefix("rfc2217://") {
+	    resolve(addr).map(Self::Rfc2217)
+	} else if let Some(addr) = s.strip_prefix("raw://") {
+	    resolve(addr).map(Self::Raw)
+	} else {
+	    s.parse().map(Self::Cuse2net)
+	}
+    }
+}
+
 #[derive(clap::Parser, Debug)]
 #[clap(author, version, about)]
 struct CliOpts {
@@ -26,9 +70,12 @@ struct CliOpts {
     /// log format
     log_format:		LogFormat,
 
-    #[clap(short,long, value_parser, value_name("server:port"))]
-    /// device major number
-    server:		SocketAddr,
+    #[clap(short,long, value_parser, value_name("ADDRESS"))]
+    /// address of the server; either 'ip:port', 'unix:PATH' or
+    /// 'vsock:CID:PORT' of a cuse2net-dev instance, 'rfc2217://host:port'
+    /// of an RFC 2217 terminal server or 'raw://host:port' of a plain TCP
+    /// port
+    server:		Server,
 
     #[clap(short('m'), long, value_parser(1..=511), value_name("node-major"))]
     /// device major number
@@ -41,6 +88,97 @@ struct CliOpts {
     #[clap(short, long, value_parser)]
     /// device name (without /dev)
     device:		String,
+
+    #[clap(long)]
+    /// use TLS for the connection to the server
+    tls:		bool,
+
+    #[clap(long, value_parser, value_name("PEM"), requires("tls"))]
+    /// CA which signed the server certificate
+    tls_ca:		Option<PathBuf>,
+
+    #[clap(long, value_parser, value_name("SHA256"), requires("tls"))]
+    /// fingerprint of an accepted server certificate; can be given
+    /// multiple times
+    tls_fingerprint:	Vec<tls::Fingerprint>,
+
+    #[clap(long, value_parser, value_name("PEM"), requires_all(["tls", "tls_key"]))]
+    /// client certificate
+    tls_cert:		Option<PathBuf>,
+
+    #[clap(long, value_parser, value_name("PEM"), requires("tls_cert"))]
+    /// key of the client certificate
+    tls_key:		Option<PathBuf>,
+
+    #[clap(long, value_parser, value_name("NAME"), requires("tls"))]
+    /// name in the server certificate [default: ip address of server]
+    tls_server_name:	Option<String>,
+
+    #[clap(long, value_parser, value_name("SECS"), default_value("10"))]
+    /// interval of heartbeat probes; 0 disables the heartbeat
+    heartbeat_interval:	u64,
+
+    #[clap(long, value_parser(clap::value_parser!(u32).range(1..)), value_name("COUNT"),
+	   default_value("3"))]
+    /// number of unanswered probes after which the peer is considered
+    /// dead; used for the heartbeat and TCP keepalive
+    heartbeat_misses:	u32,
+
+    #[clap(long, value_parser, value_name("SECS"), default_value("30"))]
+    /// interval of TCP keepalive probes; 0 disables them
+    tcp_keepalive:	u64,
+}
+
+impl CliOpts {
+    fn connector(&self) -> Result<Connector> {
+	let tls = match self.tls {
+	    false	=> None,
+	    true	=> Some(tls::Client::new(tls::ClientParams {
+		ca:		self.tls_ca.clone(),
+		fingerprints:	self.tls_fingerprint.clone(),
+		cert:		self.tls_cert.clone().zip(self.tls_key.clone()),
+		server_name:	self.tls_server_name.clone(),
+	    }, self.server.addr())?),
+	};
+
+	Ok(Connector::new(self.server.addr().clone(), tls, self.keepalive()))
+    }
+
+    fn remote(&self) -> Result<virtdev::Remote> {
+	let connector = Arc::new(self.connector()?);
+
+	Ok(match self.server {
+	    Server::Cuse2net(_)	=> virtdev::Remote::Cuse2net {
+		connector:	connector,
+		heartbeat:	self.heartbeat(),
+	    },
+
+	    Server::Rfc2217(_)	=> virtdev::Remote::Rfc2217 {
+		connector:	connector,
+	    },
+
+	    Server::Raw(_)	=> virtdev::Remote::Raw {
+		connector:	connector,
+	    },
+	})
+    }
+
+    fn heartbeat(&self) -> proto::Heartbeat {
+	proto::Heartbeat {
+	    interval:	Duration::from_secs(self.heartbeat_interval),
+	    misses:	self.heartbeat_misses,
+	}
+    }
+
+    fn keepalive(&self) -> Option<Keepalive> {
+	match self.tcp_keepalive {
+	    0		=> None,
+	    secs	=> Some(Keepalive {
+		interval:	Duration::from_secs(secs),
+		count:		self.heartbeat_misses,
+	    }),
+	}
+    }
 }
 
 fn main() -> Result<()> {
@@ -48,6 +186,15 @@ fn main() -> Result<()> {
 
     let mut args = CliOpts::parse();
 
+    if args.tls && !matches!(args.server, Server::Cuse2net(_)) {
+	use clap::CommandFactory;
+
+	CliOpts::command()
+	    .error(clap::error::ErrorKind::ArgumentConflict,
+		   "TLS is only supported for cuse2net-dev servers")
+	    .exit();
+    }
+
     if args.log_format == LogFormat::Default {
 	args.log_format = LogFormat::Full;
     }
@@ -70,8 +217,7 @@ fn main() -> Result<()> {
 	.open("/dev/cuse")
 	.map(|d| Arc::new(CuseFileDevice::new(d)))?;
 
-    let devices = DeviceRegistry::new(cuse.clone());
-    let addr = args.server;
+    let devices = DeviceRegistry::new(cuse.clone(), args.remote()?);
 
     let f = cuse.as_ref();
 
@@ -113,7 +259,7 @@ fn main() -> Result<()> {
 	    },
 
 	    OpIn::FuseOpen(params)			=>
-		devices.create(addr, info, params)?,
+		devices.create(info, params)?,
 
 	    OpIn::FuseRelease(params)			=>
 		devices.release(params.fh, info),
@@ -3,12 +3,14 @@
 #[macro_use]
 extern crate tracing;
 
-use std::mem::MaybeUninit;
 use std::path::PathBuf;
-use std::net::{TcpStream, TcpListener, SocketAddr};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
 
-use r_cuse2net::Result;
-use r_cuse2net::realdev;
+use r_cuse2net::{ Error, Result };
+use r_cuse2net::{proto, realdev};
+use r_cuse2net::transport::{Address, Keepalive, Listener, Stream, tls};
 
 #[derive(clap::ValueEnum)]
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -19,6 +21,25 @@ enum LogFormat {
     Json,
 }
 
+/// Either a complete address or an ip address which is combined with
+/// `--port`
+#[derive(Clone, Debug)]
+enum ListenAddr {
+    Ip(IpAddr),
+    Addr(Address),
+}
+
+impl std::str::FromStr for ListenAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+	match s.parse() {
+	    Ok(ip)	=> Ok(Self::Ip(ip)),
+	    Err(_)	=> s.parse().map(Self::Addr),
+	}
+    }
+}
+
 #[derive(clap::Parser, Debug)]
 #[clap(author, version, about)]
 struct CliOpts {
@@ -26,41 +47,210 @@ struct CliOpts {
 	   default_value("default"))]
     log_format:		LogFormat,
 
-    #[clap(short, long, value_parser, value_name("IP"), default_value("::"))]
-    /// ip address to listen on
-    listen:		std::net::IpAddr,
+    #[clap(short, long, value_parser, value_name("ADDRESS"), default_value("::"))]
+    /// address to listen on; either 'ip', 'ip:port', 'unix:PATH' or
+    /// 'vsock:CID:PORT'
+    listen:		ListenAddr,
 
     #[clap(short, long, value_parser, default_value("8000"))]
-    /// port to listen on
+    /// port to listen on when '--listen' is an ip address
     port:		u16,
 
     #[clap(short, long, value_parser)]
     /// device
     device:		PathBuf,
+
+    #[clap(long, value_parser, value_name("PEM"), requires("tls_key"))]
+    /// server certificate; enables TLS
+    tls_cert:		Option<PathBuf>,
+
+    #[clap(long, value_parser, value_name("PEM"), requires("tls_cert"))]
+    /// key of the server certificate
+    tls_key:		Option<PathBuf>,
+
+    #[clap(long, value_parser, value_name("PEM"), requires("tls_cert"))]
+    /// CA which signed the client certificates; when given, clients
+    /// must authenticate themselves
+    tls_ca:		Option<PathBuf>,
+
+    #[clap(long, value_parser, value_name("SECS"), default_value("10"))]
+    /// interval of heartbeat probes; 0 disables the heartbeat
+    heartbeat_interval:	u64,
+
+    #[clap(long, value_parser(clap::value_parser!(u32).range(1..)), value_name("COUNT"),
+	   default_value("3"))]
+    /// number of unanswered probes after which the peer is considered
+    /// dead; used for the heartbeat and TCP keepalive
+    heartbeat_misses:	u32,
+
+    #[clap(long, value_parser, value_name("SECS"), default_value("30"))]
+    /// interval of TCP keepalive probes; 0 disables them
+    tcp_keepalive:	u64,
+
+    #[clap(long, value_parser, value_name("ADDRESS"))]
+    /// additionally accept RFC 2217 clients on this address; either
+    /// 'ip:port', 'unix:PATH' or 'vsock:CID:PORT'.  The listener is
+    /// unauthenticated and unencrypted; ip and vsock addresses require
+    /// '--rfc2217-insecure'
+    rfc2217:		Option<Address>,
+
+    #[clap(long, requires("rfc2217"))]
+    /// confirm that everybody who can reach the '--rfc2217' address may
+    /// use the device
+    rfc2217_insecure:	bool,
+
+    #[clap(long, value_parser(parse_mask), value_name("MASK"), default_value("0x3030"))]
+    /// 'ASYNC_*' flags of TIOCSSERIAL which can be changed by clients;
+    /// other flags keep the settings of the device
+    serial_flags:	u32,
+
+    #[clap(long, value_parser, value_name("IOCTL"))]
+    /// pass unknown ioctls through to the device; either 'NUM',
+    /// 'NUM-NUM' or 'type:NUM'.  Only ioctls with direction and size
+    /// bits (_IOR, _IOW, _IOWR) are accepted.
+    allow_ioctl:	Vec<realdev::RawIoctl>,
+
+    #[clap(long, value_parser, value_name("FILE"))]
+    /// file with additional '--allow-ioctl' entries; one per line, '#'
+    /// starts a comment
+    allow_ioctl_file:	Option<PathBuf>,
+
+    #[clap(long, value_parser, value_name("LDISC"))]
+    /// line discipline which can be attached by clients with TIOCSETD
+    /// (e.g. 1 for N_SLIP, 3 for N_PPP or 21 for N_GSM0710); N_TTY is
+    /// always allowed and restored when the client disconnects
+    allow_ldisc:	Vec<u32>,
 }
 
-fn run_thread(sock: TcpStream, device: PathBuf) -> Result<()> {
-    use r_cuse2net::proto;
+fn parse_mask(s: &str) -> std::result::Result<u32, String> {
+    let res = match s.strip_prefix("0x") {
+	Some(hex)	=> u32::from_str_radix(hex, 16),
+	None		=> s.parse(),
+    };
+
+    res.map_err(|e| format!("bad mask '{s}': {e}"))
+}
+
+impl CliOpts {
+    fn address(&self) -> Address {
+	match &self.listen {
+	    ListenAddr::Ip(ip)		=> Address::Tcp(SocketAddr::new(*ip, self.port)),
+	    ListenAddr::Addr(addr)	=> addr.clone(),
+	}
+    }
+
+    fn tls_server(&self) -> Result<Option<tls::Server>> {
+	let (Some(cert), Some(key)) = (&self.tls_cert, &self.tls_key) else {
+	    return Ok(None);
+	};
+
+	Ok(Some(tls::Server::new(tls::ServerParams {
+	    cert:	cert.clone(),
+	    key:	key.clone(),
+	    ca:		self.tls_ca.clone(),
+	})?))
+    }
 
-    let dev = {
-	let mut buf: [MaybeUninit<u8>; proto::MAX_MSG_SIZE] = [MaybeUninit::uninit(); proto::MAX_MSG_SIZE];
+    fn heartbeat(&self) -> proto::Heartbeat {
+	proto::Heartbeat {
+	    interval:	Duration::from_secs(self.heartbeat_interval),
+	    misses:	self.heartbeat_misses,
+	}
+    }
+
+    fn raw_ioctls(&self) -> Result<Vec<realdev::RawIoctl>> {
+	let mut res = self.allow_ioctl.clone();
 
-	let op = proto::Request::recv(&sock, &mut buf)?;
-	debug!("running {op:?}");
+	let Some(path) = &self.allow_ioctl_file else {
+	    return Ok(res);
+	};
 
-	match op {
-	    proto::Request::Open(seq, args) =>
-		realdev::Device::open(device, seq, args.flags.as_ffi(), sock)?,
+	for (idx, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+	    let line = line.split('#').next().unwrap_or_default().trim();
 
-	    op		=> {
-		warn!("unexpected operation {op:?}");
-		return Err(proto::Error::BadRequest.into());
+	    if line.is_empty() {
+		continue;
 	    }
+
+	    let entry = line.parse()
+		.map_err(|e| Error::Config(format!("{}:{}: {e}", path.display(), idx + 1)))?;
+
+	    res.push(entry);
 	}
-    };
 
-    dev.run()
+	Ok(res)
+    }
+
+    fn policy(&self) -> Result<realdev::Policy> {
+	Ok(realdev::Policy {
+	    serial_flags:	self.serial_flags,
+	    raw_ioctls:		self.raw_ioctls()?,
+	    ldiscs:		self.allow_ldisc.clone(),
+	})
+    }
+
+    /// Returns the address of the RFC 2217 listener; only unix sockets
+    /// can be restricted without '--rfc2217-insecure'
+    fn rfc2217(&self) -> Result<Option<&Address>> {
+	match &self.rfc2217 {
+	    Some(Address::Unix(_)) | None	=> Ok(self.rfc2217.as_ref()),
+	    Some(_) if self.rfc2217_insecure	=> Ok(self.rfc2217.as_ref()),
+	    Some(addr)				=> Err(Error::Config(format!(
+		"RFC 2217 clients on {addr} are not authenticated; confirm with --rfc2217-insecure"))),
+	}
+    }
+
+    fn keepalive(&self) -> Option<Keepalive> {
+	match self.tcp_keepalive {
+	    0		=> None,
+	    secs	=> Some(Keepalive {
+		interval:	Duration::from_secs(secs),
+		count:		self.heartbeat_misses,
+	    }),
+	}
+    }
+}
+
+fn accept(conn: Stream, tls: Option<&tls::Server>, keepalive: Option<&Keepalive>) -> Result<Stream> {
+    conn.set_keepalive(keepalive)?;
+
+    match tls {
+	None		=> Ok(conn),
+	Some(tls)	=> tls.accept(conn),
+    }
 }
+
+fn run_thread(sock: Stream, device: PathBuf, heartbeat: proto::Heartbeat,
+	      policy: Arc<realdev::Policy>) -> Result<()> {
+    let session = proto::Hello::accept(&sock, &heartbeat)?;
+    let timeout = heartbeat.timeout(&session);
+
+    realdev::Connection::new(device, sock, session, timeout, policy).run()
+}
+
+fn run_rfc2217(socket: Listener, device: PathBuf, keepalive: Option<Keepalive>) -> Result<()> {
+    loop {
+	let (conn, addr) = socket.accept()?;
+	let device = device.clone();
+
+	info!("RFC 2217 connection from {addr}");
+
+	std::thread::Builder::new()
+	    .name(format!("rfc2217-{addr}"))
+	    .spawn(move || {
+		let res = (|| {
+		    conn.set_keepalive(keepalive.as_ref())?;
+		    realdev::Rfc2217::open(&device, conn)?.run()
+		})();
+
+		match res {
+		    Ok(_)	=> debug!("RFC 2217 connection from {addr} finished successfully"),
+		    Err(e)	=> warn!("RFC 2217 connection from {addr} failed with {e:?}"),
+		}
+	    })?;
+    }
+}
+
 fn main() -> Result<()> {
     use clap::Parser;
 
@@ -80,26 +270,50 @@ fn main() -> Result<()> {
 	LogFormat::Default		=> unreachable!(),
     }
 
-    let socket = TcpListener::bind(SocketAddr::new(args.listen, args.port))?;
+    let tls = args.tls_server()?.map(Arc::new);
+    let policy = Arc::new(args.policy()?);
+    let rfc2217 = args.rfc2217()?;
+    let socket = Listener::bind(&args.address())?;
+
+    info!("running cuse2net-dev on {}", args.address());
+
+    if let Some(addr) = rfc2217 {
+	let socket = Listener::bind(addr)?;
+	let device = args.device.clone();
+	let keepalive = args.keepalive();
 
-    info!("running cuse2net-dev");
+	info!("accepting RFC 2217 clients on {addr}");
+
+	std::thread::Builder::new()
+	    .name("rfc2217".to_string())
+	    .spawn(move || {
+		if let Err(e) = run_rfc2217(socket, device, keepalive) {
+		    error!("RFC 2217 listener failed with {e:?}");
+		}
+	    })?;
+    }
 
     r_cuse2net::deadlock_detect();
 
     loop {
 	let (conn, addr) = socket.accept()?;
 	let device = args.device.clone();
+	let tls = tls.clone();
+	let heartbeat = args.heartbeat();
+	let keepalive = args.keepalive();
+	let policy = policy.clone();
 
-	conn.set_nodelay(true)?;
-
-	info!("connection from {addr:?}");
+	info!("connection from {addr}");
 
 	std::thread::Builder::new()
-	    .name(format!("{addr:?}"))
+	    .name(addr.clone())
 	    .spawn(move || {
-		match run_thread(conn, device) {
-		    Ok(_)	=> debug!("connection from {addr:?} finished successfully"),
-		    Err(e)	=> warn!("connection from {addr:?} failed with {e:?}"),
+		let res = accept(conn, tls.as_deref(), keepalive.as_ref())
+		    .and_then(|conn| run_thread(conn, device, heartbeat, policy));
+
+		match res {
+		    Ok(_)	=> debug!("connection from {addr} finished successfully"),
+		    Err(e)	=> warn!("connection from {addr} failed with {e:?}"),
 		}
 	    })?;
     }
@@ -12,6 +12,15 @@ pub enum Error {
     #[error(transparent)]
     Protocol(#[from] crate::proto::Error),
 
+    #[error(transparent)]
+    Tls(#[from] rustls::Error),
+
+    #[error("bad TLS configuration: {0}")]
+    TlsConfig(String),
+
+    #[error("bad configuration: {0}")]
+    Config(String),
+
     #[error("remote error {0}")]
     Remote(i32),
 }
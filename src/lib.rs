@@ -1,5 +1,6 @@
 #![allow(clippy::redundant_field_names)]
 #![allow(clippy::len_without_is_empty)]
+#![allow(clippy::items_after_test_module)]
 
 #[macro_use]
 extern crate tracing;
@@ -8,6 +9,8 @@ mod error;
 pub mod virtdev;
 pub mod realdev;
 pub mod proto;
+pub mod transport;
+pub mod rfc2217;
 
 use ensc_cuse_ffi::CuseDevice;
 pub use error::Error;
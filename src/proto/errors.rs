@@ -33,6 +33,21 @@ pub enum Error {
     #[error("bad ioctl param")]
     BadIoctlParam,
 
+    #[error("peer is not a cuse2net program")]
+    BadMagic,
+
+    #[error("protocol version mismatch; local {0}, remote {1}")]
+    VersionMismatch(u16, u16),
+
+    #[error("incompatible peer; {0}")]
+    Incompatible(String),
+
+    #[error("peer does not support the protocol handshake")]
+    NoHandshake,
+
+    #[error("peer does not respond")]
+    PeerDead,
+
     #[error("remote error {1} on sequence {0:?}")]
     RemoteError(Option<Sequence>, i32),
 }
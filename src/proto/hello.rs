@@ -0,0 +1,376 @@
+//! Handshake which is exchanged as the first message on every connection
+//!
+//! The client sends a [`Hello`] request and the server answers with its
+//! own [`Hello`] in a `HelloAck` response; both sides then check with
+//! [`Hello::negotiate()`] whether they can talk to each other.  The
+//! server always answers before rejecting a client so that the client
+//! can report the reason of the mismatch.
+
+use std::os::fd::AsFd;
+use std::time::Duration;
+
+use ensc_ioctl_ffi::abi::Abi;
+
+use super::endian::*;
+use super::{AsReprBytes, AsReprBytesMut, Error, Request, Response, Result};
+
+pub const MAGIC: [u8;4] = *b"C2N\0";
+
+/// Version of the wire protocol; peers must use the same version
+pub const PROTOCOL_VERSION: u16 = 1;
+
+const PTR_WIDTH: usize = core::mem::size_of::<* const u8>();
+
+#[repr(transparent)]
+#[derive(Copy, Clone, PartialEq, Eq, Default)]
+pub struct Capabilities(be64);
+
+impl Capabilities {
+    /// Capabilities which are implemented by this program
+    pub const LOCAL: Self = Self::empty();
+
+    #[allow(dead_code)]
+    const fn bit(pos: u8) -> Self {
+	Self(be64::from_native(1 << pos))
+    }
+
+    pub const fn empty() -> Self {
+	Self(be64::from_native(0))
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+	self.0.bit_and(other.0).as_native() == other.0.as_native()
+    }
+
+    pub const fn intersection(self, other: Self) -> Self {
+	Self(self.0.bit_and(other.0))
+    }
+}
+
+impl std::fmt::Debug for Capabilities {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	write!(f, "{:#x}", self.0.as_native())
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Endian {
+    Little	= 1,
+    Big		= 2,
+}
+
+impl Endian {
+    pub const fn native() -> Self {
+	match cfg!(target_endian = "big") {
+	    true	=> Self::Big,
+	    false	=> Self::Little,
+	}
+    }
+
+    fn try_from_u8(v: u8) -> Option<Self> {
+	Some(match v {
+	    1	=> Self::Little,
+	    2	=> Self::Big,
+	    _	=> return None,
+	})
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct Hello {
+    magic:		[u8;4],
+    version:		be16,
+    endian:		be8,
+    /// size of pointers of the sender in bytes; zero when unknown
+    ptr_width:		be8,
+    /// maximum payload size which is accepted by the sender
+    max_msg_size:	be32,
+    /// heartbeat interval of the sender in ms; zero when disabled
+    heartbeat:		be32,
+    capabilities:	Capabilities,
+    /// architecture of the sender; NUL padded
+    arch:		[u8;16],
+}
+
+unsafe impl AsReprBytes for Hello {}
+unsafe impl AsReprBytesMut for Hello {}
+
+/// Parameters of a connection which were agreed on in the handshake
+#[derive(Debug, Clone, Copy)]
+pub struct Session {
+    /// maximum payload size which is accepted by the peer
+    pub max_msg_size:	usize,
+    /// capabilities which are supported by both sides
+    pub capabilities:	Capabilities,
+    /// interval of the heartbeat; `None` when it is disabled by one side
+    pub heartbeat:	Option<Duration>,
+    /// ioctl family of the peer; arguments without a translating codec
+    /// can be exchanged only within the same family
+    pub abi:		Abi,
+    /// size of pointers of the peer in bytes; indirect ioctls require
+    /// the same size on both sides
+    pub ptr_width:	usize,
+    /// byte order of the peer; the wire format is big endian but raw
+    /// and indirect ioctl arguments are exchanged in native order
+    pub endian:		Endian,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+	Self {
+	    max_msg_size:	super::MAX_PAYLOAD_SIZE,
+	    capabilities:	Capabilities::empty(),
+	    heartbeat:		None,
+	    abi:		Abi::NATIVE,
+	    ptr_width:		PTR_WIDTH,
+	    endian:		Endian::native(),
+	}
+    }
+}
+
+/// Detection of dead peers
+#[derive(Debug, Clone, Copy)]
+pub struct Heartbeat {
+    /// interval of the probes; zero disables the heartbeat
+    pub interval:	Duration,
+    /// number of unanswered probes after which the peer is considered dead
+    pub misses:		u32,
+}
+
+impl Heartbeat {
+    /// Returns the time without any message from the peer after which it
+    /// is considered dead
+    pub fn timeout(&self, session: &Session) -> Option<Duration> {
+	session.heartbeat.map(|interval| interval * (self.misses + 1))
+    }
+}
+
+impl Hello {
+    pub fn local() -> Self {
+	let mut arch = [0_u8; 16];
+	let name = std::env::consts::ARCH.as_bytes();
+	let len = name.len().min(arch.len());
+
+	arch[..len].copy_from_slice(&name[..len]);
+
+	Self {
+	    magic:		MAGIC,
+	    version:		PROTOCOL_VERSION.into(),
+	    endian:		(Endian::native() as u8).into(),
+	    ptr_width:		(PTR_WIDTH as u8).into(),
+	    max_msg_size:	(super::MAX_PAYLOAD_SIZE as u32).into(),
+	    capabilities:	Capabilities::LOCAL,
+	    arch:		arch,
+	    .. Default::default()
+	}
+    }
+
+    pub fn arch(&self) -> String {
+	let len = self.arch.iter().position(|c| *c == 0).unwrap_or(self.arch.len());
+
+	String::from_utf8_lossy(&self.arch[..len]).into_owned()
+    }
+
+    pub fn version(&self) -> u16 {
+	self.version.as_native()
+    }
+
+    pub fn with_heartbeat(mut self, interval: Duration) -> Self {
+	self.heartbeat = (interval.as_millis().min(u32::MAX as u128) as u32).into();
+	self
+    }
+
+    fn heartbeat(&self) -> Option<Duration> {
+	match self.heartbeat.as_native() {
+	    0	=> None,
+	    ms	=> Some(Duration::from_millis(ms as u64)),
+	}
+    }
+
+    /// Checks whether the local side is compatible with `peer`
+    pub fn negotiate(&self, peer: &Self) -> Result<Session> {
+	if peer.magic != MAGIC {
+	    return Err(Error::BadMagic);
+	}
+
+	if peer.version() != self.version() {
+	    return Err(Error::VersionMismatch(self.version(), peer.version()));
+	}
+
+	let Some(endian) = Endian::try_from_u8(peer.endian.as_native()) else {
+	    return Err(Error::Incompatible(format!("unknown endianness {}",
+						   peer.endian.as_native())));
+	};
+
+	Ok(Session {
+	    max_msg_size:	(peer.max_msg_size.as_native() as usize).min(super::MAX_PAYLOAD_SIZE),
+	    capabilities:	self.capabilities.intersection(peer.capabilities),
+	    // both sides must use the same interval for probing and detecting
+	    // dead peers
+	    heartbeat:		self.heartbeat().zip(peer.heartbeat()).map(|(a, b)| a.max(b)),
+	    abi:		Abi::from_arch(&peer.arch()),
+	    ptr_width:		peer.ptr_width.as_native() as usize,
+	    endian:		endian,
+	})
+    }
+
+    /// Runs the client side of the handshake
+    pub fn connect<S: AsFd + std::io::Read + std::io::Write + Copy>(conn: S, heartbeat: &Heartbeat)
+								    -> Result<Session> {
+	let local = Self::local().with_heartbeat(heartbeat.interval);
+
+	let seq = Request::send_hello(conn, &local)?;
+
+	let peer = match Response::recv_to(conn) {
+	    Ok((Some(r_seq), Response::HelloAck(peer))) if r_seq == seq	=> peer,
+
+	    Ok((r_seq, resp))		=> {
+		warn!("unexpected response {resp:?}@{r_seq:?} for HELLO");
+		return Err(Error::BadResponse);
+	    }
+
+	    // servers without handshake support close the connection
+	    Err(Error::Io(e)) if e.raw_os_error() == Some(nix::libc::EPIPE)	=>
+		return Err(Error::NoHandshake),
+
+	    Err(e)			=> return Err(e),
+	};
+
+	let session = local.negotiate(&peer)?;
+
+	debug!("handshake with {} server completed: {session:?}", peer.arch());
+
+	Ok(session)
+    }
+
+    /// Runs the server side of the handshake
+    pub fn accept<S: AsFd + std::io::Read + std::io::Write + Copy>(conn: S, heartbeat: &Heartbeat)
+								   -> Result<Session> {
+	let mut buf = [core::mem::MaybeUninit::uninit(); core::mem::size_of::<Hello>() + 256];
+	let local = Self::local().with_heartbeat(heartbeat.interval);
+
+	let (_, op) = Request::recv(conn, &mut buf)?;
+
+	let (seq, peer) = match op {
+	    Request::Hello(seq, peer)	=> (seq, peer),
+
+	    op				=> {
+		warn!("client does not support the handshake; got {op:?}");
+		Response::send_err(conn, op.seq(), nix::Error::EPROTO)?;
+		return Err(Error::NoHandshake);
+	    }
+	};
+
+	// answer in every case so that the client can report the problem
+	Response::send_hello_ack(conn, seq, &local)?;
+
+	let session = local.negotiate(&peer)
+	    .inspect_err(|e| warn!("incompatible client: {e}"))?;
+
+	debug!("handshake with {} client completed: {session:?}", peer.arch());
+
+	Ok(session)
+    }
+}
+
+mod compile_test {
+    #![allow(dead_code)]
+    use super::*;
+
+    fn test_00() {
+	use core::mem::size_of;
+
+	const _: () = assert!(size_of::<Hello>() == 40);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_negotiate() {
+	let local = Hello::local();
+
+	assert!(local.negotiate(&Hello::local()).is_ok());
+
+	let mut peer = Hello::local();
+	peer.version = (PROTOCOL_VERSION + 1).into();
+	assert!(matches!(local.negotiate(&peer), Err(Error::VersionMismatch(..))));
+
+	let mut peer = Hello::local();
+	peer.magic = *b"XXXX";
+	assert!(matches!(local.negotiate(&peer), Err(Error::BadMagic)));
+
+	let mut peer = Hello::local();
+	peer.endian = 3.into();
+	assert!(matches!(local.negotiate(&peer), Err(Error::Incompatible(_))));
+
+	let mut peer = Hello::local();
+	peer.max_msg_size = 0x100.into();
+	assert_eq!(local.negotiate(&peer).unwrap().max_msg_size, 0x100);
+
+	// a different pointer width restricts only indirect ioctls
+	let mut peer = Hello::local();
+	peer.ptr_width = (12 - PTR_WIDTH as u8).into();
+	assert_eq!(local.negotiate(&peer).unwrap().ptr_width, 12 - PTR_WIDTH);
+	assert_eq!(local.negotiate(&Hello::local()).unwrap().ptr_width, PTR_WIDTH);
+    }
+
+    #[test]
+    fn test_heartbeat() {
+	let secs = Duration::from_secs;
+	let local = Hello::local().with_heartbeat(secs(10));
+
+	assert_eq!(local.negotiate(&Hello::local()).unwrap().heartbeat, None);
+	assert_eq!(local.negotiate(&Hello::local().with_heartbeat(secs(5))).unwrap().heartbeat,
+		   Some(secs(10)));
+	assert_eq!(local.negotiate(&Hello::local().with_heartbeat(secs(20))).unwrap().heartbeat,
+		   Some(secs(20)));
+    }
+
+    #[test]
+    fn test_ioctl_abi() {
+	assert_eq!(Abi::from_arch("x86_64"), Abi::from_arch("aarch64"));
+	assert_eq!(Abi::from_arch("x86"), Abi::from_arch("arm"));
+	assert_eq!(Abi::from_arch("mips"), Abi::from_arch("mips64"));
+	assert_ne!(Abi::from_arch("x86_64"), Abi::from_arch("powerpc64"));
+    }
+
+    #[test]
+    fn test_cross_abi() {
+	let local = Hello::local();
+	let mut peer = Hello::local();
+
+	let arch: &[u8] = match Abi::NATIVE {
+	    Abi::PowerPc	=> b"x86_64",
+	    _			=> b"powerpc64",
+	};
+
+	peer.arch = Default::default();
+	peer.arch[..arch.len()].copy_from_slice(arch);
+
+	// the arguments are translated by the codecs
+	let session = local.negotiate(&peer).unwrap();
+
+	assert_eq!(session.abi, Abi::from_arch(&peer.arch()));
+	assert_ne!(session.abi, Abi::NATIVE);
+	assert_eq!(session.endian, Endian::native());
+	assert_eq!(local.negotiate(&Hello::local()).unwrap().abi, Abi::NATIVE);
+
+	// e.g. a big endian powerpc64 client on a x86_64 server
+	let other = match Endian::native() {
+	    Endian::Little	=> Endian::Big,
+	    Endian::Big		=> Endian::Little,
+	};
+
+	peer.endian = (other as u8).into();
+
+	let session = local.negotiate(&peer).unwrap();
+
+	assert_eq!(session.abi, Abi::from_arch(&peer.arch()));
+	assert_eq!(session.endian, other);
+    }
+}
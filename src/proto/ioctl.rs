@@ -1,11 +1,15 @@
 #[path = "ioctl_serial.rs"]
 mod serial;
+#[path = "ioctl_registry.rs"]
+pub mod registry;
+#[path = "ioctl_indirect.rs"]
+pub mod indirect;
 
 use std::mem::MaybeUninit;
 
 use super::{ Error, Result, AsReprBytes };
 
-use ensc_ioctl_ffi::{ffi::ioctl, BadIoctl};
+use ensc_ioctl_ffi::ffi::ioctl;
 use ensc_ioctl_ffi::ffi as ioctl_ffi;
 pub use serial::*;
 use crate::proto::endian::*;
@@ -30,6 +34,10 @@ impl Source {
 pub union OsArg {
     termios:	core::mem::ManuallyDrop<ioctl_ffi::termios>,
     termios2:	core::mem::ManuallyDrop<ioctl_ffi::termios2>,
+    winsize:	core::mem::ManuallyDrop<ioctl_ffi::winsize>,
+    icount:	core::mem::ManuallyDrop<ioctl_ffi::serial_icounter_struct>,
+    rs485:	core::mem::ManuallyDrop<ioctl_ffi::serial_rs485>,
+    serial:	core::mem::ManuallyDrop<ioctl_ffi::serial_struct>,
     int:	nix::libc::c_int,
     uint:	nix::libc::c_uint,
     raw:	[u8; 64*1024],
@@ -64,6 +72,10 @@ pub enum Arg {
     TermIOs(TermIOs),
     Int(be32),
     UInt(be32),
+    WinSize(WinSize),
+    ICounter(ICounter),
+    Rs485(Rs485),
+    Serial(Serial),
 }
 
 fn uninit_arg<T: Sized>() -> (u64, Vec<u8>) {
@@ -123,6 +135,10 @@ impl Arg {
 	    Self::TermIOs(_)	=> 4,
 	    Self::Int(_)	=> 5,
 	    Self::UInt(_)	=> 6,
+	    Self::WinSize(_)	=> 7,
+	    Self::ICounter(_)	=> 8,
+	    Self::Rs485(_)	=> 9,
+	    Self::Serial(_)	=> 10,
 	}.into()
     }
 
@@ -141,6 +157,10 @@ impl Arg {
 	    4	=> Self::TermIOs(Self::try_as_object(buf)?),
 	    5	=> Self::Int(Self::try_as_object(buf)?),
 	    6	=> Self::UInt(Self::try_as_object(buf)?),
+	    7	=> Self::WinSize(Self::try_as_object(buf)?),
+	    8	=> Self::ICounter(Self::try_as_object(buf)?),
+	    9	=> Self::Rs485(Self::try_as_object(buf)?),
+	    10	=> Self::Serial(Self::try_as_object(buf)?),
 
 	    c	=> {
 		warn!("bad raw ioctl code {c}");
@@ -191,177 +211,104 @@ impl Arg {
     }
 
     pub fn cuse_response(self, cmd: ioctl) -> Result<Option<Vec<u8>>> {
-	let cmd = BadIoctl::new(cmd);
-
-	Ok(match cmd.get_native() {
-	    ioctl::TIOCGLCKTRMIOS |
-	    ioctl::TCGETS		=> match self {
-		Self::TermIOs(ios)	=> obj_to_cuse(ios.into_os()),
-		_			=> return Err(Error::BadIoctlParam),
-	    },
-	    ioctl::TCGETS2		=> match self {
-		Self::TermIOs(ios)	=> obj_to_cuse(ios.into_os2()),
-		_			=> return Err(Error::BadIoctlParam),
-	    },
-	    ioctl::TIOCSWINSZ		=> match self {
-		// todo: implemnt me!
-		_			=> return Err(Error::BadIoctlParam),
-	    },
+	if let Some(def) = registry::lookup(cmd) {
+	    return def.cuse_response(self);
+	}
 
-	    _ if !cmd.is_read()		=> None,
+	if indirect::lookup(cmd).is_some() {
+	    // the content of all 'out' regions
+	    return Ok(match self {
+		Self::Raw(data)	=> Some(data),
+		_		=> None,
+	    });
+	}
 
-	    _				=> match self {
-		Self::None |
-		Self::Arg(_) |
-		Self::RawArg(_)		=> None,
+	if !cmd.is_read() {
+	    return Ok(None);
+	}
 
-		Arg::TermIOs(ios)	=> {
-		    error!("can not handle termios {ios:?} here");
-		    return Err(Error::BadIoctlParam);
-		},
+	Ok(match self {
+	    Self::None |
+	    Self::Arg(_) |
+	    Self::RawArg(_)	=> None,
+	    Self::Raw(data)	=> Some(data),
+	    Self::Int(val)	=> obj_to_cuse(val.as_native()),
+	    Self::UInt(val)	=> obj_to_cuse(val.as_native()),
 
-		Arg::Raw(data)		=> Some(data),
-		Arg::Int(val)		=> obj_to_cuse(val.as_native()),
-		Arg::UInt(val)		=> obj_to_cuse(val.as_native()),
+	    arg			=> {
+		error!("can not handle {arg:?} for raw ioctl {cmd:?}");
+		return Err(Error::BadIoctlParam);
 	    }
 	})
     }
 
     pub fn encode(self, cmd: u32) -> Result<(u32, u64, Vec<u8>)>
     {
-	let cmd = BadIoctl::new(cmd.into());
-	let code = cmd.get_native().as_numeric();
-
-	// warn!("XXXXX {self:?} {cmd:?}");
-
-	let (arg, buf): (u64, Vec<u8>) = match cmd.get_native() {
-	    ioctl::TIOCSLCKTRMIOS |
-	    ioctl::TCSETSW |
-	    ioctl::TCSETSF |
-	    ioctl::TCSETS		=> match self {
-		Self::TermIOs(ios)	=> obj_to_arg(ios.into_os()),
-		_			=> return Err(Error::BadIoctlParam),
-	    }
-
-	    ioctl::TCSETSW2 |
-	    ioctl::TCSETSF2 |
-	    ioctl::TCSETS2		=> match self {
-		Self::TermIOs(ios)	=> obj_to_arg(ios.into_os2()),
-		_			=> return Err(Error::BadIoctlParam),
-	    }
-
-	    ioctl::TIOCGLCKTRMIOS |
-	    ioctl::TCGETS		=> match self {
-		Self::None		=> uninit_arg::<ioctl_ffi::termios>(),
-		_			=> return Err(Error::BadIoctlParam),
-	    }
+	let cmd = ioctl::from(cmd);
+	let code = cmd.as_numeric();
 
-	    ioctl::TIOCMGET |
-	    ioctl::TIOCINQ		=> match self {
-		Self::None		=> uninit_arg::<nix::libc::c_int>(),
-		_			=> return Err(Error::BadIoctlParam),
-	    }
-
-	    _ if !cmd.is_write()	=> match self {
-		Self::Arg(arg) |
-		Self::RawArg(arg)	=> (arg.into(), Vec::new()),
-		_			=> {
-		    error!("impossible internal state for no io");
-		    return Err(Error::BadIoctlParam);
-		}
-	    },
-
-	    _				=> match self {
-		Self::Raw(_data)	=> todo!(),
-		Self::Int(val)		=> obj_to_arg(val.as_native()),
-		Self::UInt(val)		=> obj_to_arg(val.as_native()),
-		_			=> {
-		    error!("impossible internal state");
-		    return Err(Error::BadIoctlParam);
-		}
-	    }
+	let (arg, buf) = match registry::lookup(cmd) {
+	    Some(def)	=> def.encode(self)?,
+	    None	=> self.encode_raw(cmd)?,
 	};
 
 	Ok((code, arg, buf))
     }
 
-    pub fn decode(cmd: u32, arg: u64, buf: &[u8], src: Source) -> Result<Self> {
-	let cmd = BadIoctl::new(cmd.into());
-	let size = cmd.get_size();
+    fn encode_raw(self, cmd: ioctl) -> Result<(u64, Vec<u8>)> {
+	Ok(match self {
+	    Self::Arg(arg) |
+	    Self::RawArg(arg)	if !cmd.is_io()		=> (arg.into(), Vec::new()),
 
-	#[allow(clippy::len_zero)]
-	if buf.len() > 0 && size < buf.len() {
-	    warn!("excess data in ioctl param ({size} < {})", buf.len());
-	}
-
-	// warn!("XXXXXX {cmd:?} {arg:x}");
-
-	Ok(match cmd.get_native() {
-	    ioctl::TIOCSLCKTRMIOS |
-	    ioctl::TCSETSW |
-	    ioctl::TCSETSF |
-	    ioctl::TCSETS		=> match src {
-		Source::Cuse		=> Self::TermIOs(TermIOs::try_from_os(buf)?),
-		Source::Device		=> Self::None,
-	    },
-
-	    ioctl::TCSETSW2 |
-	    ioctl::TCSETSF2 |
-	    ioctl::TCSETS2		=> match src {
-		Source::Cuse		=> Self::TermIOs(TermIOs::try_from_raw_os2(buf)?),
-		Source::Device		=> Self::None,
+	    Self::Raw(mut data)	if cmd.is_write()	=> {
+		data.resize(cmd.get_size().max(data.len()), 0);
+		(data.as_mut_ptr() as u64, data)
 	    },
 
-	    ioctl::TIOCSWINSZ		=> match src {
-		Source::Cuse		=> todo!(),
-		Source::Device		=> Self::None,
+	    Self::None		if cmd.is_read()	=> {
+		let mut data = vec![0; cmd.get_size()];
+		(data.as_mut_ptr() as u64, data)
 	    },
 
-	    ioctl::TIOCGWINSZ		=> match src {
-		Source::Cuse		=> Self::None,
-		Source::Device		=> todo!(),
-	    },
-
-	    ioctl::TIOCGLCKTRMIOS |
-	    ioctl::TCGETS		=> match src {
-		Source::Cuse		=> Self::None,
-		Source::Device		=> Self::TermIOs(TermIOs::try_from_os(buf)?),
-	    },
+	    Self::Int(val)	if cmd.is_write()	=> obj_to_arg(val.as_native()),
+	    Self::UInt(val)	if cmd.is_write()	=> obj_to_arg(val.as_native()),
 
-	    ioctl::TCGETS2		=> match src {
-		Source::Cuse		=> Self::None,
-		Source::Device		=> Self::TermIOs(TermIOs::try_from_raw_os2(buf)?),
-	    },
+	    arg						=> {
+		error!("impossible internal state {arg:?} for raw ioctl {cmd:?}");
+		return Err(Error::BadIoctlParam);
+	    }
+	})
+    }
 
-	    ioctl::TIOCSSOFTCAR |
-	    ioctl::TIOCMSET |
-	    ioctl::TIOCMBIC |
-	    ioctl::TIOCMBIS		=> match src {
-		Source::Cuse		=> Self::Int(Self::try_as_i32(buf)?),
-		Source::Device		=> Self::None,
-	    },
+    pub fn decode(cmd: u32, arg: u64, buf: &[u8], src: Source) -> Result<Self> {
+	let cmd = ioctl::from(cmd);
 
-	    ioctl::TIOCINQ		=> match src {
-		Source::Cuse		=> Self::None,
-		Source::Device		=> Self::Int(Self::try_as_i32(buf)?),
-	    },
+	if let Some(def) = registry::lookup(cmd) {
+	    return def.decode(arg, buf, src);
+	}
 
-	    ioctl::TCFLSH		=> match src {
-		Source::Cuse		=> Self::Arg(arg.into()),
-		Source::Device		=> Self::None,
-	    }
+	if indirect::lookup(cmd).is_some() {
+	    // the content of all 'in' regions; see `indirect::Memory` for the
+	    // device side
+	    return Ok(match src {
+		Source::Cuse	=> Self::Raw(buf.to_vec()),
+		Source::Device	=> Self::None,
+	    });
+	}
 
-	    _ if cmd.is_write()		=> match src {
-		Source::Cuse		=> Self::Raw(buf.to_vec()),
-		Source::Device		=> Self::None,
-	    },
+	let size = cmd.get_size();
 
-	    _ if cmd.is_read()		=> match src {
-		Source::Cuse		=> Self::None,
-		Source::Device		=> Self::Raw(buf.to_vec()),
-	    },
+	#[allow(clippy::len_zero)]
+	if buf.len() > 0 && size < buf.len() {
+	    warn!("excess data in ioctl param ({size} < {})", buf.len());
+	}
 
-	    _				=> Self::RawArg(arg.into()),
+	Ok(match src {
+	    Source::Cuse if cmd.is_write()	=> Self::Raw(buf.to_vec()),
+	    Source::Device if cmd.is_read()	=> Self::Raw(buf.to_vec()),
+	    _ if cmd.is_io()			=> Self::None,
+	    Source::Cuse			=> Self::RawArg(arg.into()),
+	    Source::Device			=> Self::None,
 	})
     }
 }
@@ -375,7 +322,11 @@ unsafe impl AsReprBytes for Arg {
 	    Arg::RawArg(arg)	=> arg.as_repr_bytes(),
 	    Arg::TermIOs(ios)	=> ios.as_repr_bytes(),
 	    Arg::Int(i)		=> i.as_repr_bytes(),
-	    Arg::UInt(u)	=> u.as_repr_bytes()
+	    Arg::UInt(u)	=> u.as_repr_bytes(),
+	    Arg::WinSize(ws)	=> ws.as_repr_bytes(),
+	    Arg::ICounter(cnt)	=> cnt.as_repr_bytes(),
+	    Arg::Rs485(rs485)	=> rs485.as_repr_bytes(),
+	    Arg::Serial(serial)	=> serial.as_repr_bytes(),
 	}
     }
 }
@@ -0,0 +1,681 @@
+//! Ioctls whose argument contains pointers to further buffers of the
+//! caller (e.g. `SPI_IOC_MESSAGE`, `I2C_RDWR`, `USBDEVFS_CONTROL`)
+//!
+//! A [`Desc`] declares where the pointers are located.  On the client,
+//! [`Desc::regions()`] is called for every stage of the FUSE ioctl retry
+//! with the data fetched so far and returns the memory regions of the
+//! caller which must be read by the kernel (`in`) or written back to
+//! it (`out`).  The concatenation of all `in` regions is transported as
+//! a single [`Arg::Raw`] object.  The server rebuilds the structures
+//! with pointers into local buffers ([`Memory`]) and returns the
+//! concatenation of all `out` regions.
+//!
+//! Structures are described in the native layout; both peers must use
+//! the same pointer width and the server refuses these ioctls else.
+//! Pointers which do not reference a buffer (`NULL` or zero length) are
+//! cleared on the server.
+
+use ensc_ioctl_ffi::abi::Abi;
+use ensc_ioctl_ffi::ffi::ioctl;
+
+use super::{Arg, Error, Result};
+use super::registry::Dir;
+
+/// Maximum number of regions; this is the `FUSE_IOCTL_MAX_IOV` limit of
+/// the kernel
+pub const MAX_REGIONS: usize = 256;
+
+pub const PTR: usize = core::mem::size_of::<* const u8>();
+
+/// Integer field of a structure in native endianness
+#[derive(Clone, Copy, Debug)]
+pub struct Field {
+    pub offset:	usize,
+    pub size:	usize,
+}
+
+impl Field {
+    pub const fn new(offset: usize, size: usize) -> Self {
+	Self {
+	    offset:	offset,
+	    size:	size,
+	}
+    }
+
+    pub fn get(self, buf: &[u8]) -> Option<u64> {
+	let b = buf.get(self.offset..self.offset + self.size)?;
+
+	Some(match self.size {
+	    1	=> b[0] as u64,
+	    2	=> u16::from_ne_bytes(b.try_into().ok()?) as u64,
+	    4	=> u32::from_ne_bytes(b.try_into().ok()?) as u64,
+	    8	=> u64::from_ne_bytes(b.try_into().ok()?),
+	    _	=> return None,
+	})
+    }
+
+    pub fn set(self, buf: &mut [u8], val: u64) -> Option<()> {
+	let b = buf.get_mut(self.offset..self.offset + self.size)?;
+
+	match self.size {
+	    1	=> b.copy_from_slice(&(val as u8).to_ne_bytes()),
+	    2	=> b.copy_from_slice(&(val as u16).to_ne_bytes()),
+	    4	=> b.copy_from_slice(&(val as u32).to_ne_bytes()),
+	    8	=> b.copy_from_slice(&val.to_ne_bytes()),
+	    _	=> return None,
+	}
+
+	Some(())
+    }
+}
+
+/// Direction of the data transfer of a buffer
+#[derive(Clone, Copy, Debug)]
+pub enum Access {
+    Fixed(Dir),
+    /// [`Dir::Read`] when one of the `mask` bits in `field` of the
+    /// referencing structure is set; [`Dir::Write`] else
+    ReadIf(Field, u64),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Length {
+    /// number of bytes
+    Bytes(Field),
+    /// number of elements of the target structure
+    Count(Field),
+}
+
+/// Pointer within a structure
+#[derive(Clone, Copy, Debug)]
+pub struct Pointer {
+    pub ptr:	Field,
+    pub len:	Length,
+    pub access:	Access,
+    /// layout of the referenced buffer when it contains pointers itself
+    pub target:	Option<&'static Struct>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Struct {
+    pub size:		usize,
+    pub pointers:	&'static [Pointer],
+}
+
+/// Number of elements at the address of the ioctl argument
+#[derive(Clone, Copy, Debug)]
+pub enum Count {
+    One,
+    /// given by the size bits of the ioctl number (`SPI_IOC_MESSAGE(n)`)
+    FromSize,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Match {
+    Cmd(u32),
+    /// all ioctls with this type and number; used with [`Count::FromSize`]
+    TypeNr(u8, u32),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Desc {
+    pub cmd:	Match,
+    pub count:	Count,
+    pub access:	Dir,
+    pub elem:	&'static Struct,
+}
+
+/// Memory of the caller which is accessed by an ioctl
+#[derive(Clone, Copy, Debug)]
+pub struct Region {
+    pub addr:	u64,
+    pub len:	usize,
+    pub read:	bool,
+    pub write:	bool,
+    /// index of the region and offset within it where the pointer to
+    /// this region is stored; `None` for the ioctl argument
+    pub parent:	Option<(usize, usize)>,
+    elem:	Option<&'static Struct>,
+}
+
+impl Region {
+    /// Region at the ioctl argument without embedded pointers
+    pub fn new(addr: u64, len: usize, dir: Dir) -> Self {
+	Self {
+	    addr:	addr,
+	    len:	len,
+	    read:	dir.is_read(),
+	    write:	dir.is_write(),
+	    parent:	None,
+	    elem:	None,
+	}
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Regions {
+    pub list:		Vec<Region>,
+    /// whether the content of all structures was available
+    pub complete:	bool,
+    /// pointers which do not reference a buffer; index of the region
+    /// and location within it
+    pub skipped:	Vec<(usize, Field)>,
+}
+
+impl Regions {
+    /// Total size of the regions which are read by the kernel
+    pub fn in_size(&self) -> usize {
+	self.list.iter().filter(|r| r.write).map(|r| r.len).sum()
+    }
+
+    /// Total size of the regions which are written by the kernel
+    pub fn out_size(&self) -> usize {
+	self.list.iter().filter(|r| r.read).map(|r| r.len).sum()
+    }
+
+    /// Total size of all regions; saturates instead of overflowing
+    pub fn total_size(&self) -> usize {
+	self.list.iter().fold(0, |sz, r| sz.saturating_add(r.len))
+    }
+}
+
+impl Desc {
+    pub fn matches(&self, cmd: ioctl) -> bool {
+	match self.cmd {
+	    Match::Cmd(c)		=> cmd.as_numeric() == c,
+	    Match::TypeNr(tp, nr)	=> {
+		cmd.get_type() == tp && cmd.get_nr() == nr &&
+		    cmd.is_write() && !cmd.is_read() &&
+		    cmd.get_size() > 0 && cmd.get_size().is_multiple_of(self.elem.size)
+	    },
+	}
+    }
+
+    fn count(&self, cmd: ioctl) -> usize {
+	match self.count {
+	    Count::One		=> 1,
+	    Count::FromSize	=> cmd.get_size() / self.elem.size,
+	}
+    }
+
+    /// Returns the regions of the caller which are accessed by the
+    /// ioctl.  `data` is the concatenation of the `in` regions which
+    /// were returned by a previous call; the returned list is extended
+    /// by the buffers of every structure whose content is in `data`.
+    pub fn regions(&self, cmd: ioctl, arg: u64, data: &[u8]) -> Result<Regions> {
+	let mut list = vec![Region {
+	    addr:	arg,
+	    len:	self.count(cmd) * self.elem.size,
+	    read:	self.access.is_read(),
+	    write:	self.access.is_write(),
+	    parent:	None,
+	    elem:	Some(self.elem),
+	}];
+
+	let mut complete = true;
+	let mut skipped = Vec::new();
+	let mut in_pos = 0;
+	let mut idx = 0;
+
+	while idx < list.len() {
+	    let region = list[idx];
+	    let pos = in_pos;
+
+	    if region.write {
+		in_pos += region.len;
+	    }
+
+	    idx += 1;
+
+	    let Some(elem) = region.elem else {
+		continue;
+	    };
+
+	    if !region.write {
+		error!("structure with pointers is not read by the kernel");
+		return Err(Error::BadIoctlParam);
+	    }
+
+	    let Some(buf) = data.get(pos..pos + region.len) else {
+		complete = false;
+		continue;
+	    };
+
+	    for (i, obj) in buf.chunks_exact(elem.size).enumerate() {
+		for p in elem.pointers {
+		    let Some(child) = p.region(obj, idx - 1, i * elem.size) else {
+			return Err(Error::BadIoctlParam);
+		    };
+
+		    if child.addr == 0 || child.len == 0 {
+			skipped.push((idx - 1, Field::new(i * elem.size + p.ptr.offset,
+							  p.ptr.size)));
+			continue;
+		    }
+
+		    if list.len() >= MAX_REGIONS {
+			warn!("too much buffers in ioctl {cmd:?}");
+			return Err(Error::BadLength);
+		    }
+
+		    list.push(child);
+		}
+	    }
+	}
+
+	Ok(Regions {
+	    list:	list,
+	    complete:	complete,
+	    skipped:	skipped,
+	})
+    }
+}
+
+impl Pointer {
+    fn region(&self, obj: &[u8], parent: usize, offset: usize) -> Option<Region> {
+	let len = match self.len {
+	    Length::Bytes(f)	=> f.get(obj)? as usize,
+	    Length::Count(f)	=> (f.get(obj)? as usize).checked_mul(self.target?.size)?,
+	};
+
+	let dir = match self.access {
+	    Access::Fixed(dir)		=> dir,
+	    Access::ReadIf(f, mask)	=> match f.get(obj)? & mask {
+		0	=> Dir::Write,
+		_	=> Dir::Read,
+	    },
+	};
+
+	Some(Region {
+	    addr:	self.ptr.get(obj)?,
+	    len:	len,
+	    read:	dir.is_read(),
+	    write:	dir.is_write(),
+	    parent:	Some((parent, offset + self.ptr.offset)),
+	    elem:	self.target,
+	})
+    }
+}
+
+/// Local copy of the caller's memory on the server
+pub struct Memory {
+    regions:	Vec<Region>,
+    bufs:	Vec<Vec<u8>>,
+    ptrs:	Vec<Field>,
+    /// cleared pointers and their original value
+    skipped:	Vec<(usize, Field, u64)>,
+}
+
+impl Memory {
+    /// Allocates the buffers for the `in` data `data` of a client and
+    /// rewrites the pointers in them.  The buffers must not exceed
+    /// `max_size` bytes in total.
+    pub fn new(desc: &Desc, cmd: ioctl, data: &[u8], max_size: usize) -> Result<Self> {
+	let regions = desc.regions(cmd, 0, data)?;
+
+	if !regions.complete || regions.in_size() != data.len() {
+	    warn!("incomplete data for ioctl {cmd:?}");
+	    return Err(Error::BadLength);
+	}
+
+	if regions.total_size() > max_size {
+	    warn!("buffers of ioctl {cmd:?} exceed {max_size} bytes");
+	    return Err(Error::BadLength);
+	}
+
+	let mut bufs = Vec::with_capacity(regions.list.len());
+	let mut ptrs = Vec::with_capacity(regions.list.len());
+	let mut pos = 0;
+
+	for r in &regions.list {
+	    let buf = match r.write {
+		true	=> {
+		    pos += r.len;
+		    data[pos - r.len..pos].to_vec()
+		},
+		false	=> vec![0; r.len],
+	    };
+
+	    bufs.push(buf);
+	    ptrs.push(Field::new(r.parent.map(|(_, o)| o).unwrap_or(0), PTR));
+	}
+
+	// the values are addresses of the caller which must not be
+	// dereferenced by the kernel here
+	let skipped = regions.skipped.into_iter()
+	    .map(|(idx, f)| {
+		// offsets were verified when building the regions
+		let val = f.get(&bufs[idx]).unwrap();
+
+		f.set(&mut bufs[idx], 0).unwrap();
+		(idx, f, val)
+	    })
+	    .collect();
+
+	let mut res = Self {
+	    regions:	regions.list,
+	    bufs:	bufs,
+	    ptrs:	ptrs,
+	    skipped:	skipped,
+	};
+
+	res.relocate(|_, buf| buf.as_ptr() as u64);
+
+	Ok(res)
+    }
+
+    fn relocate<F: Fn(&Region, &[u8]) -> u64>(&mut self, addr: F) {
+	for (idx, r) in self.regions.iter().enumerate() {
+	    let Some((parent, _)) = r.parent else {
+		continue;
+	    };
+
+	    let val = addr(r, &self.bufs[idx]);
+
+	    // offsets were verified when building the regions
+	    self.ptrs[idx].set(&mut self.bufs[parent], val).unwrap();
+	}
+    }
+
+    /// Address of the ioctl argument
+    pub fn arg(&mut self) -> u64 {
+	self.bufs[0].as_mut_ptr() as u64
+    }
+
+    /// Restores the addresses of the caller and returns the content of
+    /// the `out` regions
+    pub fn into_output(mut self) -> Arg {
+	self.relocate(|r, _| r.addr);
+
+	for (idx, f, val) in &self.skipped {
+	    f.set(&mut self.bufs[*idx], *val).unwrap();
+	}
+
+	Arg::Raw(self.regions.iter()
+		 .zip(self.bufs)
+		 .filter(|(r, _)| r.read)
+		 .flat_map(|(_, buf)| buf)
+		 .collect())
+    }
+}
+
+/// struct spi_ioc_transfer
+static SPI_IOC_TRANSFER: Struct = Struct {
+    size:	32,
+    pointers:	&[
+	Pointer {
+	    ptr:	Field::new(0, 8),
+	    len:	Length::Bytes(Field::new(16, 4)),
+	    access:	Access::Fixed(Dir::Write),
+	    target:	None,
+	},
+	Pointer {
+	    ptr:	Field::new(8, 8),
+	    len:	Length::Bytes(Field::new(16, 4)),
+	    access:	Access::Fixed(Dir::Read),
+	    target:	None,
+	},
+    ],
+};
+
+const I2C_M_RD: u64 = 0x0001;
+
+/// struct i2c_msg
+static I2C_MSG: Struct = Struct {
+    size:	8 + PTR,
+    pointers:	&[
+	Pointer {
+	    ptr:	Field::new(8, PTR),
+	    len:	Length::Bytes(Field::new(4, 2)),
+	    access:	Access::ReadIf(Field::new(2, 2), I2C_M_RD),
+	    target:	None,
+	},
+    ],
+};
+
+/// struct i2c_rdwr_ioctl_data
+static I2C_RDWR_DATA: Struct = Struct {
+    size:	2 * PTR,
+    pointers:	&[
+	Pointer {
+	    ptr:	Field::new(0, PTR),
+	    len:	Length::Count(Field::new(PTR, 4)),
+	    access:	Access::Fixed(Dir::Write),
+	    target:	Some(&I2C_MSG),
+	},
+    ],
+};
+
+const USB_DIR_IN: u64 = 0x80;
+
+/// struct usbdevfs_ctrltransfer
+static USBDEVFS_CTRLTRANSFER: Struct = Struct {
+    size:	12_usize.next_multiple_of(PTR) + PTR,
+    pointers:	&[
+	Pointer {
+	    ptr:	Field::new(12_usize.next_multiple_of(PTR), PTR),
+	    len:	Length::Bytes(Field::new(6, 2)),
+	    access:	Access::ReadIf(Field::new(0, 1), USB_DIR_IN),
+	    target:	None,
+	},
+    ],
+};
+
+static DEFAULT: &[Desc] = &[
+    // SPI_IOC_MESSAGE(n)
+    Desc {
+	cmd:	Match::TypeNr(b'k', 0),
+	count:	Count::FromSize,
+	access:	Dir::Write,
+	elem:	&SPI_IOC_TRANSFER,
+    },
+
+    // I2C_RDWR
+    Desc {
+	cmd:	Match::Cmd(0x0707),
+	count:	Count::One,
+	access:	Dir::Write,
+	elem:	&I2C_RDWR_DATA,
+    },
+
+    // USBDEVFS_CONTROL
+    Desc {
+	cmd:	Match::Cmd(Abi::NATIVE.layout().ioc(true, true, b'U', 0,
+						    12_usize.next_multiple_of(PTR) + PTR)),
+	count:	Count::One,
+	access:	Dir::Write,
+	elem:	&USBDEVFS_CTRLTRANSFER,
+    },
+];
+
+pub fn lookup(cmd: ioctl) -> Option<&'static Desc> {
+    DEFAULT.iter().find(|d| d.matches(cmd))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn spi_transfer(tx: Option<&[u8]>, rx: Option<&mut [u8]>, len: u32) -> [u8; 32] {
+	let mut res = [0; 32];
+
+	res[0..8].copy_from_slice(&(tx.map(|b| b.as_ptr() as u64).unwrap_or(0)).to_ne_bytes());
+	res[8..16].copy_from_slice(&(rx.map(|b| b.as_mut_ptr() as u64).unwrap_or(0)).to_ne_bytes());
+	res[16..20].copy_from_slice(&len.to_ne_bytes());
+
+	res
+    }
+
+    /// Emulates the kernel which copies the regions from the memory of
+    /// the caller
+    fn fetch(regions: &Regions) -> Vec<u8> {
+	regions.list.iter()
+	    .filter(|r| r.write)
+	    .flat_map(|r| unsafe {
+		core::slice::from_raw_parts(r.addr as * const u8, r.len)
+	    })
+	    .copied()
+	    .collect()
+    }
+
+    #[test]
+    fn test_spi() {
+	let tx = [1u8, 2, 3, 4];
+	let mut rx = [0u8; 4];
+	let xfer = [ spi_transfer(Some(&tx), None, 4),
+		     spi_transfer(None, Some(&mut rx), 4) ];
+
+	// _IOW('k', 0, char[64])
+	let cmd = ioctl::from(Abi::NATIVE.layout().ioc(false, true, b'k', 0, 64));
+	let arg = xfer.as_ptr() as u64;
+	let desc = lookup(cmd).unwrap();
+
+	// first stage: the array of transfers
+	let regions = desc.regions(cmd, arg, &[]).unwrap();
+	assert!(!regions.complete);
+	assert_eq!(regions.list.len(), 1);
+	assert_eq!(regions.in_size(), 64);
+
+	// second stage: the buffers
+	let data = fetch(&regions);
+	let regions = desc.regions(cmd, arg, &data).unwrap();
+	assert!(regions.complete);
+	assert_eq!(regions.list.len(), 3);
+	assert_eq!(regions.in_size(), 68);
+	assert_eq!(regions.out_size(), 4);
+
+	// the final stage returns the same regions
+	let data = fetch(&regions);
+	let addrs = |r: &Regions| r.list.iter().map(|r| (r.addr, r.len)).collect::<Vec<_>>();
+	assert_eq!(addrs(&desc.regions(cmd, arg, &data).unwrap()), addrs(&regions));
+
+	// server side
+	assert!(Memory::new(desc, cmd, &data, 71).is_err());
+
+	let mut mem = Memory::new(desc, cmd, &data, 72).unwrap();
+	let xfer_os = mem.arg() as * const [u8; 32];
+	let (tx_os, rx_os) = unsafe {
+	    let x = &*xfer_os.add(0);
+	    let y = &*xfer_os.add(1);
+
+	    (u64::from_ne_bytes(x[0..8].try_into().unwrap()) as * const u8,
+	     u64::from_ne_bytes(y[8..16].try_into().unwrap()) as * mut u8)
+	};
+
+	assert_ne!(tx_os, tx.as_ptr());
+	assert_eq!(unsafe { core::slice::from_raw_parts(tx_os, 4) }, tx);
+
+	// emulate a loopback device
+	unsafe { rx_os.copy_from_nonoverlapping(tx_os, 4) };
+
+	assert!(matches!(mem.into_output(), Arg::Raw(out) if out == tx));
+    }
+
+    #[test]
+    fn test_i2c() {
+	let wr = [0x10u8];
+	let rd = [0u8; 2];
+	let mut msgs = [0u8; 2 * (8 + PTR)];
+
+	for (msg, (flags, buf)) in msgs.chunks_exact_mut(8 + PTR)
+	    .zip([(0u16, &wr[..]), (I2C_M_RD as u16, &rd[..])])
+	{
+	    msg[2..4].copy_from_slice(&flags.to_ne_bytes());
+	    msg[4..6].copy_from_slice(&(buf.len() as u16).to_ne_bytes());
+	    msg[8..].copy_from_slice(&(buf.as_ptr() as usize).to_ne_bytes());
+	}
+
+	let mut rdwr = [0u8; 2 * PTR];
+
+	rdwr[..PTR].copy_from_slice(&(msgs.as_ptr() as usize).to_ne_bytes());
+	rdwr[PTR..PTR + 4].copy_from_slice(&2u32.to_ne_bytes());
+
+	let cmd = ioctl::from(0x0707);
+	let arg = rdwr.as_ptr() as u64;
+	let desc = lookup(cmd).unwrap();
+
+	let mut regions = desc.regions(cmd, arg, &[]).unwrap();
+	let mut stages = 1;
+
+	while !regions.complete {
+	    regions = desc.regions(cmd, arg, &fetch(&regions)).unwrap();
+	    stages += 1;
+	}
+
+	assert_eq!(stages, 3);
+	assert_eq!(regions.list.len(), 4);
+	assert_eq!(regions.in_size(), rdwr.len() + msgs.len() + 1);
+	assert_eq!(regions.out_size(), 2);
+
+	let mem = Memory::new(desc, cmd, &fetch(&regions), usize::MAX).unwrap();
+
+	// the caller's addresses are restored in the returned structures
+	assert_eq!(mem.bufs[1].len(), msgs.len());
+	assert_ne!(mem.bufs[1], msgs);
+	assert!(matches!(mem.into_output(), Arg::Raw(out) if out == [0, 0]));
+    }
+
+    #[test]
+    fn test_bad() {
+	let cmd = ioctl::from(0x0707);
+	let desc = lookup(cmd).unwrap();
+
+	let mut rdwr = vec![0u8; 2 * PTR];
+	rdwr[..PTR].copy_from_slice(&0x1000usize.to_ne_bytes());
+	rdwr[PTR..PTR + 4].copy_from_slice(&(MAX_REGIONS as u32).to_ne_bytes());
+
+	assert!(desc.regions(cmd, 0, &rdwr).is_ok());
+
+	let mut data = rdwr.clone();
+
+	for _ in 0..MAX_REGIONS {
+	    let mut msg = [0u8; 8 + PTR];
+
+	    msg[4..6].copy_from_slice(&1u16.to_ne_bytes());
+	    msg[8..].copy_from_slice(&0x2000usize.to_ne_bytes());
+	    data.extend(msg);
+	}
+
+	// too much buffers
+	assert!(desc.regions(cmd, 0, &data).is_err());
+
+	// data does not match the regions
+	assert!(Memory::new(desc, cmd, &rdwr[..PTR], usize::MAX).is_err());
+
+	// huge buffers which are written by the kernel
+	let mut xfer = spi_transfer(None, None, u32::MAX);
+	xfer[8..16].copy_from_slice(&0x1000u64.to_ne_bytes());
+
+	let cmd = ioctl::from(Abi::NATIVE.layout().ioc(false, true, b'k', 0, 32));
+	let desc = lookup(cmd).unwrap();
+
+	assert!(Memory::new(desc, cmd, &xfer, crate::proto::MAX_PAYLOAD_SIZE).is_err());
+    }
+
+    #[test]
+    fn test_skipped() {
+	let tx = [1u8, 2];
+	// the first transfer has no length, the second one no rx buffer
+	let mut xfer = [ spi_transfer(Some(&tx), None, 0),
+			 spi_transfer(Some(&tx), None, 2) ];
+
+	xfer[0][8..16].copy_from_slice(&0x1000u64.to_ne_bytes());
+
+	let cmd = ioctl::from(Abi::NATIVE.layout().ioc(false, true, b'k', 0, 64));
+	let desc = lookup(cmd).unwrap();
+	let regions = desc.regions(cmd, 0, xfer.as_flattened()).unwrap();
+
+	assert_eq!(regions.list.len(), 2);
+	assert_eq!(regions.skipped.len(), 3);
+
+	let data = [xfer.as_flattened(), &tx].concat();
+	let mut mem = Memory::new(desc, cmd, &data, usize::MAX).unwrap();
+	let xfer_os = unsafe { &*(mem.arg() as * const [[u8; 32]; 2]) };
+
+	assert_eq!(xfer_os[0][0..16], [0; 16]);
+	assert_ne!(xfer_os[1][0..8], [0; 8]);
+	assert_eq!(xfer_os[1][8..16], [0; 8]);
+
+	assert!(matches!(mem.into_output(), Arg::Raw(out) if out.is_empty()));
+    }
+}
@@ -0,0 +1,525 @@
+//! Table of the ioctls which are transported with typed arguments
+//!
+//! Every entry declares the direction of the ioctl and a [`Codec`] which
+//! converts between the object used by the OS and its wire format.
+//! `Arg::encode()`, `Arg::decode()` and `Arg::cuse_response()` are driven
+//! by this table; ioctls without an entry are transported as raw data.
+//! Programs can add their own ioctls with [`register()`].
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::LazyLock;
+
+use parking_lot::RwLock;
+
+use ensc_ioctl_ffi::ffi::ioctl;
+use ensc_ioctl_ffi::ffi as ioctl_ffi;
+use ensc_ioctl_ffi::abi::{Abi, Flags};
+
+use crate::proto::endian::*;
+use super::{Arg, Source, Error, Result};
+use super::{TermIOs, WinSize, ICounter, Rs485, Serial};
+use super::{uninit_arg, obj_to_arg};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dir {
+    /// the argument is ignored
+    None,
+    /// integer argument which is passed by value
+    Arg,
+    /// the kernel returns an object
+    Read,
+    /// the kernel reads an object
+    Write,
+    /// the kernel reads an object and returns a modified one
+    ReadWrite,
+}
+
+impl Dir {
+    pub fn is_read(self) -> bool {
+	matches!(self, Self::Read | Self::ReadWrite)
+    }
+
+    pub fn is_write(self) -> bool {
+	matches!(self, Self::Write | Self::ReadWrite)
+    }
+}
+
+/// Conversion between the OS object of an ioctl and its wire format
+pub trait Codec: Send + Sync + std::fmt::Debug {
+    /// Size of the OS object
+    fn size(&self) -> usize;
+
+    /// Converts the OS object in `buf` into the wire format
+    fn to_wire(&self, buf: &[u8]) -> Result<Arg>;
+
+    /// Converts the wire format into an OS object; returns its address
+    /// and the buffer which holds it
+    fn to_os(&self, arg: Arg) -> Result<(u64, Vec<u8>)>;
+
+    /// Allocates an OS object which is filled by the kernel
+    fn alloc(&self) -> (u64, Vec<u8>);
+
+    /// Converts an integer argument which is passed by value into the
+    /// wire format
+    fn arg_to_wire(&self, arg: u64) -> u64 {
+	arg
+    }
+
+    /// Converts an integer argument from the wire format
+    fn arg_to_os(&self, arg: u64) -> u64 {
+	arg
+    }
+}
+
+/// Wire type of the OS type `O`
+pub trait WireType<O>: Sized {
+    fn try_from_os(buf: &[u8]) -> Result<Self>;
+    fn into_os(self) -> O;
+    fn into_arg(self) -> Arg;
+    fn try_from_arg(arg: Arg) -> Option<Self>;
+}
+
+/// [`Codec`] which converts between the OS type `O` and the wire type
+/// `W`
+pub struct Typed<W, O>(PhantomData<fn() -> (W, O)>);
+
+impl <W, O> Typed<W, O> {
+    pub const fn new() -> Self {
+	Self(PhantomData)
+    }
+}
+
+impl <W, O> Default for Typed<W, O> {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+impl <W, O> std::fmt::Debug for Typed<W, O> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	write!(f, "Typed<{}, {}>", core::any::type_name::<W>(), core::any::type_name::<O>())
+    }
+}
+
+impl <W: WireType<O>, O> Codec for Typed<W, O> {
+    fn size(&self) -> usize {
+	core::mem::size_of::<O>()
+    }
+
+    fn to_wire(&self, buf: &[u8]) -> Result<Arg> {
+	W::try_from_os(buf).map(W::into_arg)
+    }
+
+    fn to_os(&self, arg: Arg) -> Result<(u64, Vec<u8>)> {
+	match W::try_from_arg(arg) {
+	    Some(v)	=> Ok(obj_to_arg(v.into_os())),
+	    None	=> Err(Error::BadIoctlParam),
+	}
+    }
+
+    fn alloc(&self) -> (u64, Vec<u8>) {
+	uninit_arg::<O>()
+    }
+}
+
+macro_rules! declare_wire {
+    ($wire:ty, $os:ty, $variant:ident, $from:expr, $into:expr) => {
+	impl WireType<$os> for $wire {
+	    fn try_from_os(buf: &[u8]) -> Result<Self> {
+		$from(buf)
+	    }
+
+	    fn into_os(self) -> $os {
+		$into(self)
+	    }
+
+	    fn into_arg(self) -> Arg {
+		Arg::$variant(self)
+	    }
+
+	    fn try_from_arg(arg: Arg) -> Option<Self> {
+		match arg {
+		    Arg::$variant(v)	=> Some(v),
+		    _			=> None,
+		}
+	    }
+	}
+    };
+}
+
+declare_wire!(TermIOs, ioctl_ffi::termios,  TermIOs, TermIOs::try_from_os,      TermIOs::into_os);
+declare_wire!(TermIOs, ioctl_ffi::termios2, TermIOs, TermIOs::try_from_raw_os2, TermIOs::into_os2);
+declare_wire!(TermIOs, ioctl_ffi::termio,   TermIOs, TermIOs::try_from_os_termio, TermIOs::into_os_termio);
+declare_wire!(WinSize, ioctl_ffi::winsize,  WinSize, WinSize::try_from_os,      WinSize::into_os);
+declare_wire!(ICounter, ioctl_ffi::serial_icounter_struct, ICounter,
+	      ICounter::try_from_os, ICounter::into_os);
+declare_wire!(Rs485,   ioctl_ffi::serial_rs485,  Rs485,  Rs485::try_from_os,  Rs485::into_os);
+declare_wire!(Serial,  ioctl_ffi::serial_struct, Serial, Serial::try_from_os, Serial::into_os);
+declare_wire!(be32,    nix::libc::c_int,     Int,    Arg::try_as_i32,
+	      |v: be32| v.as_native() as nix::libc::c_int);
+
+/// [`Codec`] of the `TIOCM_*` bits; they differ between the
+/// architecture families
+#[derive(Debug)]
+pub struct ModemLines;
+
+impl Codec for ModemLines {
+    fn size(&self) -> usize {
+	core::mem::size_of::<nix::libc::c_int>()
+    }
+
+    fn to_wire(&self, buf: &[u8]) -> Result<Arg> {
+	let v = Arg::try_as_i32(buf)?.as_native();
+
+	Ok(Arg::Int(Abi::NATIVE.flags_to_wire(Flags::Modem, v).into()))
+    }
+
+    fn to_os(&self, arg: Arg) -> Result<(u64, Vec<u8>)> {
+	match arg {
+	    Arg::Int(v)	=> {
+		let v = Abi::NATIVE.flags_from_wire(Flags::Modem, v.as_native());
+
+		Ok(obj_to_arg(v as nix::libc::c_int))
+	    }
+	    _		=> Err(Error::BadIoctlParam),
+	}
+    }
+
+    fn alloc(&self) -> (u64, Vec<u8>) {
+	uninit_arg::<nix::libc::c_int>()
+    }
+
+    fn arg_to_wire(&self, arg: u64) -> u64 {
+	Abi::NATIVE.flags_to_wire(Flags::Modem, arg as u32).into()
+    }
+
+    fn arg_to_os(&self, arg: u64) -> u64 {
+	Abi::NATIVE.flags_from_wire(Flags::Modem, arg as u32).into()
+    }
+}
+
+pub const TERMIOS:	&dyn Codec = &Typed::<TermIOs, ioctl_ffi::termios>::new();
+pub const TERMIOS2:	&dyn Codec = &Typed::<TermIOs, ioctl_ffi::termios2>::new();
+pub const TERMIO:	&dyn Codec = &Typed::<TermIOs, ioctl_ffi::termio>::new();
+pub const WINSIZE:	&dyn Codec = &Typed::<WinSize, ioctl_ffi::winsize>::new();
+pub const ICOUNTER:	&dyn Codec = &Typed::<ICounter, ioctl_ffi::serial_icounter_struct>::new();
+pub const RS485:	&dyn Codec = &Typed::<Rs485, ioctl_ffi::serial_rs485>::new();
+pub const SERIAL:	&dyn Codec = &Typed::<Serial, ioctl_ffi::serial_struct>::new();
+pub const INT:		&dyn Codec = &Typed::<be32, nix::libc::c_int>::new();
+pub const MODEM:	&dyn Codec = &ModemLines;
+
+/// Declaration of an ioctl
+#[derive(Clone, Copy, Debug)]
+pub struct Def {
+    pub cmd:		ioctl,
+    pub dir:		Dir,
+    pub codec:		Option<&'static dyn Codec>,
+    /// whether the server executes the ioctl
+    pub allowed:	bool,
+}
+
+impl Def {
+    const fn new(cmd: ioctl, dir: Dir, codec: Option<&'static dyn Codec>) -> Self {
+	Self {
+	    cmd:	cmd,
+	    dir:	dir,
+	    codec:	codec,
+	    allowed:	true,
+	}
+    }
+
+    pub const fn none(cmd: ioctl) -> Self {
+	Self::new(cmd, Dir::None, None)
+    }
+
+    pub const fn arg(cmd: ioctl) -> Self {
+	Self::new(cmd, Dir::Arg, None)
+    }
+
+    /// Integer argument which is translated by `codec`
+    pub const fn arg_with(cmd: ioctl, codec: &'static dyn Codec) -> Self {
+	Self::new(cmd, Dir::Arg, Some(codec))
+    }
+
+    pub const fn read(cmd: ioctl, codec: &'static dyn Codec) -> Self {
+	Self::new(cmd, Dir::Read, Some(codec))
+    }
+
+    pub const fn write(cmd: ioctl, codec: &'static dyn Codec) -> Self {
+	Self::new(cmd, Dir::Write, Some(codec))
+    }
+
+    pub const fn read_write(cmd: ioctl, codec: &'static dyn Codec) -> Self {
+	Self::new(cmd, Dir::ReadWrite, Some(codec))
+    }
+
+    /// Marks the ioctl as not executed by the server
+    pub const fn denied(self) -> Self {
+	Self {
+	    allowed:	false,
+	    .. self
+	}
+    }
+
+    /// Size of the object which is transferred to/from the kernel
+    pub fn size(&self) -> usize {
+	self.codec.map(|c| c.size()).unwrap_or(0)
+    }
+
+    fn codec(&self) -> Result<&'static dyn Codec> {
+	self.codec.ok_or_else(|| {
+	    error!("no codec for {:?}", self.cmd);
+	    Error::BadIoctlParam
+	})
+    }
+
+    pub fn decode(&self, arg: u64, buf: &[u8], src: Source) -> Result<Arg> {
+	if self.dir.is_read() || self.dir.is_write() {
+	    let size = self.size();
+
+	    if size < buf.len() {
+		warn!("excess data in ioctl param ({size} < {})", buf.len());
+	    }
+	}
+
+	Ok(match (self.dir, src) {
+	    (Dir::Arg, Source::Cuse)		=> match self.codec {
+		Some(codec)	=> Arg::Arg(codec.arg_to_wire(arg).into()),
+		None		=> Arg::Arg(arg.into()),
+	    },
+
+	    (Dir::None, _) |
+	    (Dir::Arg, Source::Device) |
+	    (Dir::Read, Source::Cuse) |
+	    (Dir::Write, Source::Device)	=> Arg::None,
+
+	    (Dir::Read, Source::Device) |
+	    (Dir::Write, Source::Cuse) |
+	    (Dir::ReadWrite, _)			=> self.codec()?.to_wire(buf)?,
+	})
+    }
+
+    pub fn encode(&self, arg: Arg) -> Result<(u64, Vec<u8>)> {
+	match (self.dir, arg) {
+	    (Dir::None, _)		=> Ok((0, Vec::new())),
+	    (Dir::Arg, Arg::Arg(v))	=> match self.codec {
+		Some(codec)	=> Ok((codec.arg_to_os(v.into()), Vec::new())),
+		None		=> Ok((v.into(), Vec::new())),
+	    },
+	    (Dir::Read, Arg::None)	=> Ok(self.codec()?.alloc()),
+	    (Dir::Write, arg) |
+	    (Dir::ReadWrite, arg)	=> self.codec()?.to_os(arg),
+
+	    (_, arg)			=> {
+		error!("bad argument {arg:?} for {:?}", self.cmd);
+		Err(Error::BadIoctlParam)
+	    }
+	}
+    }
+
+    pub fn cuse_response(&self, arg: Arg) -> Result<Option<Vec<u8>>> {
+	match self.dir.is_read() {
+	    true	=> self.codec()?.to_os(arg).map(|(_, buf)| Some(buf)),
+	    false	=> Ok(None),
+	}
+    }
+}
+
+static DEFAULT: &[Def] = &[
+    Def::read(ioctl::TCGETS, TERMIOS),
+    Def::write(ioctl::TCSETS, TERMIOS),
+    Def::write(ioctl::TCSETSW, TERMIOS),
+    Def::write(ioctl::TCSETSF, TERMIOS),
+
+    Def::read(ioctl::TCGETS2, TERMIOS2),
+    Def::write(ioctl::TCSETS2, TERMIOS2),
+    Def::write(ioctl::TCSETSW2, TERMIOS2),
+    Def::write(ioctl::TCSETSF2, TERMIOS2),
+
+    // SysV termio; the kernel keeps the upper 16 bits of the flags
+    Def::read(ioctl::TCGETA, TERMIO),
+    Def::write(ioctl::TCSETA, TERMIO),
+    Def::write(ioctl::TCSETAW, TERMIO),
+    Def::write(ioctl::TCSETAF, TERMIO),
+
+    Def::read(ioctl::TIOCGLCKTRMIOS, TERMIOS),
+    Def::write(ioctl::TIOCSLCKTRMIOS, TERMIOS),
+
+    Def::read(ioctl::TIOCGWINSZ, WINSIZE),
+    Def::write(ioctl::TIOCSWINSZ, WINSIZE),
+
+    Def::write(ioctl::TIOCSSOFTCAR, INT),
+
+    Def::read(ioctl::TIOCMGET, MODEM),
+    Def::write(ioctl::TIOCMBIS, MODEM),
+    Def::write(ioctl::TIOCMBIC, MODEM),
+    Def::write(ioctl::TIOCMSET, MODEM),
+
+    Def::read(ioctl::TIOCINQ, INT),
+    Def::read(ioctl::TIOCOUTQ, INT),
+
+    // handled by the VFS of the client which passes the changed file
+    // flags with the following requests; the device on the server
+    // must stay non-blocking
+    Def::write(ioctl::FIONBIO, INT).denied(),
+
+    Def::arg_with(ioctl::TIOCMIWAIT, MODEM),
+    Def::read(ioctl::TIOCGICOUNT, ICOUNTER),
+
+    Def::read(ioctl::TIOCGSERIAL, SERIAL),
+    Def::write(ioctl::TIOCSSERIAL, SERIAL),
+
+    Def::read(ioctl::TIOCSERGETLSR, INT),
+
+    Def::read(ioctl::TIOCGRS485, RS485),
+    // the kernel returns the applied settings
+    Def::read_write(ioctl::TIOCSRS485, RS485),
+
+    Def::none(ioctl::TIOCEXCL),
+    Def::none(ioctl::TIOCNXCL),
+    Def::read(ioctl::TIOCGEXCL, INT),
+
+    Def::write(ioctl::TIOCSETD, INT),
+    Def::read(ioctl::TIOCGETD, INT),
+
+    Def::arg(ioctl::TCFLSH),
+    Def::arg(ioctl::TCXONC),
+    Def::arg(ioctl::TCSBRK),
+    Def::arg(ioctl::TCSBRKP),
+    Def::arg(ioctl::TIOCSBRK),
+    Def::arg(ioctl::TIOCCBRK),
+];
+
+static REGISTRY: LazyLock<RwLock<HashMap<u32, Def>>> = LazyLock::new(|| {
+    RwLock::new(DEFAULT.iter()
+		.map(|def| (def.cmd.as_numeric(), *def))
+		.collect())
+});
+
+/// Adds an ioctl to the table or replaces an existing entry.  It must be
+/// registered on both the client and the server side.
+pub fn register(def: Def) {
+    REGISTRY.write().insert(def.cmd.as_numeric(), def);
+}
+
+pub fn lookup(cmd: ioctl) -> Option<Def> {
+    REGISTRY.read().get(&cmd.as_numeric()).copied()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_winsize() {
+	let os = ioctl_ffi::winsize {
+	    ws_row:	24,
+	    ws_col:	80,
+	    ws_xpixel:	0,
+	    ws_ypixel:	0,
+	};
+
+	let (_, buf) = obj_to_arg(os);
+	let cmd = ioctl::TIOCSWINSZ.as_numeric();
+
+	let arg = Arg::decode(cmd, 0, &buf, Source::Cuse).unwrap();
+	assert!(matches!(arg, Arg::WinSize(_)));
+
+	let (code, _, os_buf) = arg.clone().encode(cmd).unwrap();
+	assert_eq!(code, cmd);
+	assert_eq!(os_buf, buf);
+
+	assert!(matches!(Arg::decode(cmd, 0, &buf, Source::Device).unwrap(), Arg::None));
+	assert_eq!(arg.cuse_response(ioctl::TIOCGWINSZ).unwrap(), Some(buf));
+    }
+
+    #[test]
+    fn test_termio() {
+	let mut os = ioctl_ffi::termio {
+	    c_iflag:	0x1234,
+	    c_cflag:	0x00bd,
+	    c_line:	0,
+	    .. Default::default()
+	};
+
+	os.c_cc[ioctl_ffi::NCC - 1] = 42;
+
+	let (_, buf) = obj_to_arg(os);
+	let cmd = ioctl::TCSETA.as_numeric();
+
+	let Arg::TermIOs(ios) = Arg::decode(cmd, 0, &buf, Source::Cuse).unwrap() else {
+	    panic!("bad termio decoding");
+	};
+
+	assert_eq!(ios.iflag(), 0x1234);
+	assert_eq!(ios.cflag(), 0x00bd);
+
+	// ignore the trailing padding
+	let sz = core::mem::offset_of!(ioctl_ffi::termio, c_cc) + ioctl_ffi::NCC;
+
+	let (_, _, os_buf) = Arg::TermIOs(ios.clone()).encode(cmd).unwrap();
+	assert_eq!(os_buf[..sz], buf[..sz]);
+
+	// the upper bits of the flags are kept
+	let cur = TermIOs::from_os2(&ioctl_ffi::termios2 {
+	    c_iflag:	0x0001_0000.into(),
+	    c_oflag:	0.into(),
+	    c_cflag:	(nix::libc::CRTSCTS | nix::libc::B9600).into(),
+	    c_lflag:	0.into(),
+	    c_line:	0,
+	    c_cc:	[7; ioctl_ffi::NCCS],
+	    c_ispeed:	9600,
+	    c_ospeed:	9600,
+	});
+
+	let res = ios.merge_termio(&cur);
+
+	assert_eq!(res.iflag(), 0x0001_1234);
+	assert_eq!(res.cflag(), ensc_ioctl_ffi::termbits::CRTSCTS | 0x00bd);
+	assert_eq!(res.ospeed(), 9600);
+	assert_eq!(res.into_os2().c_cc[ioctl_ffi::NCC], 7);
+    }
+
+    #[test]
+    fn test_modem() {
+	use ensc_ioctl_ffi::termbits;
+	use nix::libc;
+
+	let native = libc::TIOCM_DTR | libc::TIOCM_CTS | libc::TIOCM_CAR;
+	let wire = termbits::TIOCM_DTR | termbits::TIOCM_CTS | termbits::TIOCM_CAR;
+
+	let (_, buf) = obj_to_arg(native);
+	let cmd = ioctl::TIOCMSET.as_numeric();
+
+	let arg = Arg::decode(cmd, 0, &buf, Source::Cuse).unwrap();
+	assert!(matches!(arg, Arg::Int(v) if v.as_native() == wire as u32));
+
+	let (_, _, os_buf) = arg.encode(cmd).unwrap();
+	assert_eq!(os_buf, buf);
+
+	// TIOCMIWAIT passes the mask by value
+	let cmd = ioctl::TIOCMIWAIT.as_numeric();
+
+	let arg = Arg::decode(cmd, native as u64, &[], Source::Cuse).unwrap();
+	assert!(matches!(arg, Arg::Arg(v) if v.as_native() == wire as u64));
+
+	let (_, ptr, _) = arg.encode(cmd).unwrap();
+	assert_eq!(ptr, native as u64);
+    }
+
+    #[test]
+    fn test_raw() {
+	// _IOW('X', 1, u32)
+	let cmd = ioctl::from(0x4004_5801);
+
+	assert!(lookup(cmd).is_none());
+
+	let arg = Arg::decode(cmd.as_numeric(), 0, &[1, 2, 3, 4], Source::Cuse).unwrap();
+	assert!(arg.is_raw());
+
+	let (_, ptr, buf) = arg.encode(cmd.as_numeric()).unwrap();
+	assert_eq!(ptr, buf.as_ptr() as u64);
+	assert_eq!(buf, [1, 2, 3, 4]);
+    }
+}
@@ -1,4 +1,6 @@
 use ensc_ioctl_ffi::ffi as ioctl_ffi;
+use ensc_ioctl_ffi::abi::{Abi, Flags};
+use ensc_ioctl_ffi::termbits;
 
 use crate::proto::{endian::*, AsReprBytes, AsReprBytesMut};
 
@@ -23,59 +25,285 @@ unsafe impl AsReprBytesMut for TermIOs {}
 
 const _: () = assert!(core::mem::size_of::<TermIOs>() == 0x40);
 
-impl From<be32> for ioctl_ffi::c_iflag {
-    fn from(value: be32) -> Self {
-        Self(value.into())
-    }
+#[repr(C,packed)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WinSize {
+    row:	be16,
+    col:	be16,
+    xpixel:	be16,
+    ypixel:	be16,
 }
 
-impl From<be32> for ioctl_ffi::c_oflag {
-    fn from(value: be32) -> Self {
-        Self(value.into())
-    }
+unsafe impl AsReprBytes for WinSize {}
+unsafe impl AsReprBytesMut for WinSize {}
+
+const _: () = assert!(core::mem::size_of::<WinSize>() == 8);
+
+/// Counters of `TIOCGICOUNT`; the reserved fields are not transported
+#[repr(C,packed)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ICounter {
+    cts:		be32,
+    dsr:		be32,
+    rng:		be32,
+    dcd:		be32,
+    rx:			be32,
+    tx:			be32,
+    frame:		be32,
+    overrun:		be32,
+    parity:		be32,
+    brk:		be32,
+    buf_overrun:	be32,
 }
 
-impl From<be32> for ioctl_ffi::c_cflag {
-    fn from(value: be32) -> Self {
-        Self(value.into())
-    }
+unsafe impl AsReprBytes for ICounter {}
+unsafe impl AsReprBytesMut for ICounter {}
+
+const _: () = assert!(core::mem::size_of::<ICounter>() == 44);
+
+/// Settings of `TIOCGRS485` and `TIOCSRS485`; the padding is not
+/// transported
+#[repr(C,packed)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rs485 {
+    flags:			be32,
+    delay_rts_before_send:	be32,
+    delay_rts_after_send:	be32,
+    addr_recv:			be8,
+    addr_dest:			be8,
+    _pad:			[u8;2],
 }
 
-impl From<be32> for ioctl_ffi::c_lflag {
-    fn from(value: be32) -> Self {
-        Self(value.into())
+unsafe impl AsReprBytes for Rs485 {}
+unsafe impl AsReprBytesMut for Rs485 {}
+
+const _: () = assert!(core::mem::size_of::<Rs485>() == 16);
+
+/// Settings of `TIOCGSERIAL` and `TIOCSSERIAL`; the `iomem_base`
+/// pointer is not transported
+#[repr(C,packed)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Serial {
+    type_:		be32,
+    line:		be32,
+    port:		be32,
+    irq:		be32,
+    flags:		be32,
+    xmit_fifo_size:	be32,
+    custom_divisor:	be32,
+    baud_base:		be32,
+    close_delay:	be16,
+    io_type:		be8,
+    reserved_char:	be8,
+    hub6:		be32,
+    closing_wait:	be16,
+    closing_wait2:	be16,
+    iomem_reg_shift:	be16,
+    _pad:		[u8;2],
+    port_high:		be32,
+    iomap_base:		be64,
+}
+
+unsafe impl AsReprBytes for Serial {}
+unsafe impl AsReprBytesMut for Serial {}
+
+const _: () = assert!(core::mem::size_of::<Serial>() == 60);
+
+/// Returns the baudrate of a `Bxxx` constant in wire format
+pub fn cbaud_to_rate(cbaud: ioctl_ffi::tcflag_t) -> Option<u32> {
+    Abi::Generic.cbaud_to_rate(cbaud)
+}
+
+/// Returns the `Bxxx` constant in wire format for a baudrate
+pub fn rate_to_cbaud(rate: u32) -> Option<ioctl_ffi::tcflag_t> {
+    Abi::Generic.rate_to_cbaud(rate)
+}
+
+/// Input and output baudrate of a termios.  Like in the kernel, they are
+/// described by the `CBAUD` and `CIBAUD` bits of `c_cflag` and, when
+/// these bits are `BOTHER`, by the explicit speeds of `termios2`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Speed {
+    pub ispeed:	u32,
+    pub ospeed:	u32,
+}
+
+impl Speed {
+    /// Decodes the baudrates like `tty_termios_baud_rate()` and
+    /// `tty_termios_input_baud_rate()`; an input speed of `B0` means
+    /// "same as output"
+    pub fn decode(cflag: u32, ispeed: u32, ospeed: u32) -> Self {
+	let ospeed = match cflag & termbits::CBAUD {
+	    termbits::BOTHER	=> ospeed,
+	    cbaud		=> cbaud_to_rate(cbaud).unwrap_or(0),
+	};
+
+	let ispeed = match (cflag >> termbits::IBSHIFT) & termbits::CBAUD {
+	    termbits::B0	=> ospeed,
+	    termbits::BOTHER	=> ispeed,
+	    cbaud		=> cbaud_to_rate(cbaud).unwrap_or(0),
+	};
+
+	Self {
+	    ispeed:	ispeed,
+	    ospeed:	ospeed,
+	}
     }
+
+    /// Returns `cflag` with `CBAUD` and `CIBAUD` set like
+    /// `tty_termios_encode_baud_rate()` does.  Rates without `Bxxx`
+    /// constant are encoded as `BOTHER`; the input speed is encoded
+    /// only when it differs from the output one or was given explicitly.
+    pub fn encode(&self, cflag: u32) -> u32 {
+	let split = self.ispeed != self.ospeed || cflag & termbits::CIBAUD != 0;
+	let mut res = cflag & !(termbits::CBAUD | termbits::CIBAUD);
+
+	res |= rate_to_cbaud(self.ospeed).unwrap_or(termbits::BOTHER);
+
+	if split {
+	    res |= rate_to_cbaud(self.ispeed).unwrap_or(termbits::BOTHER) << termbits::IBSHIFT;
+	}
+
+	res
+    }
+}
+
+/// Fields of a termios in the representation of the local architecture
+struct Native {
+    iflag:	u32,
+    oflag:	u32,
+    cflag:	u32,
+    lflag:	u32,
+    line:	u8,
+    cc:		[ioctl_ffi::cc_t; ioctl_ffi::NCCS],
+    ispeed:	u32,
+    ospeed:	u32,
 }
 
 impl TermIOs {
-    pub fn try_from_os(raw: &[u8]) -> Result<Self> {
-	if raw.len() < core::mem::size_of::<ioctl_ffi::termios>() {
-	    warn!("os termios param too short");
-	    return Err(Error::BadIoctlParam);
+    pub fn iflag(&self) -> u32 {
+	self.iflag.into()
+    }
+
+    pub fn cflag(&self) -> u32 {
+	self.cflag.into()
+    }
+
+    pub fn speed(&self) -> Speed {
+	Speed::decode(self.cflag(), self.ispeed.into(), self.ospeed.into())
+    }
+
+    /// Returns the output baudrate; it is taken from the speed fields
+    /// when `BOTHER` is set and from the `CBAUD` bits else
+    pub fn ospeed(&self) -> u32 {
+	self.speed().ospeed
+    }
+
+    /// Makes the `CBAUD`/`CIBAUD` bits and the speed fields consistent.
+    /// A `BOTHER` speed of 0 is unknown (e.g. after `TCGETS`) and kept.
+    fn reconcile_speed(&mut self) {
+	let cflag = self.cflag();
+	let speed = self.speed();
+
+	let unknown = |cbaud: u32, rate: u32| cbaud & termbits::CBAUD == termbits::BOTHER && rate == 0;
+
+	if unknown(cflag, speed.ospeed) || unknown(cflag >> termbits::IBSHIFT, speed.ispeed) {
+	    return;
 	}
 
-	let params = unsafe {
-	    (raw as * const _ as * const ioctl_ffi::termios).read_unaligned()
-	};
+	self.cflag = speed.encode(cflag).into();
+	self.ispeed = speed.ispeed.into();
+	self.ospeed = speed.ospeed.into();
+    }
 
+    /// Applies a `TCSETS*` request like the kernel: the speed fields
+    /// which are not part of `struct termios` are kept from `cur`
+    pub fn inherit_speed(&self, cur: &Self) -> Self {
 	let mut res = Self {
-	    iflag:	params.c_iflag.0.into(),
-	    oflag:	params.c_oflag.0.into(),
-	    cflag:	params.c_cflag.0.into(),
-	    lflag:	params.c_lflag.0.into(),
-	    line:	params.c_line.into(),
-	    cc:		Default::default(),
-	    ispeed:	0.into(),
-	    ospeed:	0.into(),
+	    ispeed:	cur.ispeed,
+	    ospeed:	cur.ospeed,
+	    .. self.clone()
+	};
+
+	res.reconcile_speed();
+
+	res
+    }
 
+    /// Converts the native representation into the wire format
+    fn from_native(os: Native) -> Self {
+	let abi = Abi::NATIVE;
+	let (cflag, ispeed, ospeed) = abi.cflag_to_wire(os.cflag, os.ispeed, os.ospeed);
+	let mut cc = [0; 31];
+
+	abi.cc_to_wire(&os.cc, &mut cc);
+
+	let mut res = Self {
+	    iflag:	abi.flags_to_wire(Flags::Iflag, os.iflag).into(),
+	    oflag:	abi.flags_to_wire(Flags::Oflag, os.oflag).into(),
+	    cflag:	cflag.into(),
+	    lflag:	abi.flags_to_wire(Flags::Lflag, os.lflag).into(),
+	    line:	os.line.into(),
+	    cc:		cc.map(Into::into),
+	    ispeed:	ispeed.into(),
+	    ospeed:	ospeed.into(),
 	    _pad:	0,
 	};
 
-	for (idx, v) in params.c_cc.into_iter().enumerate() {
-	    res.cc[idx] = v.into();
+	res.reconcile_speed();
+
+	res
+    }
+
+    fn into_native(mut self) -> Native {
+	self.reconcile_speed();
+
+	let abi = Abi::NATIVE;
+	let lflag = u32::from(self.lflag);
+	let (cflag, ispeed, ospeed) = abi.cflag_from_wire(self.cflag(), self.ispeed.into(),
+							  self.ospeed.into());
+	let mut cc = [0; ioctl_ffi::NCCS];
+
+	abi.cc_from_wire(&self.cc.map(Into::into), lflag & termbits::ICANON != 0, &mut cc);
+
+	Native {
+	    iflag:	abi.flags_from_wire(Flags::Iflag, self.iflag()),
+	    oflag:	abi.flags_from_wire(Flags::Oflag, self.oflag.into()),
+	    cflag:	cflag,
+	    lflag:	abi.flags_from_wire(Flags::Lflag, lflag),
+	    line:	self.line.into(),
+	    cc:		cc,
+	    ispeed:	ispeed,
+	    ospeed:	ospeed,
 	}
+    }
 
-	Ok(res)
+    pub fn try_from_os(raw: &[u8]) -> Result<Self> {
+	if raw.len() < core::mem::size_of::<ioctl_ffi::termios>() {
+	    warn!("os termios param too short");
+	    return Err(Error::BadIoctlParam);
+	}
+
+	let params = unsafe {
+	    (raw as * const _ as * const ioctl_ffi::termios).read_unaligned()
+	};
+
+	Ok(Self::from_native(Native {
+	    iflag:	params.c_iflag.0,
+	    oflag:	params.c_oflag.0,
+	    cflag:	params.c_cflag.0,
+	    lflag:	params.c_lflag.0,
+	    line:	params.c_line,
+	    cc:		params.c_cc,
+	    #[cfg(any(target_arch = "powerpc", target_arch = "powerpc64"))]
+	    ispeed:	params.c_ispeed,
+	    #[cfg(any(target_arch = "powerpc", target_arch = "powerpc64"))]
+	    ospeed:	params.c_ospeed,
+	    #[cfg(not(any(target_arch = "powerpc", target_arch = "powerpc64")))]
+	    ispeed:	0,
+	    #[cfg(not(any(target_arch = "powerpc", target_arch = "powerpc64")))]
+	    ospeed:	0,
+	}))
     }
 
     pub fn try_from_raw_os2(raw: &[u8]) -> Result<Self> {
@@ -92,58 +320,418 @@ impl TermIOs {
     }
 
     pub fn from_os2(params: &ioctl_ffi::termios2) -> Self {
-	let mut res = Self {
-	    iflag:	params.c_iflag.0.into(),
-	    oflag:	params.c_oflag.0.into(),
-	    cflag:	params.c_cflag.0.into(),
-	    lflag:	params.c_lflag.0.into(),
-	    line:	params.c_line.into(),
-	    cc:		Default::default(),
-	    ispeed:	params.c_ispeed.into(),
-	    ospeed:	params.c_ospeed.into(),
-	    _pad:	0,
-	};
+	Self::from_native(Native {
+	    iflag:	params.c_iflag.0,
+	    oflag:	params.c_oflag.0,
+	    cflag:	params.c_cflag.0,
+	    lflag:	params.c_lflag.0,
+	    line:	params.c_line,
+	    cc:		params.c_cc,
+	    ispeed:	params.c_ispeed,
+	    ospeed:	params.c_ospeed,
+	})
+    }
+
+    pub fn into_os(self) -> ioctl_ffi::termios {
+	let os = self.into_native();
 
-	for (idx, v) in params.c_cc.iter().enumerate() {
-	    res.cc[idx] = (*v).into();
+	ioctl_ffi::termios {
+	    c_iflag:	ioctl_ffi::c_iflag(os.iflag),
+	    c_oflag:	ioctl_ffi::c_oflag(os.oflag),
+	    c_cflag:	ioctl_ffi::c_cflag(os.cflag),
+	    c_lflag:	ioctl_ffi::c_lflag(os.lflag),
+	    c_line:	os.line,
+	    c_cc:	os.cc,
+	    #[cfg(any(target_arch = "powerpc", target_arch = "powerpc64"))]
+	    c_ispeed:	os.ispeed,
+	    #[cfg(any(target_arch = "powerpc", target_arch = "powerpc64"))]
+	    c_ospeed:	os.ospeed,
 	}
+    }
 
-	res
+    pub fn try_from_os_termio(raw: &[u8]) -> Result<Self> {
+	if raw.len() < core::mem::size_of::<ioctl_ffi::termio>() {
+	    warn!("os termio param too short");
+	    return Err(Error::BadIoctlParam);
+	}
+
+	let params = unsafe {
+	    (raw as * const _ as * const ioctl_ffi::termio).read_unaligned()
+	};
+
+	let mut cc = [0; ioctl_ffi::NCCS];
+
+	cc[..ioctl_ffi::NCC].copy_from_slice(&params.c_cc);
+
+	Ok(Self::from_native(Native {
+	    iflag:	params.c_iflag as u32,
+	    oflag:	params.c_oflag as u32,
+	    cflag:	params.c_cflag as u32,
+	    lflag:	params.c_lflag as u32,
+	    line:	params.c_line,
+	    cc:		cc,
+	    ispeed:	0,
+	    ospeed:	0,
+	}))
     }
 
-    pub fn into_os(self) -> ioctl_ffi::termios {
-	let mut res = ioctl_ffi::termios {
-	    c_iflag:	self.iflag.into(),
-	    c_oflag:	self.oflag.into(),
-	    c_cflag:	self.cflag.into(),
-	    c_lflag:	self.lflag.into(),
-	    c_line:	self.line.into(),
+    /// Converts into a SysV termio; the upper 16 bits of the flags and
+    /// the control characters beyond `NCC` are lost
+    pub fn into_os_termio(self) -> ioctl_ffi::termio {
+	let os = self.into_native();
+
+	let mut res = ioctl_ffi::termio {
+	    c_iflag:	os.iflag as u16,
+	    c_oflag:	os.oflag as u16,
+	    c_cflag:	os.cflag as u16,
+	    c_lflag:	os.lflag as u16,
+	    c_line:	os.line,
 	    c_cc:	Default::default(),
 	};
 
-	for (idx, v) in res.c_cc.iter_mut().enumerate() {
-	    *v = self.cc[idx].into();
-	}
+	res.c_cc.copy_from_slice(&os.cc[..ioctl_ffi::NCC]);
 
 	res
     }
 
+    /// Applies the settings of a `TCSETA*` request like the kernel: only
+    /// the lower 16 bits of the native flags and the first `NCC` control
+    /// characters are taken from `self`; the rest is kept from `cur`
+    pub fn merge_termio(&self, cur: &Self) -> Self {
+	let new = self.clone().into_os_termio();
+	let mut res = cur.clone().into_native();
+
+	let merge = |old: u32, new: u16| (old & 0xffff_0000) | new as u32;
+
+	res.iflag = merge(res.iflag, new.c_iflag);
+	res.oflag = merge(res.oflag, new.c_oflag);
+	res.cflag = merge(res.cflag, new.c_cflag);
+	res.lflag = merge(res.lflag, new.c_lflag);
+	res.line = new.c_line;
+	res.cc[..ioctl_ffi::NCC].copy_from_slice(&new.c_cc);
+
+	Self::from_native(res)
+    }
+
     pub fn into_os2(self) -> ioctl_ffi::termios2 {
-	let mut res = ioctl_ffi::termios2 {
-	    c_iflag:	self.iflag.into(),
-	    c_oflag:	self.oflag.into(),
-	    c_cflag:	self.cflag.into(),
-	    c_lflag:	self.lflag.into(),
-	    c_line:	self.line.into(),
-	    c_ospeed:	self.ospeed.into(),
-	    c_ispeed:	self.ispeed.into(),
-	    c_cc:	Default::default(),
+	let os = self.into_native();
+
+	ioctl_ffi::termios2 {
+	    c_iflag:	ioctl_ffi::c_iflag(os.iflag),
+	    c_oflag:	ioctl_ffi::c_oflag(os.oflag),
+	    c_cflag:	ioctl_ffi::c_cflag(os.cflag),
+	    c_lflag:	ioctl_ffi::c_lflag(os.lflag),
+	    c_line:	os.line,
+	    c_ospeed:	os.ospeed,
+	    c_ispeed:	os.ispeed,
+	    c_cc:	os.cc,
+	}
+    }
+}
+
+impl WinSize {
+    pub fn try_from_os(raw: &[u8]) -> Result<Self> {
+	if raw.len() < core::mem::size_of::<ioctl_ffi::winsize>() {
+	    warn!("os winsize param too short");
+	    return Err(Error::BadIoctlParam);
+	}
+
+	let params = unsafe {
+	    (raw as * const _ as * const ioctl_ffi::winsize).read_unaligned()
 	};
 
-	for (idx, v) in res.c_cc.iter_mut().enumerate() {
-	    *v = self.cc[idx].into();
+	Ok(Self::from_os(&params))
+    }
+
+    pub fn from_os(params: &ioctl_ffi::winsize) -> Self {
+	Self {
+	    row:	params.ws_row.into(),
+	    col:	params.ws_col.into(),
+	    xpixel:	params.ws_xpixel.into(),
+	    ypixel:	params.ws_ypixel.into(),
 	}
+    }
 
-	res
+    pub fn into_os(self) -> ioctl_ffi::winsize {
+	ioctl_ffi::winsize {
+	    ws_row:	self.row.into(),
+	    ws_col:	self.col.into(),
+	    ws_xpixel:	self.xpixel.into(),
+	    ws_ypixel:	self.ypixel.into(),
+	}
+    }
+}
+
+impl ICounter {
+    pub fn try_from_os(raw: &[u8]) -> Result<Self> {
+	if raw.len() < core::mem::size_of::<ioctl_ffi::serial_icounter_struct>() {
+	    warn!("os serial_icounter_struct param too short");
+	    return Err(Error::BadIoctlParam);
+	}
+
+	let params = unsafe {
+	    (raw as * const _ as * const ioctl_ffi::serial_icounter_struct).read_unaligned()
+	};
+
+	Ok(Self::from_os(&params))
+    }
+
+    pub fn from_os(params: &ioctl_ffi::serial_icounter_struct) -> Self {
+	Self {
+	    cts:		(params.cts as u32).into(),
+	    dsr:		(params.dsr as u32).into(),
+	    rng:		(params.rng as u32).into(),
+	    dcd:		(params.dcd as u32).into(),
+	    rx:			(params.rx as u32).into(),
+	    tx:			(params.tx as u32).into(),
+	    frame:		(params.frame as u32).into(),
+	    overrun:		(params.overrun as u32).into(),
+	    parity:		(params.parity as u32).into(),
+	    brk:		(params.brk as u32).into(),
+	    buf_overrun:	(params.buf_overrun as u32).into(),
+	}
+    }
+
+    pub fn into_os(self) -> ioctl_ffi::serial_icounter_struct {
+	ioctl_ffi::serial_icounter_struct {
+	    cts:		u32::from(self.cts) as _,
+	    dsr:		u32::from(self.dsr) as _,
+	    rng:		u32::from(self.rng) as _,
+	    dcd:		u32::from(self.dcd) as _,
+	    rx:			u32::from(self.rx) as _,
+	    tx:			u32::from(self.tx) as _,
+	    frame:		u32::from(self.frame) as _,
+	    overrun:		u32::from(self.overrun) as _,
+	    parity:		u32::from(self.parity) as _,
+	    brk:		u32::from(self.brk) as _,
+	    buf_overrun:	u32::from(self.buf_overrun) as _,
+	    reserved:		Default::default(),
+	}
+    }
+}
+
+impl Rs485 {
+    pub fn try_from_os(raw: &[u8]) -> Result<Self> {
+	if raw.len() < core::mem::size_of::<ioctl_ffi::serial_rs485>() {
+	    warn!("os serial_rs485 param too short");
+	    return Err(Error::BadIoctlParam);
+	}
+
+	let params = unsafe {
+	    (raw as * const _ as * const ioctl_ffi::serial_rs485).read_unaligned()
+	};
+
+	Ok(Self::from_os(&params))
+    }
+
+    pub fn from_os(params: &ioctl_ffi::serial_rs485) -> Self {
+	Self {
+	    flags:			params.flags.into(),
+	    delay_rts_before_send:	params.delay_rts_before_send.into(),
+	    delay_rts_after_send:	params.delay_rts_after_send.into(),
+	    addr_recv:			params.addr_recv.into(),
+	    addr_dest:			params.addr_dest.into(),
+	    _pad:			Default::default(),
+	}
+    }
+
+    pub fn into_os(self) -> ioctl_ffi::serial_rs485 {
+	ioctl_ffi::serial_rs485 {
+	    flags:			self.flags.into(),
+	    delay_rts_before_send:	self.delay_rts_before_send.into(),
+	    delay_rts_after_send:	self.delay_rts_after_send.into(),
+	    addr_recv:			self.addr_recv.into(),
+	    addr_dest:			self.addr_dest.into(),
+	    .. Default::default()
+	}
+    }
+}
+
+impl Serial {
+    pub fn try_from_os(raw: &[u8]) -> Result<Self> {
+	if raw.len() < core::mem::size_of::<ioctl_ffi::serial_struct>() {
+	    warn!("os serial_struct param too short");
+	    return Err(Error::BadIoctlParam);
+	}
+
+	let params = unsafe {
+	    (raw as * const _ as * const ioctl_ffi::serial_struct).read_unaligned()
+	};
+
+	Ok(Self::from_os(&params))
+    }
+
+    // 'c_ulong' is not 64 bit on all architectures
+    #[allow(clippy::useless_conversion)]
+    pub fn from_os(params: &ioctl_ffi::serial_struct) -> Self {
+	Self {
+	    type_:		(params.type_ as u32).into(),
+	    line:		(params.line as u32).into(),
+	    port:		params.port.into(),
+	    irq:		(params.irq as u32).into(),
+	    flags:		(params.flags as u32).into(),
+	    xmit_fifo_size:	(params.xmit_fifo_size as u32).into(),
+	    custom_divisor:	(params.custom_divisor as u32).into(),
+	    baud_base:		(params.baud_base as u32).into(),
+	    close_delay:	params.close_delay.into(),
+	    io_type:		(params.io_type as u8).into(),
+	    reserved_char:	(params.reserved_char as u8).into(),
+	    hub6:		(params.hub6 as u32).into(),
+	    closing_wait:	params.closing_wait.into(),
+	    closing_wait2:	params.closing_wait2.into(),
+	    iomem_reg_shift:	params.iomem_reg_shift.into(),
+	    _pad:		Default::default(),
+	    port_high:		params.port_high.into(),
+	    iomap_base:		u64::from(params.iomap_base).into(),
+	}
+    }
+
+    pub fn into_os(self) -> ioctl_ffi::serial_struct {
+	ioctl_ffi::serial_struct {
+	    type_:		u32::from(self.type_) as _,
+	    line:		u32::from(self.line) as _,
+	    port:		self.port.into(),
+	    irq:		u32::from(self.irq) as _,
+	    flags:		u32::from(self.flags) as _,
+	    xmit_fifo_size:	u32::from(self.xmit_fifo_size) as _,
+	    custom_divisor:	u32::from(self.custom_divisor) as _,
+	    baud_base:		u32::from(self.baud_base) as _,
+	    close_delay:	self.close_delay.into(),
+	    io_type:		u8::from(self.io_type) as _,
+	    reserved_char:	u8::from(self.reserved_char) as _,
+	    hub6:		u32::from(self.hub6) as _,
+	    closing_wait:	self.closing_wait.into(),
+	    closing_wait2:	self.closing_wait2.into(),
+	    iomem_reg_shift:	self.iomem_reg_shift.into(),
+	    port_high:		self.port_high.into(),
+	    iomap_base:		u64::from(self.iomap_base) as _,
+	    .. Default::default()
+	}
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::obj_to_arg;
+    use nix::libc;
+
+    fn termios2(cflag: u32, ispeed: u32, ospeed: u32) -> ioctl_ffi::termios2 {
+	ioctl_ffi::termios2 {
+	    c_iflag:	0.into(),
+	    c_oflag:	0.into(),
+	    c_cflag:	(libc::CS8 | libc::CREAD | cflag).into(),
+	    c_lflag:	0.into(),
+	    c_line:	0,
+	    c_cc:	Default::default(),
+	    c_ispeed:	ispeed,
+	    c_ospeed:	ospeed,
+	}
+    }
+
+    /// Reads `ios` back like `TCGETS` does
+    fn tcgets(ios: &TermIOs) -> (ioctl_ffi::tcflag_t, TermIOs) {
+	let os = ios.clone().into_os();
+	let cflag = os.c_cflag.0;
+	let (_, buf) = obj_to_arg(os);
+
+	(cflag, TermIOs::try_from_os(&buf).unwrap())
+    }
+
+    #[test]
+    fn test_speed_standard() {
+	for rate in [0, 50, 75, 110, 134, 150, 200, 300, 600, 1200, 1800, 2400, 4800, 9600,
+		     19200, 38400, 57600, 115200, 230400, 460800, 500000, 576000, 921600,
+		     1000000, 1152000, 1500000, 2000000, 2500000, 3000000, 3500000, 4000000] {
+	    let Some(cbaud) = Abi::NATIVE.rate_to_cbaud(rate) else {
+		continue;
+	    };
+
+	    let ios = TermIOs::from_os2(&termios2(cbaud, 0, 0));
+	    let os2 = ios.clone().into_os2();
+
+	    assert_eq!(os2.c_cflag.0 & (libc::CBAUD | libc::CIBAUD), cbaud);
+	    assert_eq!((os2.c_ispeed, os2.c_ospeed), (rate, rate));
+
+	    let (cflag, res) = tcgets(&ios);
+
+	    assert_eq!(cflag & libc::CBAUD, cbaud);
+	    assert_eq!(res.speed(), ios.speed());
+
+	    // a 'BOTHER' speed of 0 is unknown
+	    if rate == 0 {
+		continue;
+	    }
+
+	    // 'BOTHER' with a standard rate is reported as 'Bxxx'
+	    let ios = TermIOs::from_os2(&termios2(libc::BOTHER, rate, rate));
+	    let os2 = ios.clone().into_os2();
+
+	    assert_eq!(os2.c_cflag.0 & (libc::CBAUD | libc::CIBAUD), cbaud);
+	    assert_eq!((os2.c_ispeed, os2.c_ospeed), (rate, rate));
+
+	    let (cflag, res) = tcgets(&ios);
+
+	    assert_eq!(cflag & libc::CBAUD, cbaud);
+	    assert_eq!(res.ospeed(), rate);
+	}
+    }
+
+    #[test]
+    fn test_speed_custom() {
+	let cur = TermIOs::from_os2(&termios2(libc::B9600, 0, 0));
+
+	for rate in [31250, 74880, 250000, 1234567, 12000000] {
+	    let ios = TermIOs::from_os2(&termios2(libc::BOTHER, rate, rate));
+	    let os2 = ios.clone().into_os2();
+
+	    assert_eq!(os2.c_cflag.0 & (libc::CBAUD | libc::CIBAUD), libc::BOTHER);
+	    assert_eq!((os2.c_ispeed, os2.c_ospeed), (rate, rate));
+
+	    // 'TCGETS' can not transport the rate; 'BOTHER' must be kept
+	    let (cflag, res) = tcgets(&ios);
+
+	    assert_eq!(cflag & libc::CBAUD, libc::BOTHER);
+	    assert_eq!(res.clone().into_os().c_cflag.0 & libc::CBAUD, libc::BOTHER);
+
+	    // 'TCSETS' with 'BOTHER' keeps the current rate
+	    assert_eq!(res.inherit_speed(&ios).speed(), ios.speed());
+	    assert_eq!(res.inherit_speed(&cur).ospeed(), 9600);
+
+	    // 'TCSETS' with 'Bxxx' replaces it
+	    let res = TermIOs::from_os2(&termios2(libc::B115200, 0, 0));
+
+	    assert_eq!(res.inherit_speed(&ios).ospeed(), 115200);
+	}
+    }
+
+    #[test]
+    fn test_speed_split() {
+	let bother = libc::BOTHER | (libc::BOTHER << libc::IBSHIFT);
+
+	let ios = TermIOs::from_os2(&termios2(bother, 1200, 115200));
+
+	assert_eq!(ios.cflag() & (termbits::CBAUD | termbits::CIBAUD),
+		   termbits::B115200 | (termbits::B1200 << termbits::IBSHIFT));
+	assert_eq!(ios.speed(), Speed { ispeed: 1200, ospeed: 115200 });
+	assert_eq!(tcgets(&ios).1.speed(), ios.speed());
+
+	let ios = TermIOs::from_os2(&termios2(bother, 250000, 115200));
+
+	assert_eq!(ios.cflag() & (termbits::CBAUD | termbits::CIBAUD),
+		   termbits::B115200 | (termbits::BOTHER << termbits::IBSHIFT));
+	assert_eq!(ios.speed(), Speed { ispeed: 250000, ospeed: 115200 });
+
+	// an input speed of 'B0' follows the output speed
+	let ios = TermIOs::from_os2(&termios2(libc::B38400, 0, 0));
+
+	assert_eq!(ios.cflag() & termbits::CIBAUD, 0);
+	assert_eq!(ios.speed(), Speed { ispeed: 38400, ospeed: 38400 });
+
+	// the explicitly given input speed is kept
+	let ios = TermIOs::from_os2(&termios2(libc::B38400 | (libc::B38400 << libc::IBSHIFT), 0, 0));
+
+	assert_eq!(ios.cflag() & termbits::CIBAUD, termbits::B38400 << termbits::IBSHIFT);
     }
 }
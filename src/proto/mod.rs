@@ -6,11 +6,15 @@ mod rawbuffer;
 pub mod request;
 pub mod response;
 pub mod ioctl;
+pub mod hello;
 
 use std::{time::Duration, os::fd::AsFd};
 
 const TIMEOUT_READ: Duration = Duration::from_secs(3);
 pub const MAX_MSG_SIZE: usize = 128 * 1024;
+/// maximum payload of a received message; announced to the peer in the
+/// handshake
+pub const MAX_PAYLOAD_SIZE: usize = 0x1_0000;
 
 pub use endian::*;
 
@@ -23,11 +27,30 @@ pub use asrepr::{ AsReprBytes, AsReprBytesMut };
 pub use rawbuffer::RawBuffer;
 pub use request::Request;
 pub use response::Response;
+pub use hello::{Hello, Heartbeat, Session};
 
 #[repr(C)]
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Sequence(u64);
 
+/// Identifies an opened file on the server
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Handle(u64);
+
+impl Handle {
+    /// Used for requests which do not operate on a file
+    pub const NONE: Self = Self(0);
+
+    pub fn from_ffi(v: u64) -> Self {
+	Self(v)
+    }
+
+    pub fn as_ffi(self) -> u64 {
+	self.0
+    }
+}
+
 impl Sequence {
     pub fn from_ffi(v: u64) -> Self {
 	assert!(v != 0);
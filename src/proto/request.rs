@@ -2,13 +2,15 @@ use std::mem::MaybeUninit;
 use std::sync::atomic::AtomicU64;
 use std::io::IoSlice;
 use std::os::fd::AsFd;
+use std::time::Duration;
 
 use ensc_cuse_ffi::ffi as cuse_ffi;
 use ensc_cuse_ffi::{WriteParams, ReadParams, PollParams};
 use ensc_ioctl_ffi::ffi::ioctl;
 
 use super::ioctl::Arg;
-use super::{Sequence, AsReprBytes, TIMEOUT_READ, Error, Result, AsReprBytesMut};
+use super::hello::Hello;
+use super::{Sequence, Handle, AsReprBytes, TIMEOUT_READ, Error, Result, AsReprBytesMut};
 use super::io::{send_vectored_all, recv_exact_timeout, recv_to, send_all};
 use super::endian::*;
 
@@ -29,6 +31,8 @@ pub enum RequestCode {
     Ioctl	= 5,
     Poll	= 6,
     Interrupt	= 7,
+    Hello	= 8,
+    Ping	= 9,
 }
 
 impl RequestCode {
@@ -45,6 +49,8 @@ impl RequestCode {
 	    5	=> Self::Ioctl,
 	    6	=> Self::Poll,
 	    7	=> Self::Interrupt,
+	    8	=> Self::Hello,
+	    9	=> Self::Ping,
 	    _	=> return None,
 	})
     }
@@ -58,6 +64,8 @@ pub enum Request<'a> {
     Ioctl(Sequence, Ioctl, Arg),
     Poll(Sequence, Poll),
     Interrupt(Sequence),
+    Hello(Sequence, Hello),
+    Ping(Sequence),
 }
 
 impl std::fmt::Debug for Request<'_> {
@@ -103,6 +111,17 @@ impl std::fmt::Debug for Request<'_> {
 		f.debug_tuple("Interrupt")
 		.field(seq)
 		.finish(),
+
+            Self::Hello(seq, hello)		=>
+		f.debug_tuple("Hello")
+		.field(seq)
+		.field(hello)
+		.finish(),
+
+            Self::Ping(seq)			=>
+		f.debug_tuple("Ping")
+		.field(seq)
+		.finish(),
         }
     }
 }
@@ -116,13 +135,36 @@ fn sub_slice(buf: &mut [MaybeUninit<u8>], sz: usize) -> MaybeUninit<&mut [u8]> {
 }
 
 impl <'a> Request<'a> {
-    const MAX_SZ: usize = 0x1_0000;
+    const MAX_SZ: usize = super::MAX_PAYLOAD_SIZE;
+
+    pub fn seq(&self) -> Sequence {
+	match self {
+	    Self::Open(seq, _) |
+	    Self::Release(seq) |
+	    Self::Write(seq, _, _) |
+	    Self::Read(seq, _) |
+	    Self::Ioctl(seq, _, _) |
+	    Self::Poll(seq, _) |
+	    Self::Interrupt(seq) |
+	    Self::Hello(seq, _) |
+	    Self::Ping(seq)		=> *seq,
+	}
+    }
 
+    /// Receives the next request and returns it together with the file
+    /// handle it operates on
     //#[instrument(level="trace", skip(r, tmp_buf), ret)]
-    pub fn recv<R: AsFd + std::io::Read>(r: R, tmp_buf: &'a mut [MaybeUninit<u8>]) -> Result<Self> {
+    pub fn recv<R: AsFd + std::io::Read>(r: R, tmp_buf: &'a mut [MaybeUninit<u8>]) -> Result<(Handle, Self)> {
+	Self::recv_timeout(r, tmp_buf, None)
+    }
+
+    /// Like [`Self::recv()`] but fails with `ETIMEDOUT` when no request
+    /// arrives within `to`
+    pub fn recv_timeout<R: AsFd + std::io::Read>(r: R, tmp_buf: &'a mut [MaybeUninit<u8>],
+						 to: Option<Duration>) -> Result<(Handle, Self)> {
 	let mut hdr = Header::uninit();
 
-	let hdr = recv_exact_timeout(&r, &mut hdr, &mut None, None, Some(TIMEOUT_READ))?;
+	let hdr = recv_exact_timeout(&r, &mut hdr, &mut None, to, Some(TIMEOUT_READ))?;
 	debug!("hdr={hdr:?}");
 	let len = hdr.len();
 
@@ -136,6 +178,7 @@ impl <'a> Request<'a> {
 	let op = RequestCode::try_from_u8(op)
 	    .ok_or(Error::BadOp(op))?;
 	let seq = hdr.seq()?;
+	let fh = hdr.fh();
 
 	let res = match op {
 	    RequestCode::Open		=>
@@ -165,12 +208,28 @@ impl <'a> Request<'a> {
 		Self::Poll(seq, pollinfo)
 	    }
 	    RequestCode::Interrupt	=>
-		Self::Interrupt(seq)
+		Self::Interrupt(seq),
+	    RequestCode::Hello		=> {
+		let hello = recv_to(&r, Hello::uninit(), &mut rx_len)?;
+		let pending = *rx_len.as_ref().unwrap();
+
+		if pending > tmp_buf.len() {
+		    return Err(Error::BadLength);
+		}
+
+		// later protocol versions might append fields; skip them so
+		// that the version mismatch can be reported
+		recv_to(&r, sub_slice(tmp_buf, pending), &mut rx_len)?;
+
+		Self::Hello(seq, hello)
+	    }
+	    RequestCode::Ping		=>
+		Self::Ping(seq),
 	};
 
 	match rx_len.unwrap() {
 	    0		=>
-		Ok(res),
+		Ok((fh, res)),
 	    l		=> {
 		warn!("{l} octets not consumed for {op:?}");
 		Err(super::Error::BadLength)
@@ -178,10 +237,11 @@ impl <'a> Request<'a> {
 	}
     }
 
-    pub fn send_interrupt<W: AsFd + std::io::Write>(w: W, seq: Sequence) -> Result<()> {
+    pub fn send_interrupt<W: AsFd + std::io::Write>(w: W, fh: Handle, seq: Sequence) -> Result<()> {
 	let hdr = Header {
 	    op:		RequestCode::Interrupt.as_u8().into(),
 	    seq:	seq.as_ffi().into(),
+	    fh:		fh.as_ffi().into(),
 	    len:	0.into(),
 	    .. Default::default()
 	};
@@ -199,20 +259,22 @@ pub struct Header {
     _pad:	[u8;3],
     len:	be32,
     seq:	be64,
+    fh:		be64,
 }
 
 impl Header{
-    pub fn new<T: Sized>(op: RequestCode, payload: &T) -> Self {
-	Self::with_payload(op, payload, &[])
+    pub fn new<T: Sized>(op: RequestCode, fh: Handle, payload: &T) -> Self {
+	Self::with_payload(op, fh, payload, &[])
     }
 
-    pub fn with_payload<T: Sized>(op: RequestCode, payload: &T, data: &[u8]) -> Self {
+    pub fn with_payload<T: Sized>(op: RequestCode, fh: Handle, payload: &T, data: &[u8]) -> Self {
 	let len = (core::mem::size_of_val(payload) + data.len()) as u32;
 
 	Self {
 	    op:		op.as_u8().into(),
 	    seq:	OP_SEQ.fetch_add(1, std::sync::atomic::Ordering::Relaxed).into(),
 	    len:	len.into(),
+	    fh:		fh.as_ffi().into(),
 	    .. Default::default()
 	}
 
@@ -232,6 +294,10 @@ impl Header{
 	    v	=> Ok(Sequence(v))
 	}
     }
+
+    pub fn fh(&self) -> Handle {
+	Handle(self.fh.as_native())
+    }
 }
 
 unsafe impl AsReprBytes for Header {}
@@ -249,13 +315,13 @@ unsafe impl AsReprBytesMut for Open {}
 
 impl Request<'_> {
     //#[instrument(level="trace", skip(w), ret)]
-    pub fn send_open<W: AsFd + std::io::Write>(w: W, flags: cuse_ffi::fh_flags) -> Result<Sequence> {
+    pub fn send_open<W: AsFd + std::io::Write>(w: W, fh: Handle, flags: cuse_ffi::fh_flags) -> Result<Sequence> {
 	let info = Open {
 	    flags: flags.into(),
 	    ..Default::default()
 	};
 
-	let hdr = Header::new(RequestCode::Open, &info);
+	let hdr = Header::new(RequestCode::Open, fh, &info);
 	let seq = hdr.seq()?;
 
 	send_vectored_all(w, &[ IoSlice::new(hdr.as_repr_bytes()),
@@ -275,11 +341,11 @@ unsafe impl AsReprBytesMut for Release {}
 
 impl Request<'_> {
     //#[instrument(level="trace", skip(w), ret)]
-    pub fn send_release<W: AsFd + std::io::Write>(w: W) -> Result<Sequence> {
+    pub fn send_release<W: AsFd + std::io::Write>(w: W, fh: Handle) -> Result<Sequence> {
 	let info = Release {
 	};
 
-	let hdr = Header::new(RequestCode::Release, &info);
+	let hdr = Header::new(RequestCode::Release, fh, &info);
 	let seq = hdr.seq()?;
 
 	send_vectored_all(w, &[ IoSlice::new(hdr.as_repr_bytes()),
@@ -302,14 +368,14 @@ unsafe impl AsReprBytesMut for Write {}
 
 impl Request<'_> {
     //#[instrument(level="trace", skip(w), ret)]
-    pub fn send_write<W: AsFd + std::io::Write>(w: W, wrinfo: WriteParams, data: &[u8]) -> Result<Sequence> {
+    pub fn send_write<W: AsFd + std::io::Write>(w: W, fh: Handle, wrinfo: WriteParams, data: &[u8]) -> Result<Sequence> {
 	let info = Write {
 	    offset:	wrinfo.offset.into(),
 	    fh_flags:	wrinfo.flags.into(),
 	    _pad:	Default::default(),
 	};
 
-	let hdr = Header::with_payload(RequestCode::Write, &info, data);
+	let hdr = Header::with_payload(RequestCode::Write, fh, &info, data);
 	let seq = hdr.seq()?;
 
 	send_vectored_all(w, &[ IoSlice::new(hdr.as_repr_bytes()),
@@ -333,14 +399,14 @@ unsafe impl AsReprBytesMut for Read {}
 
 impl Request<'_> {
     //#[instrument(level="trace", skip(w), ret)]
-    pub fn send_read<W: AsFd + std::io::Write>(w: W, rdinfo: ReadParams) -> Result<Sequence> {
+    pub fn send_read<W: AsFd + std::io::Write>(w: W, fh: Handle, rdinfo: ReadParams) -> Result<Sequence> {
 	let info = Read {
 	    offset:	rdinfo.offset.into(),
 	    size:	rdinfo.size.into(),
 	    fh_flags:	rdinfo.flags.into(),
 	};
 
-	let hdr = Header::new(RequestCode::Read, &info);
+	let hdr = Header::new(RequestCode::Read, fh, &info);
 	let seq = hdr.seq()?;
 
 	send_vectored_all(w, &[ IoSlice::new(hdr.as_repr_bytes()),
@@ -353,25 +419,56 @@ impl Request<'_> {
 #[repr(C)]
 #[derive(Debug, Default)]
 pub struct Ioctl {
+    /// architecture independent id of the ioctl or, with `FLAG_NATIVE`,
+    /// its native number
     pub cmd:		be32,
     pub arg_type:	be8,
-    _pad:		[be8;3],
+    pub flags:		be8,
+    _pad:		[be8;2],
 }
 
 unsafe impl AsReprBytes for Ioctl {}
 unsafe impl AsReprBytesMut for Ioctl {}
 
+impl Ioctl {
+    pub const FLAG_NATIVE: u8 = 1 << 0;
+
+    /// Whether `cmd` is the native number of the sender
+    pub fn is_native(&self) -> bool {
+	self.flags.as_native() & Self::FLAG_NATIVE != 0
+    }
+
+    /// Returns the local ioctl; `None` when it is not supported on this
+    /// architecture
+    pub fn cmd(&self) -> Option<ioctl> {
+	let cmd: u32 = self.cmd.into();
+
+	if self.is_native() {
+	    return Some(ioctl::from(cmd));
+	}
+
+	u16::try_from(cmd).ok()
+	    .and_then(|id| ioctl::from_id(id.into()))
+    }
+}
+
 impl Request<'_> {
     //#[instrument(level="trace", skip(w), ret)]
-    pub fn send_ioctl<W: AsFd + std::io::Write>(w: W, cmd: ioctl, arg: Arg) -> Result<Sequence> {
+    pub fn send_ioctl<W: AsFd + std::io::Write>(w: W, fh: Handle, cmd: ioctl, arg: Arg) -> Result<Sequence> {
+	let (cmd, flags) = match cmd.id() {
+	    Some(id)	=> (id.as_numeric() as u32, 0),
+	    None	=> (cmd.as_numeric(), Ioctl::FLAG_NATIVE),
+	};
+
 	let info = Ioctl {
-	    cmd:	cmd.as_numeric().into(),
+	    cmd:	cmd.into(),
 	    arg_type:	arg.code(),
+	    flags:	flags.into(),
 	    _pad:	Default::default(),
 	};
 	let data = arg.as_repr_bytes();
 
-	let hdr = Header::with_payload(RequestCode::Ioctl, &info, data);
+	let hdr = Header::with_payload(RequestCode::Ioctl, fh, &info, data);
 	let seq = hdr.seq()?;
 
 	send_vectored_all(w, &[ IoSlice::new(hdr.as_repr_bytes()),
@@ -399,14 +496,14 @@ impl Poll {
 
 impl Request<'_> {
     //#[instrument(level="trace", skip(w), ret)]
-    pub fn send_poll<W: AsFd + std::io::Write>(w: W, parm: PollParams) -> Result<Sequence> {
+    pub fn send_poll<W: AsFd + std::io::Write>(w: W, fh: Handle, parm: PollParams) -> Result<Sequence> {
 	let info = Poll {
 	    kh:		parm.kh.into(),
 	    flags:	parm.flags.as_ffi().into(),
 	    events:	parm.events.as_ffi().into(),
 	};
 
-	let hdr = Header::new(RequestCode::Poll, &info);
+	let hdr = Header::new(RequestCode::Poll, fh, &info);
 	let seq = hdr.seq()?;
 
 	send_vectored_all(w, &[ IoSlice::new(hdr.as_repr_bytes()),
@@ -416,6 +513,29 @@ impl Request<'_> {
     }
 }
 
+impl Request<'_> {
+    pub fn send_hello<W: AsFd + std::io::Write>(w: W, hello: &Hello) -> Result<Sequence> {
+	let hdr = Header::new(RequestCode::Hello, Handle::NONE, hello);
+	let seq = hdr.seq()?;
+
+	send_vectored_all(w, &[ IoSlice::new(hdr.as_repr_bytes()),
+				IoSlice::new(hello.as_repr_bytes()) ])?;
+
+	Ok(seq)
+    }
+}
+
+impl Request<'_> {
+    pub fn send_ping<W: AsFd + std::io::Write>(w: W) -> Result<Sequence> {
+	let hdr = Header::new(RequestCode::Ping, Handle::NONE, &());
+	let seq = hdr.seq()?;
+
+	send_all(w, hdr.as_repr_bytes())?;
+
+	Ok(seq)
+    }
+}
+
 mod compile_test {
     #![allow(dead_code)]
     use super::*;
@@ -423,7 +543,7 @@ mod compile_test {
     fn test_00() {
 	use core::mem::size_of;
 
-	const _: () = assert!(size_of::<Header>() == 16);
+	const _: () = assert!(size_of::<Header>() == 24);
 	const _: () = assert!(size_of::<Open>() == 8);
     }
 }
@@ -108,10 +108,12 @@ impl From<cuse_ffi::fh_flags> for FhFlags {
 }
 
 
+#[allow(dead_code)]
 #[repr(transparent)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
 pub struct PollFlags(be32);
 
+#[allow(dead_code)]
 impl PollFlags {
     pub const SCHEDULE_NOTIFY: Self = Self::bit(0);
 
@@ -5,6 +5,7 @@ use std::time::Duration;
 
 use super::io::{recv_to, recv_exact_timeout, send_all, send_vectored_all};
 use super::ioctl::Arg;
+use super::hello::Hello;
 use super::{Sequence, Result, AsReprBytes, AsReprBytesMut, TIMEOUT_READ, Error};
 use super::endian::*;
 
@@ -20,6 +21,8 @@ pub enum ResponseCode {
     Poll = 5,
     PollWakeup = 6,
     PollWakeup1 = 7,
+    HelloAck = 8,
+    Pong = 9,
 }
 
 impl ResponseCode {
@@ -36,6 +39,8 @@ impl ResponseCode {
 	    5	=> Self::Poll,
 	    6	=> Self::PollWakeup,
 	    7	=> Self::PollWakeup1,
+	    8	=> Self::HelloAck,
+	    9	=> Self::Pong,
 
 	    _	=> return None,
 	})
@@ -141,10 +146,12 @@ pub enum Response {
     Poll(PollEvent),
     PollWakeup(Vec<u64>),
     PollWakeup1(u64),
+    HelloAck(Hello),
+    Pong,
 }
 
 impl Response {
-    const MAX_SZ: usize = 0x1_0000;
+    const MAX_SZ: usize = super::MAX_PAYLOAD_SIZE;
 
     pub fn send_poll<W: AsFd + std::io::Write>(w: W, seq: Sequence, ev: PollEvent) -> Result<()> {
 	trace!("send_poll({seq:?}, {ev:x})");
@@ -275,6 +282,27 @@ impl Response {
 	Ok(())
     }
 
+    pub fn send_hello_ack<W: AsFd + std::io::Write>(w: W, seq: Sequence, hello: &Hello) -> Result<()> {
+	trace!("send_hello_ack({seq:?}, {hello:?})");
+
+	let hdr = Header::new(ResponseCode::HelloAck, seq, hello);
+
+	send_vectored_all(w, &[ IoSlice::new(hdr.as_repr_bytes()),
+				IoSlice::new(hello.as_repr_bytes()) ])?;
+
+	Ok(())
+    }
+
+    pub fn send_pong<W: AsFd + std::io::Write>(w: W, seq: Sequence) -> Result<()> {
+	trace!("send_pong({seq:?})");
+
+	let hdr = Header::new(ResponseCode::Pong, seq, &());
+
+	send_all(w, hdr.as_repr_bytes())?;
+
+	Ok(())
+    }
+
     pub fn send_err<W: AsFd + std::io::Write>(w: W, seq: Sequence, err: nix::Error) -> Result<()> {
 	trace!("send_err({seq:?}, {err})");
 
@@ -375,6 +403,19 @@ impl Response {
 
 		Self::PollWakeup(khs.iter().map(|kh| (*kh).into()).collect())
 	    }
+
+	    ResponseCode::HelloAck			=> {
+		let hello = recv_to(&r, Hello::uninit(), &mut rx_len)?;
+		let mut tmp = Alloc::new(*rx_len.as_ref().unwrap());
+
+		// skip fields which were appended by later protocol versions
+		recv_to(&r, tmp.as_uninit(), &mut rx_len)?;
+
+		Self::HelloAck(hello)
+	    }
+
+	    ResponseCode::Pong				=>
+		Self::Pong,
 	}))
     }
 
@@ -385,6 +426,12 @@ impl Response {
     pub fn recv<R: AsFd + std::io::Read>(r: R) -> Result<(Option<Sequence>, Self)> {
 	Self::recv_internal(r, None)
     }
+
+    /// Like [`Self::recv()`] but fails with `ETIMEDOUT` when no response
+    /// arrives within `to`
+    pub fn recv_timeout<R: AsFd + std::io::Read>(r: R, to: Option<Duration>) -> Result<(Option<Sequence>, Self)> {
+	Self::recv_internal(r, to)
+    }
 }
 
 #[repr(C)]
@@ -0,0 +1,264 @@
+//! Dispatches the requests of a client to the opened devices
+
+use std::collections::HashMap;
+use std::mem::MaybeUninit;
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use nix::fcntl::OFlag;
+
+use crate::proto::{self, Handle, Sequence};
+use crate::transport::Stream;
+
+use super::{Device, Op, Policy};
+
+/// A device which was opened with certain flags; it is shared by all
+/// handles which use these flags
+struct Opened {
+    fd:		Arc<OwnedFd>,
+    tx:		mpsc::Sender<Op>,
+    thread:	JoinHandle<()>,
+    users:	usize,
+}
+
+/// Returns whether the device was put into exclusive mode by TIOCEXCL
+fn is_exclusive(fd: &OwnedFd) -> bool {
+    let mut excl: nix::libc::c_int = 0;
+
+    let rc = unsafe {
+	nix::libc::ioctl(fd.as_raw_fd(), nix::libc::TIOCGEXCL, &mut excl)
+    };
+
+    rc == 0 && excl != 0
+}
+
+pub struct Connection {
+    path:	PathBuf,
+    conn:	Arc<Stream>,
+    session:	proto::Session,
+    policy:	Arc<Policy>,
+    /// time without a request after which the client is considered dead
+    timeout:	Option<Duration>,
+    /// opened devices; key are the open flags
+    devices:	HashMap<OFlag, Opened>,
+    handles:	HashMap<Handle, OFlag>,
+    /// threads of released devices
+    finished:	Vec<JoinHandle<()>>,
+}
+
+impl Connection {
+    pub fn new(path: PathBuf, conn: Stream, session: proto::Session,
+	       timeout: Option<Duration>, policy: Arc<Policy>) -> Self {
+	Self {
+	    path:	path,
+	    conn:	Arc::new(conn),
+	    session:	session,
+	    policy:	policy,
+	    timeout:	timeout,
+	    devices:	HashMap::new(),
+	    handles:	HashMap::new(),
+	    finished:	Vec::new(),
+	}
+    }
+
+    fn conn(&self) -> &Stream {
+	&self.conn
+    }
+
+    fn spawn(&self, dev: Device) -> crate::Result<Opened> {
+	let (tx, rx) = mpsc::channel();
+	let fd = dev.fd.clone();
+
+	let thread = std::thread::Builder::new()
+	    .name("device".to_string())
+	    .spawn(move || {
+		if let Err(e) = dev.run(rx) {
+		    warn!("device failed: {e:?}");
+		}
+	    })?;
+
+	Ok(Opened {
+	    fd:		fd,
+	    tx:		tx,
+	    thread:	thread,
+	    users:	1,
+	})
+    }
+
+    fn open(&mut self, fh: Handle, seq: Sequence, flags: OFlag) -> crate::Result<()> {
+	if fh == Handle::NONE || self.handles.contains_key(&fh) {
+	    warn!("handle {fh:?} already in use");
+	    seq.send_err(self.conn(), nix::Error::EINVAL)?;
+	    return Ok(());
+	}
+
+	match self.devices.get_mut(&flags) {
+	    Some(dev) if is_exclusive(&dev.fd)	=> {
+		seq.send_err(self.conn(), nix::Error::EBUSY)?;
+		return Ok(());
+	    }
+
+	    Some(dev)				=> {
+		debug!("sharing device with {} other handles", dev.users);
+		dev.users += 1;
+	    }
+
+	    None				=> {
+		let dev = match Device::open(&self.path, flags, self.conn.clone(), self.session,
+					   self.policy.clone()) {
+		    Ok(dev)	=> dev,
+		    Err(e)	=> {
+			seq.send_err(self.conn(), e)?;
+			return Ok(());
+		    }
+		};
+
+		let dev = self.spawn(dev)?;
+
+		self.devices.insert(flags, dev);
+	    }
+	}
+
+	self.handles.insert(fh, flags);
+	seq.send_ok(self.conn())?;
+
+	Ok(())
+    }
+
+    fn release(&mut self, fh: Handle, seq: Sequence) -> crate::Result<()> {
+	let Some(flags) = self.handles.remove(&fh) else {
+	    warn!("no such handle {fh:?}");
+	    seq.send_err(self.conn(), nix::Error::EBADF)?;
+	    return Ok(());
+	};
+
+	let dev = self.devices.get_mut(&flags).unwrap();
+
+	dev.users -= 1;
+
+	if dev.users > 0 {
+	    seq.send_ok(self.conn())?;
+	    return Ok(());
+	}
+
+	let Opened { fd, tx, thread, .. } = self.devices.remove(&flags).unwrap();
+
+	// the device thread holds the last reference and closes the device
+	// before acknowledging the release
+	drop(fd);
+
+	if tx.send(Op::Release(seq)).is_err() {
+	    seq.send_ok(self.conn())?;
+	}
+
+	self.finished.retain(|t| !t.is_finished());
+	self.finished.push(thread);
+
+	Ok(())
+    }
+
+    fn dispatch(&mut self, fh: Handle, op: proto::Request) -> crate::Result<()> {
+	use proto::Request as R;
+
+	let (seq, op) = match op {
+	    R::Hello(seq, _)		=> {
+		warn!("handshake already done");
+		seq.send_err(self.conn(), nix::Error::EPROTO)?;
+		return Ok(());
+	    }
+
+	    R::Ping(seq)		=> {
+		proto::Response::send_pong(self.conn(), seq)?;
+		return Ok(());
+	    }
+
+	    R::Open(seq, args)		=> return self.open(fh, seq, args.flags.as_ffi()),
+	    R::Release(seq)		=> return self.release(fh, seq),
+
+	    R::Write(seq, wrinfo, data)	=> (Some(seq), Op::Write(seq, wrinfo, data.to_vec())),
+	    R::Read(seq, rdinfo)	=> (Some(seq), Op::Read(seq, rdinfo)),
+	    R::Ioctl(seq, ioinfo, arg)	=> (Some(seq), Op::Ioctl(seq, ioinfo, arg)),
+	    R::Poll(seq, parm)		=> (Some(seq), Op::Poll(seq, parm)),
+	    // there is no response for interrupts
+	    R::Interrupt(seq)		=> (None, Op::Interrupt(seq)),
+	};
+
+	let dev = self.handles.get(&fh)
+	    .and_then(|flags| self.devices.get(flags));
+
+	match (dev.map(|dev| dev.tx.send(op)), seq) {
+	    (Some(Ok(_)), _)		=> {},
+	    (_, None)			=> debug!("ignoring interrupt for handle {fh:?}"),
+
+	    (_, Some(seq))		=> {
+		warn!("no device for handle {fh:?}");
+		seq.send_err(self.conn(), nix::Error::EBADF)?;
+	    }
+	}
+
+	Ok(())
+    }
+
+    pub fn run(mut self) -> crate::Result<()> {
+	let mut buf: [MaybeUninit<u8>; proto::MAX_MSG_SIZE] = [MaybeUninit::uninit(); proto::MAX_MSG_SIZE];
+
+	let res = loop {
+	    let (fh, op) = match proto::Request::recv_timeout(&*self.conn, &mut buf, self.timeout) {
+		Ok(req)		=> req,
+
+		// client closed the connection
+		Err(proto::Error::Io(e)) if e.raw_os_error() == Some(nix::libc::EPIPE)	=>
+		    break Ok(()),
+
+		Err(proto::Error::Io(e)) if e.raw_os_error() == Some(nix::libc::ETIMEDOUT)	=> {
+		    warn!("no request from client within {:?}", self.timeout.unwrap());
+		    let _ = self.conn.shutdown(std::net::Shutdown::Both);
+		    break Err(proto::Error::PeerDead.into());
+		}
+
+		Err(e)		=> break Err(e.into()),
+	    };
+
+	    debug!("got {op:?} for {fh:?}");
+
+	    if let Err(e) = self.dispatch(fh, op) {
+		break Err(e);
+	    }
+	};
+
+	// dropping the channels terminates the device threads
+	let threads = self.devices.drain()
+	    .map(|(_, dev)| dev.thread)
+	    .chain(self.finished.drain(..))
+	    .collect::<Vec<_>>();
+
+	for t in threads {
+	    let _ = t.join();
+	}
+
+	res
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_exclusive() {
+	let pty = nix::pty::openpty(None, None).unwrap();
+	let fd = pty.slave;
+
+	assert!(!is_exclusive(&fd));
+
+	assert_eq!(unsafe { nix::libc::ioctl(fd.as_raw_fd(), nix::libc::TIOCEXCL) }, 0);
+	assert!(is_exclusive(&fd));
+
+	assert_eq!(unsafe { nix::libc::ioctl(fd.as_raw_fd(), nix::libc::TIOCNXCL) }, 0);
+	assert!(!is_exclusive(&fd));
+    }
+}
@@ -1,25 +1,105 @@
 mod read;
 mod poll;
+mod worker;
+mod connection;
+mod rfc2217;
+mod policy;
 
-use std::mem::MaybeUninit;
-use std::os::fd::{OwnedFd, FromRawFd, AsRawFd};
+use std::os::fd::{OwnedFd, FromRawFd, AsRawFd, AsFd, BorrowedFd};
 use std::path::Path;
-use std::net::TcpStream;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
 use std::thread::scope;
 
 use nix::fcntl::OFlag;
 
-use crate::proto::ioctl::Arg;
+use ensc_ioctl_ffi::{ffi as ioctl_ffi, ffi::ioctl};
+use ensc_ioctl_ffi::abi::Abi;
+
+use crate::proto::ioctl::{registry, indirect, Arg};
 use crate::proto::{self, Sequence};
+use crate::proto::hello::Endian;
+use crate::Error;
+use crate::transport::Stream;
+
+pub use connection::Connection;
+pub use rfc2217::Rfc2217;
+pub use policy::{ Policy, RawIoctl };
+
+/// Executes an ioctl on the device and decodes its result; the buffers
+/// of indirect ioctls are limited to `max_size` bytes
+fn run_ioctl(fd: BorrowedFd, cmd: u32, arg: Arg, max_size: usize) -> crate::Result<(u64, Arg)> {
+    if let Some(desc) = indirect::lookup(cmd.into()) {
+	return run_ioctl_indirect(fd, cmd, desc, arg, max_size);
+    }
+
+    let (cmd, arg, buf) = arg.encode(cmd)?;
+
+    let rc = unsafe {
+	nix::libc::ioctl(fd.as_raw_fd(), cmd as u64, arg)
+    };
+
+    if rc < 0 {
+	let err = nix::Error::last();
+
+	warn!("ioctl ({cmd:x}, {arg:?}) failed: {err}");
+	return Err(err.into());
+    }
+
+    let res_arg = Arg::decode(cmd, arg, &buf, proto::ioctl::Source::Device)?;
+
+    Ok((rc as u64, res_arg))
+}
+
+/// Executes an ioctl whose argument contains pointers to further buffers
+fn run_ioctl_indirect(fd: BorrowedFd, cmd: u32, desc: &indirect::Desc,
+		      arg: Arg, max_size: usize) -> crate::Result<(u64, Arg)> {
+    let Arg::Raw(data) = arg else {
+	return Err(proto::Error::BadIoctlParam.into());
+    };
+
+    let mut mem = indirect::Memory::new(desc, cmd.into(), &data, max_size)?;
+
+    let rc = unsafe {
+	nix::libc::ioctl(fd.as_raw_fd(), cmd as u64, mem.arg())
+    };
+
+    if rc < 0 {
+	let err = nix::Error::last();
+
+	warn!("ioctl ({cmd:x}) failed: {err}");
+	return Err(err.into());
+    }
+
+    Ok((rc as u64, mem.into_output()))
+}
+
+/// Request which is routed by the [`Connection`] to a [`Device`]
+#[derive(Debug)]
+enum Op {
+    Release(Sequence),
+    Write(Sequence, proto::request::Write, Vec<u8>),
+    Read(Sequence, proto::request::Read),
+    Ioctl(Sequence, proto::request::Ioctl, Arg),
+    Poll(Sequence, proto::request::Poll),
+    Interrupt(Sequence),
+}
 
+/// An opened file of the real device; it can be shared by several
+/// handles of the client
 pub struct Device {
-    fd:		OwnedFd,
-    conn:	TcpStream,
-    allow_raw:	bool,
+    fd:		Arc<OwnedFd>,
+    conn:	Arc<Stream>,
+    session:	proto::Session,
+    policy:	Arc<Policy>,
+    /// the client attached another line discipline
+    ldisc_set:	AtomicBool,
 }
 
 impl Device {
-    pub fn open<P: AsRef<Path>>(p: P, seq: Sequence, flags: OFlag, conn: TcpStream) -> crate::Result<Self> {
+    fn open<P: AsRef<Path>>(p: P, flags: OFlag, conn: Arc<Stream>,
+			    session: proto::Session, policy: Arc<Policy>) -> nix::Result<Self> {
 	use nix::sys::stat::Mode;
 
 	let p = p.as_ref();
@@ -27,33 +107,32 @@ impl Device {
 	let fd = nix::fcntl::open(
 	    p,
 	    OFlag::O_CLOEXEC | OFlag::O_NONBLOCK | OFlag::O_NOCTTY | flags,
-	    Mode::empty());
-
-	let fd = match fd {
-	    Ok(fd)	=> unsafe { OwnedFd::from_raw_fd(fd) },
-	    Err(e)	=> {
-		error!("failed to open {p:?}: {e:?}");
-		proto::Response::send_err(&conn, seq, e)?;
-		return Err(e.into());
-	    }
-	};
-
-	seq.send_ok(&conn)?;
+	    Mode::empty())
+	    .inspect_err(|e| error!("failed to open {p:?}: {e:?}"))?;
 
 	Ok(Self {
-	    fd:		fd,
+	    fd:		Arc::new(unsafe { OwnedFd::from_raw_fd(fd) }),
 	    conn:	conn,
-	    allow_raw:	false,
+	    session:	session,
+	    policy:	policy,
+	    ldisc_set:	AtomicBool::new(false),
 	})
     }
 
-    pub fn run(self) -> crate::Result<()> {
+    fn conn(&self) -> &Stream {
+	&self.conn
+    }
+
+    /// Processes requests until the last handle releases the device or
+    /// the connection terminates
+    fn run(self, ops: mpsc::Receiver<Op>) -> crate::Result<()> {
 	debug!("running device");
 
 	let read = read::Read::new(&self)?;
 	let poll = poll::Poll::new(&self)?;
+	let workers = worker::Workers::new(&self)?;
 
-	scope(|s| {
+	let res = scope(|s| {
 	    std::thread::Builder::new()
 		.name("read".to_string())
 		.spawn_scoped(s, || read.run())?;
@@ -62,52 +141,89 @@ impl Device {
 		.name("poll".to_string())
 		.spawn_scoped(s, || poll.run())?;
 
-	    self.main(&read, &poll)
-	})
-    }
+	    for _ in 0..worker::NUM_WORKERS {
+		std::thread::Builder::new()
+		    .name("ioctl".to_string())
+		    .spawn_scoped(s, || workers.run())?;
+	    }
 
-    fn main(&self, read: &read::Read, poll: &poll::Poll) -> crate::Result<()> {
-	debug!("running device");
+	    std::thread::Builder::new()
+		.name("ioctl-watch".to_string())
+		.spawn_scoped(s, || workers.watch())?;
+
+	    let res = self.main(&read, &poll, &workers, ops);
+
+	    // the scope waits for the helper threads; stop them so that the
+	    // device is closed when the connection terminates
+	    read.close();
+	    poll.close();
+	    workers.close();
+
+	    res
+	});
 
-	let mut buf: [MaybeUninit<u8>; proto::MAX_MSG_SIZE] = [MaybeUninit::uninit(); proto::MAX_MSG_SIZE];
+	drop(read);
+	drop(poll);
+
+	let conn = self.conn.clone();
+
+	self.reset_ldisc();
+
+	// close the device before acknowledging the release
+	drop(self);
+
+	match res {
+	    Ok(Some(seq))	=> seq.send_ok(&*conn),
+	    Ok(None)		=> Ok(()),
+	    Err(e)		=> return Err(e),
+	}?;
 
-	loop {
-	    let op = proto::Request::recv(&self.conn, &mut buf)?;
+	Ok(())
+    }
 
+    /// Returns the sequence of the final release request
+    fn main(&self, read: &read::Read, poll: &poll::Poll, workers: &worker::Workers,
+	    ops: mpsc::Receiver<Op>) -> crate::Result<Option<Sequence>> {
+	for op in ops {
 	    debug!("got {op:?}");
 
 	    match op {
-		proto::Request::Open(seq, _) => {
-		    warn!("can not open an already opened device");
-		    seq.send_err(&self.conn, nix::Error::EINVAL)?;
-		}
-
-		proto::Request::Release(seq) => {
-		    seq.send_ok(&self.conn)?;
-		    break Ok(());
-		}
+		Op::Release(seq)		=> return Ok(Some(seq)),
 
-		proto::Request::Write(seq, wrinfo, data)	=> {
-		    self.write(seq, wrinfo, data)?;
+		Op::Write(seq, wrinfo, data)	=> {
+		    self.write(seq, wrinfo, &data)?;
 		}
 
-		proto::Request::Read(seq, rdinfo)	=> {
+		Op::Read(seq, rdinfo)		=> {
 		    self.read(read, seq, rdinfo)?;
 		}
 
-		proto::Request::Ioctl(seq, ioinfo, arg)	=> {
-		    self.ioctl(seq, ioinfo.cmd.into(), arg)?;
+		Op::Ioctl(seq, ioinfo, arg)	=> match ioinfo.cmd() {
+		    // native numbers of another family have a different meaning
+		    Some(_) if ioinfo.is_native() && self.session.abi != Abi::NATIVE	=> {
+			warn!("native ioctl {:?} from {:?} client", ioinfo, self.session.abi);
+			proto::Response::send_err(self.conn(), seq, nix::Error::ENOTTY)?;
+		    }
+		    Some(cmd)	=> workers.push_request((seq, cmd.as_numeric(), arg)),
+		    None	=> {
+			warn!("unsupported ioctl {:?}", ioinfo);
+			proto::Response::send_err(self.conn(), seq, nix::Error::ENOTTY)?;
+		    }
 		},
 
-		proto::Request::Poll(seq, parm)		=> {
+		Op::Poll(seq, parm)		=> {
 		    self.poll(poll, seq, parm.kh.into(), parm.flags.into(), parm.events.into())?;
 		}
 
-		proto::Request::Interrupt(seq) => {
+		Op::Interrupt(seq)		=> {
 		    read.do_intr(Some(seq));
+		    workers.do_intr(Some(seq));
 		}
 	    }
 	}
+
+	// connection terminated
+	Ok(None)
     }
 
     fn read(&self, read: &read::Read, seq: Sequence, rdinfo: proto::request::Read)
@@ -115,7 +231,9 @@ impl Device {
     {
 	trace!("read#{seq:?}@{rdinfo:?}");
 
-	let req = (seq, rdinfo.size.as_native() as usize);
+	// the peer can not receive larger responses
+	let size = (rdinfo.size.as_native() as usize).min(self.session.max_msg_size);
+	let req = (seq, size);
 
 	match rdinfo.fh_flags.is_nonblock() {
 	    true	=> read.read_nonblock(req),
@@ -133,12 +251,12 @@ impl Device {
 	// the call
 	let l = match wrinfo.offset.into() {
 	    0		=> nix::unistd::write(self.fd.as_raw_fd(), data),
-	    offs	=> nix::sys::uio::pwrite(&self.fd, data, offs as nix::libc::off_t),
+	    offs	=> nix::sys::uio::pwrite(&*self.fd, data, offs as nix::libc::off_t),
 	};
 
 	match l {
-	    Ok(l)	=> proto::Response::send_write(&self.conn, seq, l as u32),
-	    Err(e)	=> proto::Response::send_err(&self.conn, seq, e),
+	    Ok(l)	=> proto::Response::send_write(self.conn(), seq, l as u32),
+	    Err(e)	=> proto::Response::send_err(self.conn(), seq, e),
 	}?;
 
 	Ok(())
@@ -147,31 +265,122 @@ impl Device {
     fn ioctl(&self, seq: Sequence, cmd: u32, arg: Arg) -> crate::Result<()> {
 	trace!("ioctl({seq:?}, {cmd:x}, {arg:?})");
 
-	if arg.is_raw() && !self.allow_raw {
-	    warn!("raw ioctl {arg:?} not allowed");
-	    proto::Response::send_err(&self.conn, seq, nix::Error::EPERM)?;
+	// raw and indirect arguments are not translated between the
+	// architecture families and byte orders; indirect ones contain
+	// pointers
+	let same_abi = self.session.abi == Abi::NATIVE && self.session.endian == Endian::native();
+	let same_ptr = self.session.ptr_width == indirect::PTR;
+
+	let allowed = match (registry::lookup(cmd.into()), indirect::lookup(cmd.into())) {
+	    (Some(def), _)	=> def.allowed && !arg.is_raw(),
+	    (None, Some(_))	=> same_abi && same_ptr && self.policy.allow_indirect(cmd.into(), &arg),
+	    (None, None)	=> same_abi && self.policy.allow_raw(cmd.into(), &arg),
+	};
+
+	if !allowed {
+	    warn!("ioctl {:?} with {arg:?} not allowed", ioctl::from(cmd));
+	    proto::Response::send_err(self.conn(), seq, nix::Error::EPERM)?;
 
 	    return Ok(())
 	}
 
-	let (cmd, arg, buf) = arg.encode(cmd)?;
+	if ioctl::from(cmd) == ioctl::TIOCSSERIAL {
+	    match self.set_serial(arg) {
+		Ok(())	=> proto::Response::send_ioctl(self.conn(), seq, 0, Arg::None),
+		Err(e)	=> proto::Response::send_err(self.conn(), seq, e),
+	    }?;
+
+	    return Ok(());
+	}
+
+	let arg = match ioctl::from(cmd) {
+	    ioctl::TIOCSETD	=> self.restrict_ldisc(arg),
+	    _			=> Ok(arg),
+	};
 
-	let rc = unsafe {
-	    nix::libc::ioctl(self.fd.as_raw_fd(), cmd as u64, arg)
+	let arg = match arg {
+	    Ok(arg)	=> arg,
+	    Err(e)	=> {
+		proto::Response::send_err(self.conn(), seq, e)?;
+		return Ok(());
+	    }
 	};
 
-	if rc < 0 {
-	    warn!("ioctl ({cmd:x}, {arg:?}) failed: {rc}");
-	    proto::Response::send_err(&self.conn, seq, nix::Error::last())
-	} else {
-	    let res_arg = Arg::decode(cmd, arg, &buf, proto::ioctl::Source::Device)?;
+	let res = worker::interruptible(|| run_ioctl(self.fd.as_fd(), cmd, arg,
+								 self.session.max_msg_size));
 
-	    proto::Response::send_ioctl(&self.conn, seq, rc as u64, res_arg)
+	if res.is_ok() && ioctl::from(cmd) == ioctl::TIOCSETD {
+	    self.ldisc_set.store(true, Ordering::Relaxed);
+	}
+
+	match res {
+	    Ok((rc, arg))		=> proto::Response::send_ioctl(self.conn(), seq, rc, arg),
+	    Err(Error::Nix(e))		=> proto::Response::send_err(self.conn(), seq, e),
+	    Err(e)			=> return Err(e),
 	}?;
 
 	Ok(())
     }
 
+    /// Executes a `TIOCSSERIAL` request after applying the [`Policy`].
+    /// The merged settings are passed to the kernel directly; converting
+    /// them back into the wire format would lose `iomem_base`.
+    fn set_serial(&self, arg: Arg) -> nix::Result<()> {
+	let Arg::Serial(req) = arg else {
+	    return Err(nix::Error::EINVAL);
+	};
+
+	let mut cur = ioctl_ffi::serial_struct::default();
+
+	nix::errno::Errno::result(unsafe {
+	    nix::libc::ioctl(self.fd.as_raw_fd(), nix::libc::TIOCGSERIAL, &mut cur)
+	})?;
+
+	let req = req.into_os();
+	let res = self.policy.serial(cur, req);
+
+	if res.flags != req.flags {
+	    debug!("TIOCSSERIAL: ignoring flags {:x}", res.flags ^ req.flags);
+	}
+
+	nix::errno::Errno::result(unsafe {
+	    nix::libc::ioctl(self.fd.as_raw_fd(), nix::libc::TIOCSSERIAL, &res)
+	})?;
+
+	Ok(())
+    }
+
+    /// Applies the [`Policy`] to a `TIOCSETD` request
+    fn restrict_ldisc(&self, arg: Arg) -> nix::Result<Arg> {
+	let Arg::Int(ldisc) = arg else {
+	    return Err(nix::Error::EINVAL);
+	};
+
+	if !self.policy.allow_ldisc(ldisc.as_native()) {
+	    warn!("TIOCSETD: line discipline {} not allowed", ldisc.as_native());
+	    return Err(nix::Error::EPERM);
+	}
+
+	Ok(arg)
+    }
+
+    /// Restores `N_TTY` when the client attached another line
+    /// discipline; it would stay active after closing the device when
+    /// the tty is still used by other processes
+    fn reset_ldisc(&self) {
+	if !self.ldisc_set.load(Ordering::Relaxed) {
+	    return;
+	}
+
+	let ldisc = ioctl_ffi::N_TTY;
+
+	if let Err(e) = nix::errno::Errno::result(unsafe {
+	    nix::libc::ioctl(self.fd.as_raw_fd(), nix::libc::TIOCSETD, &ldisc)
+	}) {
+	    warn!("failed to reset line discipline: {e:?}");
+	}
+    }
+
     fn poll(&self, poll: &poll::Poll, seq: Sequence, kh: u64, flags: u32, events: u32) -> crate::Result<()> {
 	trace!("poll({seq:?}, {kh}, {flags:x}, {events:?})");
 
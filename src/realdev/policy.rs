@@ -0,0 +1,237 @@
+//! Restrictions of the server for requests of clients
+
+use ensc_ioctl_ffi::ffi as ioctl_ffi;
+use ioctl_ffi::{ ioctl, serial_struct };
+
+use crate::proto::ioctl::Arg;
+
+/// Ioctls which are passed through to the device without knowing their
+/// semantics
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawIoctl {
+    Cmd(u32),
+    /// all ioctls with this type (the 8 bit "magic" number)
+    Type(u8),
+    /// inclusive range of ioctl numbers
+    Range(u32, u32),
+}
+
+fn parse_num(s: &str) -> Result<u32, String> {
+    let res = match s.strip_prefix("0x") {
+	Some(hex)	=> u32::from_str_radix(hex, 16),
+	None		=> s.parse(),
+    };
+
+    res.map_err(|e| format!("bad number '{s}': {e}"))
+}
+
+impl std::str::FromStr for RawIoctl {
+    type Err = String;
+
+    /// Parses `NUM`, `NUM-NUM` or `type:NUM`; numbers are either decimal
+    /// or hexadecimal with a `0x` prefix
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+	let s = s.trim();
+
+	if let Some(tp) = s.strip_prefix("type:") {
+	    return parse_num(tp)?
+		.try_into()
+		.map(Self::Type)
+		.map_err(|_| format!("bad ioctl type '{tp}'"));
+	}
+
+	match s.split_once('-') {
+	    None		=> parse_num(s).map(Self::Cmd),
+	    Some((a, b))	=> match (parse_num(a)?, parse_num(b)?) {
+		(a, b) if a <= b	=> Ok(Self::Range(a, b)),
+		_			=> Err(format!("empty ioctl range '{s}'")),
+	    },
+	}
+    }
+}
+
+impl RawIoctl {
+    pub fn matches(self, cmd: ioctl) -> bool {
+	let code = cmd.as_numeric();
+
+	match self {
+	    Self::Cmd(c)	=> code == c,
+	    Self::Type(tp)	=> cmd.get_type() == tp,
+	    Self::Range(a, b)	=> (a..=b).contains(&code),
+	}
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Policy {
+    /// `ASYNC_*` flags of `TIOCSSERIAL` which can be changed by clients
+    pub serial_flags:	u32,
+    /// unknown ioctls which are passed through to the device
+    pub raw_ioctls:	Vec<RawIoctl>,
+    /// line disciplines which can be attached by `TIOCSETD` besides
+    /// `N_TTY`
+    pub ldiscs:		Vec<u32>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+	Self {
+	    serial_flags:	(ioctl_ffi::ASYNC_LOW_LATENCY | ioctl_ffi::ASYNC_SPD_MASK) as u32,
+	    raw_ioctls:		Vec::new(),
+	    ldiscs:		Vec::new(),
+	}
+    }
+}
+
+impl Policy {
+    /// Merges the `TIOCSSERIAL` request of a client into the current
+    /// settings `cur` of the device.  Only the allowed flags and (when
+    /// the speed flags are allowed) the custom divisor are taken from
+    /// `req`.
+    pub fn serial(&self, cur: serial_struct, req: serial_struct) -> serial_struct {
+	let mask = self.serial_flags as nix::libc::c_int;
+
+	let custom_divisor = match mask & ioctl_ffi::ASYNC_SPD_MASK {
+	    0	=> cur.custom_divisor,
+	    _	=> req.custom_divisor,
+	};
+
+	serial_struct {
+	    flags:		(cur.flags & !mask) | (req.flags & mask),
+	    custom_divisor:	custom_divisor,
+	    .. cur
+	}
+    }
+
+    /// Checks whether the unknown ioctl `cmd` can be passed through to
+    /// the device.  Besides being listed in `raw_ioctls`, it must carry
+    /// direction and size bits so that the buffers can be copied without
+    /// knowing its semantics.
+    pub fn allow_raw(&self, cmd: ioctl, arg: &Arg) -> bool {
+	let size = cmd.get_size();
+
+	if !cmd.is_io() || size == 0 {
+	    return false;
+	}
+
+	if !self.raw_ioctls.iter().any(|r| r.matches(cmd)) {
+	    return false;
+	}
+
+	match arg {
+	    Arg::Raw(data)	=> cmd.is_write() && data.len() <= size,
+	    Arg::None		=> !cmd.is_write(),
+	    _			=> false,
+	}
+    }
+
+    /// Checks whether the line discipline `ldisc` can be attached
+    pub fn allow_ldisc(&self, ldisc: u32) -> bool {
+	ldisc == ioctl_ffi::N_TTY as u32 || self.ldiscs.contains(&ldisc)
+    }
+
+    /// Checks whether the ioctl `cmd` with embedded pointers can be
+    /// executed.  The server rebuilds the pointers from the descriptor
+    /// of the ioctl, but it must be listed in `raw_ioctls` because its
+    /// effects on the device are unknown.
+    pub fn allow_indirect(&self, cmd: ioctl, arg: &Arg) -> bool {
+	matches!(arg, Arg::Raw(_)) && self.raw_ioctls.iter().any(|r| r.matches(cmd))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::proto::ioctl::Serial;
+    use super::*;
+
+    #[test]
+    fn test_serial() {
+	let mut iomem = [0u8; 8];
+
+	let cur = serial_struct {
+	    flags:		0x0040,
+	    baud_base:		115200,
+	    custom_divisor:	0,
+	    port:		0x3f8,
+	    irq:		4,
+	    iomem_base:		iomem.as_mut_ptr(),
+	    iomem_reg_shift:	2,
+	    .. Default::default()
+	};
+
+	// like received from the client; the wire format does not carry
+	// 'iomem_base'
+	let req = Serial::from_os(&serial_struct {
+	    flags:		ioctl_ffi::ASYNC_LOW_LATENCY | ioctl_ffi::ASYNC_SPD_CUST,
+	    baud_base:		1,
+	    custom_divisor:	3,
+	    port:		0x2f8,
+	    irq:		3,
+	    iomem_base:		iomem[4..].as_mut_ptr(),
+	    .. Default::default()
+	}).into_os();
+
+	let res = Policy::default().serial(cur, req);
+
+	assert_eq!(res.flags, 0x0040 | ioctl_ffi::ASYNC_LOW_LATENCY | ioctl_ffi::ASYNC_SPD_CUST);
+	assert_eq!(res.baud_base, 115200);
+	assert_eq!(res.custom_divisor, 3);
+	assert_eq!((res.port, res.irq, res.iomem_reg_shift), (0x3f8, 4, 2));
+	assert_eq!(res.iomem_base, iomem.as_mut_ptr());
+
+	let res = Policy { serial_flags: 0, .. Default::default() }.serial(cur, req);
+
+	assert_eq!(res.flags, 0x0040);
+	assert_eq!(res.custom_divisor, 0);
+    }
+
+    #[test]
+    fn test_raw() {
+	let policy = Policy {
+	    raw_ioctls:	vec![ "0x80045601".parse().unwrap(),
+			      "type:0x57".parse().unwrap(),
+			      "0xc0085800-0xc00858ff".parse().unwrap() ],
+	    .. Default::default()
+	};
+
+	assert_eq!("type:0x100".parse::<RawIoctl>().ok(), None);
+	assert_eq!("0x10-0x1".parse::<RawIoctl>().ok(), None);
+	assert_eq!("17".parse::<RawIoctl>(), Ok(RawIoctl::Cmd(17)));
+
+	// _IOR('V', 1, u32)
+	assert!(policy.allow_raw(0x80045601.into(), &Arg::None));
+	assert!(!policy.allow_raw(0x80045601.into(), &Arg::Raw(vec![0; 4])));
+	assert!(!policy.allow_raw(0x80045602.into(), &Arg::None));
+
+	// _IOW('W', 1, u64)
+	assert!(policy.allow_raw(0x40085701.into(), &Arg::Raw(vec![0; 8])));
+	assert!(!policy.allow_raw(0x40085701.into(), &Arg::Raw(vec![0; 9])));
+	assert!(!policy.allow_raw(0x40085701.into(), &Arg::RawArg(0.into())));
+
+	// _IOWR('X', 1, u64)
+	assert!(policy.allow_raw(0xc0085801.into(), &Arg::Raw(vec![0; 8])));
+
+	// legacy numbering without direction and size
+	let policy = Policy {
+	    raw_ioctls:	vec![ RawIoctl::Type(0x54) ],
+	    .. Default::default()
+	};
+
+	assert!(!policy.allow_raw(0x5401.into(), &Arg::None));
+	assert!(!policy.allow_raw(0x5401.into(), &Arg::RawArg(0.into())));
+    }
+
+    #[test]
+    fn test_ldisc() {
+	// N_GSM0710
+	let policy = Policy {
+	    ldiscs:	vec![ 21 ],
+	    .. Default::default()
+	};
+
+	assert!(policy.allow_ldisc(ioctl_ffi::N_TTY as u32));
+	assert!(policy.allow_ldisc(21));
+	assert!(!policy.allow_ldisc(1));
+	assert!(!Policy::default().allow_ldisc(21));
+    }
+}
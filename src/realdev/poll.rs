@@ -75,7 +75,7 @@ pub struct PollInner<'a> {
 
     khs:		HashMap<Kh, EpollFlags>,
 
-    fd_epoll:		epoll::Epoll,
+    fd_epoll:		Option<epoll::Epoll>,
 }
 
 impl <'a> PollInner<'a> {
@@ -88,7 +88,7 @@ impl <'a> PollInner<'a> {
 	    device:	dev,
 	    fd_rx:	Some(unsafe { OwnedFd::from_raw_fd(pipe.0) }),
 	    fd_tx:	Some(unsafe { OwnedFd::from_raw_fd(pipe.1) }),
-	    fd_epoll:	efd,
+	    fd_epoll:	Some(efd),
 
 	    khs:	HashMap::new()
 	})
@@ -108,17 +108,17 @@ impl <'a> PollInner<'a> {
 	    self.khs.remove(kh);
 	}
 
-	let _ = proto::Response::send_poll_wakeup(&self.device.conn, &khs)
+	let _ = proto::Response::send_poll_wakeup(self.device.conn(), &khs)
 	    .map_err(|e| error!("failed to send wakeup: {e:?}"));
     }
 
     pub fn send_events(&self, seq: Sequence, ev: PollFlags) {
-	let _ = proto::Response::send_poll(&self.device.conn, seq, ev.bits() as ProtoEvent)
+	let _ = proto::Response::send_poll(self.device.conn(), seq, ev.bits() as ProtoEvent)
 	    .map_err(|e| error!("failed to send wakeup: {e:?}"));
     }
 
     fn send_err(&self, seq: Sequence, rc: nix::Error) {
-	let _ = proto::Response::send_err(&self.device.conn, seq, rc)
+	let _ = proto::Response::send_err(self.device.conn(), seq, rc)
 	    .map_err(|e| error!("failed to send err -{rc} response: {e:?}"));
     }
 
@@ -170,6 +170,11 @@ impl Poll<'_> {
 	self.0.read().fd_tx.is_some()
     }
 
+    /// Terminates the `run()` loop; closing the sync pipe wakes it up
+    pub fn close(&self) {
+	self.0.write().fd_tx = None;
+    }
+
     pub fn poll(&self, req: (Sequence, Kh, ProtoEvent)) {
 	trace!("poll{req:?}");
 
@@ -202,6 +207,7 @@ impl Poll<'_> {
 
 	match nix::unistd::read(fd.as_raw_fd(), &mut tmp) {
 	    Ok(1)	=> trace!("received sync char {tmp:?}"),
+	    Ok(0)	=> trace!("sync pipe closed"),
 	    Ok(c)	=> warn!("unexpected number {c} of chars received"),
 	    Err(e)	=> warn!("sync rx failed: {e:?}"),
 	}
@@ -212,7 +218,9 @@ impl Poll<'_> {
 	let ev_ser  = EpollEvent::new(EpollFlags::EPOLLIN | EpollFlags::EPOLLOUT |
 				      EpollFlags::EPOLLPRI | EpollFlags::EPOLLET, TOK_SER);
 
-	let efd = &self.0.read().fd_epoll;
+	// own the epoll fd and the RX side of the sync pipe; holding a lock
+	// while waiting would block the other operations
+	let efd = self.0.write().fd_epoll.take().unwrap();
 	let fd_sync = self.0.write().fd_rx.take().unwrap();
 
 	efd.add(&fd_sync, ev_sync)?;
@@ -39,6 +39,10 @@ impl <'a> ReadInner<'a> {
     }
 
     fn close_internal(&mut self) {
+	if self.fd_tx.is_none() {
+	    return;
+	}
+
 	self.do_intr(None);
 	self.send_sync();
 
@@ -65,13 +69,13 @@ impl <'a> ReadInner<'a> {
 
     fn send_data(&self, req: ReadRequest, buf: &[u8]) {
 	trace!("sending #{} bytes of data @{:?}", buf.len(), req.0);
-	let _ = proto::Response::send_read(&self.device.conn, req.0, buf)
+	let _ = proto::Response::send_read(self.device.conn(), req.0, buf)
 	    .map_err(|e| error!("failed to send data: {e:?}"));
     }
 
     fn send_sync_fd(fd: BorrowedFd) {
 	#[allow(clippy::single_match)]
-	match nix::unistd::write(fd.as_raw_fd(), &[ b'R' ]) {
+	match nix::unistd::write(fd.as_raw_fd(), b"R") {
 	    // TODO: what todo in error case?
 	    Err(e)	=> error!("failed to send sync signal: {e:?}"),
 	    _		=> (),
@@ -85,7 +89,7 @@ impl <'a> ReadInner<'a> {
     fn send_err(&self, seq: Sequence, rc: nix::Error) {
 	trace!("sending error {rc}@{seq:?}");
 
-	let _ = proto::Response::send_err(&self.device.conn, seq, rc)
+	let _ = proto::Response::send_err(self.device.conn(), seq, rc)
 	    .map_err(|e| error!("failed to send err -{rc} response: {e:?}"));
     }
 
@@ -154,6 +158,11 @@ impl Read<'_> {
 	self.0.read().fd_tx.is_some()
     }
 
+    /// Cancels pending requests and terminates the `run()` loop
+    pub fn close(&self) {
+	self.0.write().close_internal()
+    }
+
     fn next_request(&self) -> Option<ReadRequest> {
 	self.0.write().next_request()
     }
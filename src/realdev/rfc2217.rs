@@ -0,0 +1,472 @@
+//! RFC 2217 server which exports the device to standard telnet clients
+//! like pyserial or esptool
+
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::net::Shutdown;
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::time::Duration;
+
+use nix::fcntl::OFlag;
+use nix::libc;
+use nix::poll::{PollFd, PollFlags};
+use parking_lot::Mutex;
+
+use ensc_ioctl_ffi::ffi::{ioctl, serial_icounter_struct as SerialIcounter};
+use ensc_ioctl_ffi::termbits;
+
+use crate::proto::{self, be32, be64};
+use crate::proto::ioctl::{Arg, TermIOs};
+use crate::rfc2217::{self as rfc, Command, Origin, telnet};
+use crate::transport::Stream;
+
+use super::run_ioctl;
+
+/// Interval in which modem and line state are checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+const SIGNATURE: &[u8] = b"cuse2net";
+
+pub struct Rfc2217 {
+    fd:			OwnedFd,
+    conn:		Stream,
+    /// serializes writes to the connection
+    tx_lock:		Mutex<()>,
+    /// the connection terminated
+    done:		AtomicBool,
+    /// FLOWCONTROL-SUSPEND was received
+    suspended:		AtomicBool,
+    brk:		AtomicBool,
+    linestate_mask:	AtomicU8,
+    modemstate_mask:	AtomicU8,
+}
+
+impl Rfc2217 {
+    pub fn open<P: AsRef<Path>>(p: P, conn: Stream) -> crate::Result<Self> {
+	use nix::sys::stat::Mode;
+
+	let p = p.as_ref();
+
+	let fd = nix::fcntl::open(
+	    p,
+	    OFlag::O_CLOEXEC | OFlag::O_NONBLOCK | OFlag::O_NOCTTY | OFlag::O_RDWR,
+	    Mode::empty())
+	    .inspect_err(|e| error!("failed to open {p:?}: {e:?}"))?;
+
+	Ok(Self {
+	    fd:			unsafe { OwnedFd::from_raw_fd(fd) },
+	    conn:		conn,
+	    tx_lock:		Mutex::new(()),
+	    done:		AtomicBool::new(false),
+	    suspended:		AtomicBool::new(false),
+	    brk:		AtomicBool::new(false),
+	    // defaults from RFC 2217
+	    linestate_mask:	AtomicU8::new(0),
+	    modemstate_mask:	AtomicU8::new(0xff),
+	})
+    }
+
+    fn send(&self, data: &[u8]) -> std::io::Result<()> {
+	if data.is_empty() {
+	    return Ok(());
+	}
+
+	let _tx = self.tx_lock.lock();
+
+	(&self.conn).write_all(data)
+    }
+
+    fn ioctl(&self, cmd: ioctl, arg: Arg) -> crate::Result<Arg> {
+	run_ioctl(self.fd.as_fd(), cmd.as_numeric(), arg, proto::MAX_PAYLOAD_SIZE)
+	    .map(|(_, arg)| arg)
+    }
+
+    fn termios(&self) -> crate::Result<TermIOs> {
+	match self.ioctl(ioctl::TCGETS2, Arg::None)? {
+	    Arg::TermIOs(ios)	=> Ok(ios),
+	    arg			=> {
+		warn!("unexpected termios result {arg:?}");
+		Err(proto::Error::BadIoctlParam.into())
+	    }
+	}
+    }
+
+    fn modem(&self) -> crate::Result<u32> {
+	match self.ioctl(ioctl::TIOCMGET, Arg::None)? {
+	    Arg::Int(v)		=> Ok(v.as_native()),
+	    arg			=> {
+		warn!("unexpected modem result {arg:?}");
+		Err(proto::Error::BadIoctlParam.into())
+	    }
+	}
+    }
+
+    fn icount(&self) -> nix::Result<SerialIcounter> {
+	let mut cnt = SerialIcounter::default();
+
+	let rc = unsafe {
+	    libc::ioctl(self.fd.as_raw_fd(), libc::TIOCGICOUNT, &mut cnt)
+	};
+
+	nix::errno::Errno::result(rc).map(|_| cnt)
+    }
+
+    /// Executes a command of the client; returns the answer
+    fn command(&self, cmd: &Command) -> crate::Result<Option<Command>> {
+	use rfc::*;
+
+	let control = |v| Some(Command::SetControl(v));
+
+	Ok(match *cmd {
+	    Command::Signature(ref s) if s.is_empty()	=> Some(Command::Signature(SIGNATURE.to_vec())),
+
+	    Command::Signature(ref s)	=> {
+		info!("client signature '{}'", String::from_utf8_lossy(s));
+		None
+	    }
+
+	    Command::SetBaudrate(_) |
+	    Command::SetDatasize(_) |
+	    Command::SetParity(_) |
+	    Command::SetStopsize(_) |
+	    Command::SetControl(CONTROL_FLOW_REQUEST..=CONTROL_FLOW_HARDWARE)	=> {
+		let mut ios = self.termios()?.into_os2();
+
+		if apply_line_command(&mut ios, cmd) {
+		    self.ioctl(ioctl::TCSETS2, Arg::TermIOs(TermIOs::from_os2(&ios)))?;
+		}
+
+		line_reply(&self.termios()?, cmd)
+	    }
+
+	    Command::SetControl(CONTROL_BREAK_REQUEST)	=> match self.brk.load(Ordering::Relaxed) {
+		true	=> control(CONTROL_BREAK_ON),
+		false	=> control(CONTROL_BREAK_OFF),
+	    },
+
+	    Command::SetControl(v @ (CONTROL_BREAK_ON | CONTROL_BREAK_OFF))	=> {
+		let req = match v {
+		    CONTROL_BREAK_ON	=> ioctl::TIOCSBRK,
+		    _			=> ioctl::TIOCCBRK,
+		};
+
+		self.ioctl(req, Arg::Arg(be64::from_native(0)))?;
+		self.brk.store(v == CONTROL_BREAK_ON, Ordering::Relaxed);
+
+		control(v)
+	    }
+
+	    Command::SetControl(v @ (CONTROL_DTR_REQUEST | CONTROL_RTS_REQUEST))	=> {
+		let modem = self.modem()?;
+
+		let (bit, on, off) = match v {
+		    CONTROL_DTR_REQUEST	=> (termbits::TIOCM_DTR, CONTROL_DTR_ON, CONTROL_DTR_OFF),
+		    _			=> (termbits::TIOCM_RTS, CONTROL_RTS_ON, CONTROL_RTS_OFF),
+		};
+
+		match modem & bit as u32 {
+		    0	=> control(off),
+		    _	=> control(on),
+		}
+	    }
+
+	    Command::SetControl(v @ (CONTROL_DTR_ON | CONTROL_DTR_OFF |
+				     CONTROL_RTS_ON | CONTROL_RTS_OFF))	=> {
+		let (req, bit) = match v {
+		    CONTROL_DTR_ON	=> (ioctl::TIOCMBIS, termbits::TIOCM_DTR),
+		    CONTROL_DTR_OFF	=> (ioctl::TIOCMBIC, termbits::TIOCM_DTR),
+		    CONTROL_RTS_ON	=> (ioctl::TIOCMBIS, termbits::TIOCM_RTS),
+		    _			=> (ioctl::TIOCMBIC, termbits::TIOCM_RTS),
+		};
+
+		self.ioctl(req, Arg::Int(be32::from_native(bit as u32)))?;
+
+		control(v)
+	    }
+
+	    Command::SetControl(v)	=> {
+		debug!("unsupported control {v}");
+		None
+	    }
+
+	    Command::NotifyLinestate(_)		=> Some(Command::NotifyLinestate(0)),
+
+	    Command::NotifyModemstate(_)	=>
+		Some(Command::NotifyModemstate(tiocm_to_modemstate(self.modem()?))),
+
+	    Command::FlowcontrolSuspend		=> {
+		self.suspended.store(true, Ordering::Relaxed);
+		None
+	    }
+
+	    Command::FlowcontrolResume		=> {
+		self.suspended.store(false, Ordering::Relaxed);
+		None
+	    }
+
+	    Command::SetLinestateMask(m)	=> {
+		self.linestate_mask.store(m, Ordering::Relaxed);
+		Some(Command::SetLinestateMask(m))
+	    }
+
+	    Command::SetModemstateMask(m)	=> {
+		self.modemstate_mask.store(m, Ordering::Relaxed);
+		Some(Command::SetModemstateMask(m))
+	    }
+
+	    Command::PurgeData(v)		=> {
+		let queue = match v {
+		    PURGE_RX	=> libc::TCIFLUSH,
+		    PURGE_TX	=> libc::TCOFLUSH,
+		    PURGE_BOTH	=> libc::TCIOFLUSH,
+		    _		=> {
+			warn!("bad purge request {v}");
+			return Ok(None);
+		    }
+		};
+
+		self.ioctl(ioctl::TCFLSH, Arg::Arg(be64::from_native(queue as u64)))?;
+
+		Some(Command::PurgeData(v))
+	    }
+	})
+    }
+
+    fn handle_event(&self, ev: telnet::Event, reply: &mut Vec<u8>) {
+	match ev {
+	    telnet::Event::Negotiate(cmd, opt)	=> rfc::answer_negotiation(reply, cmd, opt),
+
+	    telnet::Event::Subneg(telnet::OPT_COM_PORT, payload) => match Command::decode(&payload) {
+		Some((Origin::Client, cmd))	=> match self.command(&cmd) {
+		    Ok(Some(resp))	=> resp.encode(reply, Origin::Server),
+		    Ok(None)		=> {},
+		    Err(e)		=> warn!("failed to execute {cmd:?}: {e:?}"),
+		},
+
+		_				=>
+		    warn!("unexpected com port command {payload:?}"),
+	    },
+
+	    telnet::Event::Subneg(opt, _)	=>
+		debug!("ignoring subnegotiation of option {opt}"),
+	}
+    }
+
+    fn write_dev(&self, mut data: &[u8]) -> crate::Result<()> {
+	while !data.is_empty() {
+	    match nix::unistd::write(self.fd.as_raw_fd(), data) {
+		Ok(l)				=> data = &data[l..],
+		Err(nix::Error::EINTR)		=> {},
+
+		Err(nix::Error::EAGAIN)		=> {
+		    let mut pfd = [ PollFd::new(&self.fd, PollFlags::POLLOUT) ];
+
+		    match nix::poll::poll(&mut pfd, POLL_INTERVAL.as_millis() as libc::c_int) {
+			Ok(_) | Err(nix::Error::EINTR)	=> {},
+			Err(e)				=> return Err(e.into()),
+		    }
+		}
+
+		Err(e)				=> return Err(e.into()),
+	    }
+	}
+
+	Ok(())
+    }
+
+    /// Forwards data and commands from the client to the device
+    fn net_loop(&self) -> crate::Result<()> {
+	let mut decoder = telnet::Decoder::default();
+	let mut data = VecDeque::new();
+	let mut buf = [0u8; 4096];
+
+	loop {
+	    let len = match (&self.conn).read(&mut buf) {
+		Ok(0)		=> break,
+		Ok(len)		=> len,
+		Err(e) if e.kind() == std::io::ErrorKind::Interrupted	=> continue,
+		Err(e)		=> return Err(e.into()),
+	    };
+
+	    let mut reply = Vec::new();
+	    let mut pos = 0;
+
+	    // commands must be executed in the order of the data
+	    while pos < len {
+		let (l, ev) = decoder.next(&buf[pos..len], &mut data);
+
+		pos += l;
+
+		self.write_dev(data.make_contiguous())?;
+		data.clear();
+
+		if let Some(ev) = ev {
+		    self.handle_event(ev, &mut reply);
+		}
+	    }
+
+	    self.send(&reply)?;
+	}
+
+	Ok(())
+    }
+
+    fn check_modem(&self, last: &mut Option<u8>, out: &mut Vec<u8>) -> crate::Result<()> {
+	use rfc::*;
+
+	let state = tiocm_to_modemstate(self.modem()?);
+
+	if let Some(old) = last.replace(state) {
+	    let changed = old ^ state;
+	    let mut delta = 0;
+
+	    for (line, d) in [(MODEM_CTS, MODEM_DELTA_CTS),
+			      (MODEM_DSR, MODEM_DELTA_DSR),
+			      (MODEM_CD, MODEM_DELTA_CD)] {
+		if changed & line != 0 {
+		    delta |= d;
+		}
+	    }
+
+	    if old & !state & MODEM_RI != 0 {
+		delta |= MODEM_TERI;
+	    }
+
+	    let mask = self.modemstate_mask.load(Ordering::Relaxed);
+
+	    if (changed | delta) & mask != 0 {
+		Command::NotifyModemstate((state | delta) & mask).encode(out, Origin::Server);
+	    }
+	}
+
+	Ok(())
+    }
+
+    fn check_line(&self, last: &mut Option<SerialIcounter>, out: &mut Vec<u8>) -> nix::Result<()> {
+	use rfc::*;
+
+	let mask = self.linestate_mask.load(Ordering::Relaxed);
+
+	if mask == 0 {
+	    *last = None;
+	    return Ok(());
+	}
+
+	let cnt = self.icount()?;
+
+	if let Some(old) = last.replace(cnt) {
+	    let mut state = 0;
+
+	    for (new, old, bit) in [(cnt.brk, old.brk, LINE_BREAK),
+				    (cnt.frame, old.frame, LINE_FRAMING),
+				    (cnt.parity, old.parity, LINE_PARITY),
+				    (cnt.overrun + cnt.buf_overrun, old.overrun + old.buf_overrun,
+				     LINE_OVERRUN)] {
+		if new != old {
+		    state |= bit;
+		}
+	    }
+
+	    if state & mask != 0 {
+		Command::NotifyLinestate(state & mask).encode(out, Origin::Server);
+	    }
+	}
+
+	Ok(())
+    }
+
+    /// Forwards data and state changes from the device to the client
+    fn dev_loop(&self) -> crate::Result<()> {
+	let mut buf = [0u8; 4096];
+	let mut out = Vec::new();
+	let mut modem = None;
+	let mut line = None;
+	let mut has_modem = true;
+	let mut has_icount = true;
+
+	while !self.done.load(Ordering::Relaxed) {
+	    out.clear();
+
+	    if self.suspended.load(Ordering::Relaxed) {
+		std::thread::sleep(POLL_INTERVAL);
+	    } else {
+		let mut pfd = [ PollFd::new(&self.fd, PollFlags::POLLIN) ];
+
+		match nix::poll::poll(&mut pfd, POLL_INTERVAL.as_millis() as libc::c_int) {
+		    Ok(0) | Err(nix::Error::EINTR)	=> {},
+		    Ok(_)				=> {
+			match nix::unistd::read(self.fd.as_raw_fd(), &mut buf) {
+			    Ok(0)			=> {
+				info!("device hung up");
+				break;
+			    }
+
+			    Ok(l)			=> telnet::escape_into(&mut out, &buf[..l]),
+			    Err(nix::Error::EAGAIN |
+				nix::Error::EINTR)	=> {},
+			    Err(e)			=> return Err(e.into()),
+			}
+		    }
+
+		    Err(e)				=> return Err(e.into()),
+		}
+	    }
+
+	    if has_modem {
+		if let Err(e) = self.check_modem(&mut modem, &mut out) {
+		    info!("modem lines not available: {e:?}");
+		    has_modem = false;
+		}
+	    }
+
+	    if has_icount {
+		if let Err(e) = self.check_line(&mut line, &mut out) {
+		    info!("line state not available: {e:?}");
+		    has_icount = false;
+		}
+	    }
+
+	    self.send(&out)?;
+	}
+
+	Ok(())
+    }
+
+    pub fn run(self) -> crate::Result<()> {
+	let mut setup = Vec::new();
+
+	for (cmd, opt) in [(telnet::DO, telnet::OPT_COM_PORT),
+			   (telnet::WILL, telnet::OPT_BINARY),
+			   (telnet::DO, telnet::OPT_BINARY),
+			   (telnet::WILL, telnet::OPT_SGA),
+			   (telnet::DO, telnet::OPT_SGA)] {
+	    telnet::negotiate(&mut setup, cmd, opt);
+	}
+
+	self.send(&setup)?;
+
+	std::thread::scope(|s| {
+	    let dev = std::thread::Builder::new()
+		.name("rfc2217-dev".to_string())
+		.spawn_scoped(s, || {
+		    let res = self.dev_loop();
+
+		    // wake up the network side
+		    let _ = self.conn.shutdown(Shutdown::Both);
+
+		    res
+		})?;
+
+	    let res = self.net_loop();
+
+	    self.done.store(true, Ordering::Relaxed);
+
+	    let dev_res = dev.join()
+		.unwrap_or_else(|e| std::panic::resume_unwind(e));
+
+	    res.and(dev_res)
+	})
+    }
+}
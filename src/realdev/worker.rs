@@ -0,0 +1,498 @@
+//! Executes ioctls in worker threads so that slow ones do not block the
+//! request loop
+//!
+//! Requests which wait until the output of the device has been
+//! transmitted poll the output queue with `TIOCOUTQ` first and execute
+//! the original ioctl afterwards; it waits only for the remaining bytes
+//! in the UART then.  Interrupting a running ioctl sends a signal to the
+//! worker which lets blocking system calls fail with `EINTR`.
+//!
+//! Requests are started in the order of their arrival and a request
+//! starts only after the previous ones finished.  Only ioctls which wait
+//! for external events (`TIOCMIWAIT`) do not delay the following ones.
+//!
+//! The signal is blocked in the workers except while they execute the
+//! ioctl.  Because a signal which arrives between checking for an
+//! interruption and entering the system call is lost, [`Workers::watch()`]
+//! repeats it until the worker finished the request.
+
+use std::collections::VecDeque;
+use std::os::fd::AsRawFd;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use nix::sys::pthread::{pthread_kill, pthread_self, Pthread};
+use nix::sys::signal::{self, Signal, SigAction, SigHandler, SaFlags, SigSet};
+use parking_lot::{Condvar, Mutex};
+
+use ensc_ioctl_ffi::ffi::ioctl;
+
+use crate::proto::{self, Sequence};
+use crate::proto::ioctl::Arg;
+
+use super::Device;
+
+/// Number of ioctls which can be executed concurrently per device
+pub const NUM_WORKERS: usize = 4;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Signal which interrupts system calls of a worker
+const INTR_SIGNAL: Signal = Signal::SIGUSR1;
+
+type IoctlRequest = (Sequence, u32, Arg);
+
+struct Active {
+    seq:		Sequence,
+    thread:		Pthread,
+    interrupted:	bool,
+    /// whether the request waits for external events and does not delay
+    /// the following ones
+    waiting:		bool,
+}
+
+#[derive(Default)]
+struct State {
+    requests:	VecDeque<IoctlRequest>,
+    active:	Vec<Active>,
+    closed:	bool,
+}
+
+pub struct Workers<'a> {
+    device:	&'a Device,
+    state:	Mutex<State>,
+    cond:	Condvar,
+}
+
+extern "C" fn intr_handler(_: nix::libc::c_int) {
+}
+
+/// Installs a handler for [`INTR_SIGNAL`]; without `SA_RESTART`,
+/// interrupted system calls fail with `EINTR`.  The result of the first
+/// call is reported to all callers.
+fn setup_signal() -> nix::Result<()> {
+    static RESULT: OnceLock<nix::Result<()>> = OnceLock::new();
+
+    *RESULT.get_or_init(|| {
+	let act = SigAction::new(SigHandler::Handler(intr_handler),
+				 SaFlags::empty(), SigSet::empty());
+
+	unsafe { signal::sigaction(INTR_SIGNAL, &act) }.map(|_| ())
+    })
+}
+
+fn intr_sigset() -> SigSet {
+    let mut set = SigSet::empty();
+
+    set.add(INTR_SIGNAL);
+    set
+}
+
+/// Runs `f` with [`INTR_SIGNAL`] unblocked so that a blocking ioctl can
+/// be interrupted; outside of it, pending signals must not break other
+/// system calls like sending the response
+pub fn interruptible<T>(f: impl FnOnce() -> T) -> T {
+    let set = intr_sigset();
+
+    if let Err(e) = set.thread_unblock() {
+	warn!("failed to unblock {INTR_SIGNAL:?}: {e:?}");
+    }
+
+    let res = f();
+
+    if let Err(e) = set.thread_block() {
+	warn!("failed to block {INTR_SIGNAL:?}: {e:?}");
+    }
+
+    res
+}
+
+impl <'a> Workers<'a> {
+    pub fn new(dev: &'a Device) -> nix::Result<Self> {
+	setup_signal()?;
+
+	Ok(Self {
+	    device:	dev,
+	    state:	Mutex::new(State::default()),
+	    cond:	Condvar::new(),
+	})
+    }
+
+    /// Returns whether the ioctl waits for the output queue
+    fn is_drain(cmd: u32, arg: &Arg) -> bool {
+	match ioctl::from(cmd) {
+	    ioctl::TCSETSW |
+	    ioctl::TCSETSW2 |
+	    ioctl::TCSETAW |
+	    ioctl::TCSETSF |
+	    ioctl::TCSETSF2 |
+	    ioctl::TCSETAF	=> true,
+	    // tcdrain()
+	    ioctl::TCSBRK	=> matches!(arg, Arg::Arg(v) if v.as_native() != 0),
+	    _			=> false,
+	}
+    }
+
+    /// Returns whether the ioctl waits for external events
+    fn is_waiting(cmd: u32) -> bool {
+	ioctl::from(cmd) == ioctl::TIOCMIWAIT
+    }
+
+    pub fn push_request(&self, req: IoctlRequest) {
+	self.state.lock().requests.push_back(req);
+	self.cond.notify_one();
+    }
+
+    fn send_intr(&self, seq: Sequence) {
+	trace!("sending INTR to {seq:?}");
+
+	let _ = proto::Response::send_err(self.device.conn(), seq, nix::Error::EINTR)
+	    .map_err(|e| error!("failed to send INTR response: {e:?}"));
+    }
+
+    /// Interrupts the request `seq` or all requests when it is `None`.
+    /// Queued requests are answered immediately; running ones are
+    /// answered by their worker.
+    pub fn do_intr(&self, seq: Option<Sequence>) {
+	let matches = |s: &Sequence| seq.map(|seq| seq == *s).unwrap_or(true);
+	let mut state = self.state.lock();
+	let mut intr = Vec::new();
+
+	state.requests.retain(|(req_seq, _, _)| {
+	    let m = matches(req_seq);
+
+	    if m {
+		intr.push(*req_seq);
+	    }
+
+	    !m
+	});
+
+	// the signal is sent by watch()
+	for active in state.active.iter_mut().filter(|a| matches(&a.seq)) {
+	    trace!("interrupting running {:?}", active.seq);
+
+	    active.interrupted = true;
+	}
+
+	drop(state);
+
+	self.cond.notify_all();
+
+	for seq in intr {
+	    self.send_intr(seq);
+	}
+    }
+
+    /// Cancels pending requests and terminates the `run()` and `watch()`
+    /// loops
+    pub fn close(&self) {
+	self.state.lock().closed = true;
+	self.do_intr(None);
+    }
+
+    fn is_interrupted(state: &State, seq: Sequence) -> bool {
+	state.active.iter()
+	    .any(|a| a.seq == seq && a.interrupted)
+    }
+
+    fn outq(&self) -> nix::Result<nix::libc::c_int> {
+	let mut cnt: nix::libc::c_int = 0;
+
+	let rc = unsafe {
+	    nix::libc::ioctl(self.device.fd.as_raw_fd(), nix::libc::TIOCOUTQ, &mut cnt)
+	};
+
+	nix::errno::Errno::result(rc).map(|_| cnt)
+    }
+
+    /// Waits until the output queue is empty; returns `false` when the
+    /// request was interrupted in the meantime.
+    fn wait_empty(&self, seq: Sequence) -> bool {
+	let mut state = self.state.lock();
+
+	loop {
+	    if Self::is_interrupted(&state, seq) {
+		return false;
+	    }
+
+	    match self.outq() {
+		Ok(0)	=> break,
+		Ok(_)	=> {},
+		Err(e)	=> {
+		    // let the ioctl itself do the waiting
+		    debug!("TIOCOUTQ failed: {e:?}");
+		    break;
+		}
+	    }
+
+	    self.cond.wait_for(&mut state, POLL_INTERVAL);
+	}
+
+	true
+    }
+
+    fn next_request(&self) -> Option<IoctlRequest> {
+	let mut state = self.state.lock();
+
+	loop {
+	    if state.closed {
+		return None;
+	    }
+
+	    // keep the order of the requests
+	    let busy = state.active.iter().any(|a| !a.waiting);
+	    let req = match busy {
+		true	=> None,
+		false	=> state.requests.pop_front(),
+	    };
+
+	    if let Some(req) = req {
+		state.active.push(Active {
+		    seq:		req.0,
+		    thread:		pthread_self(),
+		    interrupted:	false,
+		    waiting:		Self::is_waiting(req.1),
+		});
+
+		return Some(req);
+	    }
+
+	    self.cond.wait(&mut state);
+	}
+    }
+
+    fn finish(&self, seq: Sequence) {
+	self.state.lock().active.retain(|a| a.seq != seq);
+	self.cond.notify_all();
+
+	// watch() does not signal this thread anymore; discard a signal
+	// which arrived after the ioctl so that it does not interrupt the
+	// next request
+	let timeout = nix::libc::timespec { tv_sec: 0, tv_nsec: 0 };
+
+	while unsafe {
+	    nix::libc::sigtimedwait(intr_sigset().as_ref(), std::ptr::null_mut(), &timeout)
+	} >= 0 {}
+    }
+
+    fn execute(&self, seq: Sequence, cmd: u32, arg: Arg) -> crate::Result<()> {
+	if Self::is_drain(cmd, &arg) && !self.wait_empty(seq) {
+	    self.send_intr(seq);
+	    return Ok(());
+	}
+
+	if Self::is_interrupted(&self.state.lock(), seq) {
+	    self.send_intr(seq);
+	    return Ok(());
+	}
+
+	self.device.ioctl(seq, cmd, arg)
+    }
+
+    pub fn run(&self) -> crate::Result<()> {
+	intr_sigset().thread_block()?;
+
+	while let Some((seq, cmd, arg)) = self.next_request() {
+	    trace!("executing ioctl {seq:?}");
+
+	    let res = self.execute(seq, cmd, arg);
+
+	    self.finish(seq);
+
+	    res?;
+	}
+
+	Ok(())
+    }
+
+    /// Sends the signal to the workers of interrupted requests until
+    /// they finished them; runs until `close()` was called and all
+    /// requests are finished
+    pub fn watch(&self) {
+	let mut state = self.state.lock();
+
+	loop {
+	    let mut pending = false;
+
+	    for active in state.active.iter().filter(|a| a.interrupted) {
+		pending = true;
+
+		if let Err(e) = pthread_kill(active.thread, INTR_SIGNAL) {
+		    warn!("failed to interrupt worker: {e:?}");
+		}
+	    }
+
+	    if state.closed && state.active.is_empty() {
+		break;
+	    }
+
+	    match pending {
+		true	=> { self.cond.wait_for(&mut state, POLL_INTERVAL); },
+		false	=> self.cond.wait(&mut state),
+	    }
+	}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use std::os::fd::OwnedFd;
+    use std::os::unix::net::UnixStream;
+    use std::sync::Arc;
+    use std::thread::scope;
+
+    use crate::proto::be64;
+    use crate::transport::Stream;
+    use super::*;
+
+    /// Unix sockets report data which was not read by the peer in their
+    /// output queue; this lets the test control when the queue drains
+    fn device(fd: UnixStream, conn: UnixStream) -> Device {
+	Device {
+	    fd:		Arc::new(OwnedFd::from(fd)),
+	    conn:	Arc::new(Stream::Unix(conn)),
+	    session:	Default::default(),
+	    policy:	Default::default(),
+	    ldisc_set:	Default::default(),
+	}
+    }
+
+    /// Receives an error response; returns `None` when nothing arrived
+    /// within `to`
+    fn recv_err(conn: &UnixStream, to: Duration) -> Option<(u64, i32)> {
+	match proto::Response::recv_timeout(conn, Some(to)) {
+	    Err(proto::Error::RemoteError(Some(seq), err))	=> Some((seq.as_ffi(), err)),
+	    Err(proto::Error::Io(e)) if e.raw_os_error() == Some(nix::libc::ETIMEDOUT)	=> None,
+	    res		=> panic!("unexpected response: {res:?}"),
+	}
+    }
+
+    fn tcdrain(seq: u64) -> IoctlRequest {
+	(Sequence::from_ffi(seq), ioctl::TCSBRK.as_numeric(), Arg::Arg(be64::from_native(1)))
+    }
+
+    #[test]
+    fn test_drain() {
+	let (fd, mut peer) = UnixStream::pair().unwrap();
+	let (conn, client) = UnixStream::pair().unwrap();
+
+	(&fd).write_all(b"pending").unwrap();
+
+	let dev = device(fd, conn);
+	let workers = Workers::new(&dev).unwrap();
+	let (_, cmd, arg) = tcdrain(1);
+
+	assert!(Workers::is_drain(cmd, &arg));
+	assert!(!Workers::is_drain(cmd, &Arg::Arg(be64::from_native(0))));
+
+	for cmd in [ioctl::TCSETSW, ioctl::TCSETSW2, ioctl::TCSETAW,
+		    ioctl::TCSETSF, ioctl::TCSETSF2, ioctl::TCSETAF] {
+	    assert!(Workers::is_drain(cmd.as_numeric(), &Arg::None));
+	}
+
+	for cmd in [ioctl::TCSETS, ioctl::TCSETS2, ioctl::TCSETA] {
+	    assert!(!Workers::is_drain(cmd.as_numeric(), &Arg::None));
+	}
+
+	scope(|s| {
+	    s.spawn(|| workers.run().unwrap());
+	    s.spawn(|| workers.watch());
+
+	    workers.push_request(tcdrain(1));
+	    assert_eq!(recv_err(&client, Duration::from_millis(100)), None);
+
+	    // interrupts the request while it waits for the output queue
+	    workers.do_intr(Some(Sequence::from_ffi(1)));
+
+	    assert_eq!(recv_err(&client, Duration::from_secs(1)), Some((1, nix::libc::EINTR)));
+
+	    workers.push_request(tcdrain(2));
+	    assert_eq!(recv_err(&client, Duration::from_millis(100)), None);
+
+	    // the ioctl is executed once the queue is empty; sockets do not
+	    // support it
+	    peer.read_exact(&mut [0; 7]).unwrap();
+
+	    assert_eq!(recv_err(&client, Duration::from_secs(1)), Some((2, nix::libc::ENOTTY)));
+
+	    workers.close();
+	});
+    }
+
+    #[test]
+    fn test_order() {
+	let (fd, mut peer) = UnixStream::pair().unwrap();
+	let (conn, client) = UnixStream::pair().unwrap();
+
+	(&fd).write_all(b"pending").unwrap();
+
+	let dev = device(fd, conn);
+	let workers = Workers::new(&dev).unwrap();
+
+	scope(|s| {
+	    for _ in 0..NUM_WORKERS {
+		s.spawn(|| workers.run().unwrap());
+	    }
+	    s.spawn(|| workers.watch());
+
+	    // TCSBRK without drain would fail immediately on the socket but
+	    // must wait for the preceding tcdrain()
+	    workers.push_request(tcdrain(1));
+	    workers.push_request((Sequence::from_ffi(2), ioctl::TCSBRK.as_numeric(),
+				  Arg::Arg(be64::from_native(0))));
+
+	    assert_eq!(recv_err(&client, Duration::from_millis(100)), None);
+
+	    peer.read_exact(&mut [0; 7]).unwrap();
+
+	    assert_eq!(recv_err(&client, Duration::from_secs(1)), Some((1, nix::libc::ENOTTY)));
+	    assert_eq!(recv_err(&client, Duration::from_secs(1)), Some((2, nix::libc::ENOTTY)));
+
+	    workers.close();
+	});
+    }
+
+    #[test]
+    fn test_resignal() {
+	let (fd, _peer) = UnixStream::pair().unwrap();
+	let (conn, _client) = UnixStream::pair().unwrap();
+	let (sock, _sock_peer) = UnixStream::pair().unwrap();
+
+	let dev = device(fd, conn);
+	let workers = Workers::new(&dev).unwrap();
+	let seq = Sequence::from_ffi(1);
+
+	scope(|s| {
+	    s.spawn(|| workers.watch());
+
+	    let worker = s.spawn(|| {
+		intr_sigset().thread_block().unwrap();
+
+		workers.state.lock().active.push(Active {
+		    seq:		seq,
+		    thread:		pthread_self(),
+		    interrupted:	false,
+		    waiting:		false,
+		});
+
+		// the request is interrupted before the blocking call
+		// starts; the signal must not be lost
+		workers.do_intr(Some(seq));
+		std::thread::sleep(Duration::from_millis(50));
+
+		let res = interruptible(|| (&sock).read(&mut [0; 1]));
+
+		workers.finish(seq);
+		res
+	    });
+
+	    let err = worker.join().unwrap().unwrap_err();
+
+	    assert_eq!(err.raw_os_error(), Some(nix::libc::EINTR));
+
+	    workers.close();
+	});
+    }
+}
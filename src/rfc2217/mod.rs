@@ -0,0 +1,345 @@
+//! Telnet Com Port Control Option (RFC 2217)
+
+pub mod telnet;
+
+use ensc_ioctl_ffi::ffi as ioctl_ffi;
+use ensc_ioctl_ffi::termbits;
+
+use crate::proto::ioctl::{TermIOs, rate_to_cbaud};
+
+pub const PARITY_NONE: u8	= 1;
+pub const PARITY_ODD: u8	= 2;
+pub const PARITY_EVEN: u8	= 3;
+pub const PARITY_MARK: u8	= 4;
+pub const PARITY_SPACE: u8	= 5;
+
+pub const STOPSIZE_1: u8	= 1;
+pub const STOPSIZE_2: u8	= 2;
+pub const STOPSIZE_15: u8	= 3;
+
+pub const CONTROL_FLOW_REQUEST: u8	= 0;
+pub const CONTROL_FLOW_NONE: u8		= 1;
+pub const CONTROL_FLOW_XONXOFF: u8	= 2;
+pub const CONTROL_FLOW_HARDWARE: u8	= 3;
+pub const CONTROL_BREAK_REQUEST: u8	= 4;
+pub const CONTROL_BREAK_ON: u8		= 5;
+pub const CONTROL_BREAK_OFF: u8		= 6;
+pub const CONTROL_DTR_REQUEST: u8	= 7;
+pub const CONTROL_DTR_ON: u8		= 8;
+pub const CONTROL_DTR_OFF: u8		= 9;
+pub const CONTROL_RTS_REQUEST: u8	= 10;
+pub const CONTROL_RTS_ON: u8		= 11;
+pub const CONTROL_RTS_OFF: u8		= 12;
+
+pub const PURGE_RX: u8		= 1;
+pub const PURGE_TX: u8		= 2;
+pub const PURGE_BOTH: u8	= 3;
+
+pub const MODEM_CD: u8		= 0x80;
+pub const MODEM_RI: u8		= 0x40;
+pub const MODEM_DSR: u8		= 0x20;
+pub const MODEM_CTS: u8		= 0x10;
+pub const MODEM_DELTA_CD: u8	= 0x08;
+pub const MODEM_TERI: u8	= 0x04;
+pub const MODEM_DELTA_DSR: u8	= 0x02;
+pub const MODEM_DELTA_CTS: u8	= 0x01;
+
+pub const LINE_BREAK: u8	= 0x10;
+pub const LINE_FRAMING: u8	= 0x08;
+pub const LINE_PARITY: u8	= 0x04;
+pub const LINE_OVERRUN: u8	= 0x02;
+
+/// Offset which is added by the server to the codes of its commands
+const SERVER_OFFSET: u8 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    Client,
+    Server,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Signature(Vec<u8>),
+    SetBaudrate(u32),
+    SetDatasize(u8),
+    SetParity(u8),
+    SetStopsize(u8),
+    SetControl(u8),
+    NotifyLinestate(u8),
+    NotifyModemstate(u8),
+    FlowcontrolSuspend,
+    FlowcontrolResume,
+    SetLinestateMask(u8),
+    SetModemstateMask(u8),
+    PurgeData(u8),
+}
+
+impl Command {
+    fn code(&self) -> u8 {
+	match self {
+	    Self::Signature(_)		=> 0,
+	    Self::SetBaudrate(_)	=> 1,
+	    Self::SetDatasize(_)	=> 2,
+	    Self::SetParity(_)		=> 3,
+	    Self::SetStopsize(_)	=> 4,
+	    Self::SetControl(_)		=> 5,
+	    Self::NotifyLinestate(_)	=> 6,
+	    Self::NotifyModemstate(_)	=> 7,
+	    Self::FlowcontrolSuspend	=> 8,
+	    Self::FlowcontrolResume	=> 9,
+	    Self::SetLinestateMask(_)	=> 10,
+	    Self::SetModemstateMask(_)	=> 11,
+	    Self::PurgeData(_)		=> 12,
+	}
+    }
+
+    /// Appends the telnet subnegotiation of the command to `out`
+    pub fn encode(&self, out: &mut Vec<u8>, origin: Origin) {
+	let code = match origin {
+	    Origin::Client	=> self.code(),
+	    Origin::Server	=> self.code() + SERVER_OFFSET,
+	};
+
+	let mut buf = vec![code];
+
+	match self {
+	    Self::Signature(s)		=> buf.extend_from_slice(s),
+	    Self::SetBaudrate(v)	=> buf.extend_from_slice(&v.to_be_bytes()),
+
+	    Self::FlowcontrolSuspend |
+	    Self::FlowcontrolResume	=> {},
+
+	    Self::SetDatasize(v) |
+	    Self::SetParity(v) |
+	    Self::SetStopsize(v) |
+	    Self::SetControl(v) |
+	    Self::NotifyLinestate(v) |
+	    Self::NotifyModemstate(v) |
+	    Self::SetLinestateMask(v) |
+	    Self::SetModemstateMask(v) |
+	    Self::PurgeData(v)		=> buf.push(*v),
+	}
+
+	telnet::subneg(out, telnet::OPT_COM_PORT, &buf);
+    }
+
+    /// Parses the payload of a COM-PORT-OPTION subnegotiation
+    pub fn decode(payload: &[u8]) -> Option<(Origin, Self)> {
+	let (code, data) = payload.split_first()?;
+
+	let (origin, code) = match *code {
+	    c if c >= SERVER_OFFSET	=> (Origin::Server, c - SERVER_OFFSET),
+	    c				=> (Origin::Client, c),
+	};
+
+	let byte = || data.first().copied();
+
+	let cmd = match code {
+	    0	=> Self::Signature(data.to_vec()),
+	    1	=> Self::SetBaudrate(u32::from_be_bytes(data.get(..4)?.try_into().ok()?)),
+	    2	=> Self::SetDatasize(byte()?),
+	    3	=> Self::SetParity(byte()?),
+	    4	=> Self::SetStopsize(byte()?),
+	    5	=> Self::SetControl(byte()?),
+	    // a client sends them without value to query the state
+	    6	=> Self::NotifyLinestate(byte().unwrap_or(0)),
+	    7	=> Self::NotifyModemstate(byte().unwrap_or(0)),
+	    8	=> Self::FlowcontrolSuspend,
+	    9	=> Self::FlowcontrolResume,
+	    10	=> Self::SetLinestateMask(byte()?),
+	    11	=> Self::SetModemstateMask(byte()?),
+	    12	=> Self::PurgeData(byte()?),
+	    _	=> return None,
+	};
+
+	Some((origin, cmd))
+    }
+}
+
+/// Returns the commands which configure a port like `ios`
+pub fn line_commands(ios: &TermIOs) -> [Command; 5] {
+    let cflag = ios.cflag();
+
+    let datasize = match cflag & termbits::CSIZE {
+	termbits::CS5	=> 5,
+	termbits::CS6	=> 6,
+	termbits::CS7	=> 7,
+	_		=> 8,
+    };
+
+    let parity = match (cflag & termbits::PARENB != 0, cflag & termbits::PARODD != 0,
+			cflag & termbits::CMSPAR != 0) {
+	(false, _, _)		=> PARITY_NONE,
+	(true, true, false)	=> PARITY_ODD,
+	(true, false, false)	=> PARITY_EVEN,
+	(true, true, true)	=> PARITY_MARK,
+	(true, false, true)	=> PARITY_SPACE,
+    };
+
+    let stopsize = match cflag & termbits::CSTOPB {
+	0	=> STOPSIZE_1,
+	_	=> STOPSIZE_2,
+    };
+
+    let flow = match (cflag & termbits::CRTSCTS, ios.iflag() & (termbits::IXON | termbits::IXOFF)) {
+	(0, 0)	=> CONTROL_FLOW_NONE,
+	(0, _)	=> CONTROL_FLOW_XONXOFF,
+	_	=> CONTROL_FLOW_HARDWARE,
+    };
+
+    [
+	Command::SetBaudrate(ios.ospeed()),
+	Command::SetDatasize(datasize),
+	Command::SetParity(parity),
+	Command::SetStopsize(stopsize),
+	Command::SetControl(flow),
+    ]
+}
+
+/// Applies a SET-BAUDRATE, SET-DATASIZE, SET-PARITY, SET-STOPSIZE or
+/// flow control SET-CONTROL command to `ios`; returns `false` when the
+/// command does not change the line settings.
+pub fn apply_line_command(ios: &mut ioctl_ffi::termios2, cmd: &Command) -> bool {
+    let cflag = &mut ios.c_cflag.0;
+    let iflag = &mut ios.c_iflag.0;
+
+    match *cmd {
+	Command::SetBaudrate(0)		=> return false,
+	Command::SetBaudrate(rate)	=> {
+	    *cflag &= !(termbits::CBAUD | termbits::CIBAUD);
+	    *cflag |= rate_to_cbaud(rate).unwrap_or(termbits::BOTHER);
+	    ios.c_ispeed = rate;
+	    ios.c_ospeed = rate;
+	}
+
+	Command::SetDatasize(v @ 5..=8)	=> {
+	    *cflag &= !termbits::CSIZE;
+	    *cflag |= match v {
+		5	=> termbits::CS5,
+		6	=> termbits::CS6,
+		7	=> termbits::CS7,
+		_	=> termbits::CS8,
+	    };
+	}
+
+	Command::SetParity(v @ PARITY_NONE..=PARITY_SPACE)	=> {
+	    *cflag &= !(termbits::PARENB | termbits::PARODD | termbits::CMSPAR);
+	    *cflag |= match v {
+		PARITY_ODD	=> termbits::PARENB | termbits::PARODD,
+		PARITY_EVEN	=> termbits::PARENB,
+		PARITY_MARK	=> termbits::PARENB | termbits::PARODD | termbits::CMSPAR,
+		PARITY_SPACE	=> termbits::PARENB | termbits::CMSPAR,
+		_		=> 0,
+	    };
+	}
+
+	Command::SetStopsize(STOPSIZE_1)	=> *cflag &= !termbits::CSTOPB,
+	// 1.5 stop bits are selected by CSTOPB together with CS5
+	Command::SetStopsize(STOPSIZE_2 | STOPSIZE_15)	=> *cflag |= termbits::CSTOPB,
+
+	Command::SetControl(v @ CONTROL_FLOW_NONE..=CONTROL_FLOW_HARDWARE)	=> {
+	    *cflag &= !termbits::CRTSCTS;
+	    *iflag &= !(termbits::IXON | termbits::IXOFF);
+
+	    match v {
+		CONTROL_FLOW_XONXOFF	=> *iflag |= termbits::IXON | termbits::IXOFF,
+		CONTROL_FLOW_HARDWARE	=> *cflag |= termbits::CRTSCTS,
+		_			=> {},
+	    }
+	}
+
+	_				=> return false,
+    }
+
+    true
+}
+
+/// Returns the answer to a line setting command; it contains the value
+/// of `ios`
+pub fn line_reply(ios: &TermIOs, cmd: &Command) -> Option<Command> {
+    let [baudrate, datasize, parity, stopsize, flow] = line_commands(ios);
+
+    match cmd {
+	Command::SetBaudrate(_)		=> Some(baudrate),
+	Command::SetDatasize(_)		=> Some(datasize),
+	Command::SetParity(_)		=> Some(parity),
+	Command::SetStopsize(_)		=> Some(stopsize),
+	Command::SetControl(CONTROL_FLOW_REQUEST..=CONTROL_FLOW_HARDWARE)	=> Some(flow),
+	_				=> None,
+    }
+}
+
+/// Answers an option request of the peer.  All supported options were
+/// requested by ourselves when connecting so that their acknowledgement
+/// must not be answered again.
+pub fn answer_negotiation(reply: &mut Vec<u8>, cmd: u8, opt: u8) {
+    let supported = matches!(opt, telnet::OPT_BINARY | telnet::OPT_SGA | telnet::OPT_COM_PORT);
+
+    match (cmd, supported) {
+	(telnet::WILL | telnet::DO, true)	=> {},
+	(telnet::WILL, false)		=> telnet::negotiate(reply, telnet::DONT, opt),
+	(telnet::DO, false)		=> telnet::negotiate(reply, telnet::WONT, opt),
+	(cmd, _)			=> debug!("peer refused option {opt} ({cmd})"),
+    }
+}
+
+/// Converts `TIOCM_*` bits into the state of NOTIFY-MODEMSTATE
+pub fn tiocm_to_modemstate(tiocm: u32) -> u8 {
+    let mut res = 0;
+
+    for (m, bit) in [(MODEM_CD, termbits::TIOCM_CD),
+		     (MODEM_RI, termbits::TIOCM_RI),
+		     (MODEM_DSR, termbits::TIOCM_DSR),
+		     (MODEM_CTS, termbits::TIOCM_CTS)] {
+	if tiocm & bit as u32 != 0 {
+	    res |= m;
+	}
+    }
+
+    res
+}
+
+/// Converts the state of NOTIFY-MODEMSTATE into `TIOCM_*` bits
+pub fn modemstate_to_tiocm(state: u8) -> u32 {
+    let mut res = 0;
+
+    for (m, tiocm) in [(MODEM_CD, termbits::TIOCM_CD),
+		       (MODEM_RI, termbits::TIOCM_RI),
+		       (MODEM_DSR, termbits::TIOCM_DSR),
+		       (MODEM_CTS, termbits::TIOCM_CTS)] {
+	if state & m != 0 {
+	    res |= tiocm as u32;
+	}
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_command() {
+	for (cmd, origin, raw) in [
+	    (Command::SetBaudrate(0x1c200), Origin::Client, &[1, 0, 1, 0xc2, 0][..]),
+	    (Command::SetBaudrate(0xff), Origin::Server, &[101, 0, 0, 0, 0xff]),
+	    (Command::SetControl(CONTROL_DTR_ON), Origin::Client, &[5, 8]),
+	    (Command::NotifyModemstate(0xb0), Origin::Server, &[107, 0xb0]),
+	    (Command::FlowcontrolSuspend, Origin::Client, &[8]),
+	] {
+	    let mut out = Vec::new();
+	    let mut expect = Vec::new();
+
+	    cmd.encode(&mut out, origin);
+	    telnet::subneg(&mut expect, telnet::OPT_COM_PORT, raw);
+
+	    assert_eq!(out, expect);
+	    assert_eq!(Command::decode(raw), Some((origin, cmd)));
+	}
+
+	assert_eq!(Command::decode(&[1, 0, 0]), None);
+	assert_eq!(Command::decode(&[13]), None);
+    }
+}
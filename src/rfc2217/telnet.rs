@@ -0,0 +1,206 @@
+//! Minimal telnet codec
+
+use std::collections::VecDeque;
+
+pub const SE: u8	= 240;
+pub const SB: u8	= 250;
+pub const WILL: u8	= 251;
+pub const WONT: u8	= 252;
+pub const DO: u8	= 253;
+pub const DONT: u8	= 254;
+pub const IAC: u8	= 255;
+
+pub const OPT_BINARY: u8	= 0;
+pub const OPT_SGA: u8		= 3;
+pub const OPT_COM_PORT: u8	= 44;
+
+/// Upper limit for the payload of a subnegotiation; longer ones are
+/// truncated
+const MAX_SB_LEN: usize = 256;
+
+/// Appends `data` to `out` while doubling `IAC` bytes
+pub fn escape_into(out: &mut Vec<u8>, data: &[u8]) {
+    for &c in data {
+	if c == IAC {
+	    out.push(IAC);
+	}
+
+	out.push(c);
+    }
+}
+
+pub fn negotiate(out: &mut Vec<u8>, cmd: u8, opt: u8) {
+    out.extend_from_slice(&[IAC, cmd, opt]);
+}
+
+pub fn subneg(out: &mut Vec<u8>, opt: u8, data: &[u8]) {
+    out.extend_from_slice(&[IAC, SB, opt]);
+    escape_into(out, data);
+    out.extend_from_slice(&[IAC, SE]);
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// `WILL`, `WONT`, `DO` or `DONT` for an option
+    Negotiate(u8, u8),
+    /// option and payload of a subnegotiation
+    Subneg(u8, Vec<u8>),
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+enum State {
+    #[default]
+    Data,
+    Iac,
+    Negotiate(u8),
+    Sb,
+    SbIac,
+}
+
+#[derive(Debug, Default)]
+pub struct Decoder {
+    state:	State,
+    sb:		Vec<u8>,
+}
+
+impl Decoder {
+    /// Splits received bytes into payload and telnet commands; incomplete
+    /// sequences are kept until the next call.
+    pub fn feed(&mut self, buf: &[u8], data: &mut VecDeque<u8>, events: &mut Vec<Event>) {
+	let mut pos = 0;
+
+	while pos < buf.len() {
+	    let (len, ev) = self.next(&buf[pos..], data);
+
+	    pos += len;
+	    events.extend(ev);
+	}
+    }
+
+    /// Like [`Self::feed`] but stops after the first telnet command so
+    /// that its order relative to the payload is kept; returns the number
+    /// of consumed bytes.
+    pub fn next(&mut self, buf: &[u8], data: &mut VecDeque<u8>) -> (usize, Option<Event>) {
+	for (idx, &c) in buf.iter().enumerate() {
+	    let mut ev = None;
+
+	    self.state = match (self.state, c) {
+		(State::Data, IAC)		=> State::Iac,
+		(State::Data, c)		=> {
+		    data.push_back(c);
+		    State::Data
+		}
+
+		(State::Iac, IAC)		=> {
+		    data.push_back(IAC);
+		    State::Data
+		}
+
+		(State::Iac, WILL | WONT | DO | DONT)	=> State::Negotiate(c),
+
+		(State::Iac, SB)		=> {
+		    self.sb.clear();
+		    State::Sb
+		}
+
+		// NOP, GA and similar commands
+		(State::Iac, _)			=> State::Data,
+
+		(State::Negotiate(cmd), opt)	=> {
+		    ev = Some(Event::Negotiate(cmd, opt));
+		    State::Data
+		}
+
+		(State::Sb, IAC)		=> State::SbIac,
+		(State::Sb, c)			=> {
+		    self.push_sb(c);
+		    State::Sb
+		}
+
+		(State::SbIac, IAC)		=> {
+		    self.push_sb(IAC);
+		    State::Sb
+		}
+
+		(State::SbIac, SE)		=> {
+		    ev = self.sb.split_first()
+			.map(|(opt, payload)| Event::Subneg(*opt, payload.to_vec()));
+
+		    State::Data
+		}
+
+		(State::SbIac, c)		=> {
+		    warn!("bad telnet command {c} in subnegotiation");
+		    State::Data
+		}
+	    };
+
+	    if ev.is_some() {
+		return (idx + 1, ev);
+	    }
+	}
+
+	(buf.len(), None)
+    }
+
+    fn push_sb(&mut self, c: u8) {
+	if self.sb.len() < MAX_SB_LEN {
+	    self.sb.push(c);
+	}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+	let mut out = Vec::new();
+
+	escape_into(&mut out, &[1, IAC, 2, IAC, IAC]);
+	assert_eq!(out, [1, IAC, IAC, 2, IAC, IAC, IAC, IAC]);
+
+	out.clear();
+	subneg(&mut out, OPT_COM_PORT, &[1, 0, 0, 0xff, 0xff]);
+	assert_eq!(out, [IAC, SB, OPT_COM_PORT, 1, 0, 0, IAC, IAC, IAC, IAC, IAC, SE]);
+    }
+
+    #[test]
+    fn test_decode() {
+	let mut dec = Decoder::default();
+	let mut data = VecDeque::new();
+	let mut events = Vec::new();
+
+	let stream = [
+	    b'a', IAC, IAC, b'b',
+	    IAC, WILL, OPT_BINARY,
+	    IAC, SB, OPT_COM_PORT, 107, IAC, IAC, IAC, SE,
+	    b'c',
+	];
+
+	// feed the stream in every possible split to check that state is
+	// kept across calls
+	for pos in 0..stream.len() {
+	    data.clear();
+	    events.clear();
+
+	    dec.feed(&stream[..pos], &mut data, &mut events);
+	    dec.feed(&stream[pos..], &mut data, &mut events);
+
+	    assert_eq!(data, [b'a', IAC, b'b', b'c']);
+	    assert_eq!(events, [
+		Event::Negotiate(WILL, OPT_BINARY),
+		Event::Subneg(OPT_COM_PORT, vec![107, IAC]),
+	    ]);
+	}
+
+	data.clear();
+
+	assert_eq!(dec.next(&stream, &mut data), (7, Some(Event::Negotiate(WILL, OPT_BINARY))));
+	assert_eq!(data, [b'a', IAC, b'b']);
+	assert_eq!(dec.next(&stream[7..], &mut data), (8, Some(Event::Subneg(OPT_COM_PORT, vec![107, IAC]))));
+	assert_eq!(dec.next(&stream[15..], &mut data), (1, None));
+	assert_eq!(data, [b'a', IAC, b'b', b'c']);
+    }
+}
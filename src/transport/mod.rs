@@ -0,0 +1,326 @@
+//! Connections between `cuse2net-cuse` and `cuse2net-dev`
+
+pub mod tls;
+mod vsock;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, TcpListener, Shutdown};
+use std::os::fd::{AsFd, BorrowedFd};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixStream, UnixListener};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use vsock::{VsockStream, VsockListener};
+
+/// Address of the server
+///
+/// Written as `ip:port`, `unix:PATH` or `vsock:CID:PORT`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Address {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+    Vsock {
+	cid:	u32,
+	port:	u32,
+    },
+}
+
+impl std::fmt::Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	match self {
+	    Self::Tcp(addr)		=> addr.fmt(f),
+	    Self::Unix(path)		=> write!(f, "unix:{}", path.display()),
+	    Self::Vsock { cid, port }	=> write!(f, "vsock:{cid}:{port}"),
+	}
+    }
+}
+
+impl std::str::FromStr for Address {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+	if let Some(path) = s.strip_prefix("unix:") {
+	    if path.is_empty() {
+		return Err("missing path of unix socket".to_string());
+	    }
+
+	    Ok(Self::Unix(path.into()))
+	} else if let Some(addr) = s.strip_prefix("vsock:") {
+	    let (cid, port) = addr.rsplit_once(':')
+		.ok_or_else(|| "vsock address must be 'vsock:CID:PORT'".to_string())?;
+
+	    Ok(Self::Vsock {
+		cid:	vsock::parse_cid(cid)?,
+		port:	port.parse().map_err(|e| format!("bad vsock port '{port}': {e}"))?,
+	    })
+	} else {
+	    s.parse()
+		.map(Self::Tcp)
+		.map_err(|e| format!("bad address '{s}': {e}"))
+	}
+    }
+}
+
+/// TCP keepalive settings
+#[derive(Clone, Copy, Debug)]
+pub struct Keepalive {
+    /// idle time before the first probe and time between probes
+    pub interval:	Duration,
+    /// number of unanswered probes after which the connection is dropped
+    pub count:		u32,
+}
+
+impl Keepalive {
+    fn apply(&self, s: &TcpStream) -> std::io::Result<()> {
+	use nix::sys::socket::{setsockopt, sockopt};
+
+	let secs = self.interval.as_secs().clamp(1, i32::MAX as u64) as u32;
+
+	setsockopt(s, sockopt::KeepAlive, &true)?;
+	setsockopt(s, sockopt::TcpKeepIdle, &secs)?;
+	setsockopt(s, sockopt::TcpKeepInterval, &secs)?;
+	setsockopt(s, sockopt::TcpKeepCount, &self.count)?;
+
+	Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+    Vsock(VsockStream),
+    /// local end of a TLS relay; see [`tls`]
+    Tls(UnixStream),
+}
+
+impl Stream {
+    pub fn shutdown(&self, how: Shutdown) -> std::io::Result<()> {
+	match self {
+	    Self::Tcp(s)	=> s.shutdown(how),
+	    Self::Unix(s)	=> s.shutdown(how),
+	    Self::Vsock(s)	=> s.shutdown(how),
+	    Self::Tls(s)	=> s.shutdown(how),
+	}
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()> {
+	match self {
+	    Self::Tcp(s)	=> s.set_nonblocking(nonblocking),
+	    Self::Unix(s)	=> s.set_nonblocking(nonblocking),
+	    Self::Vsock(s)	=> s.set_nonblocking(nonblocking),
+	    Self::Tls(s)	=> s.set_nonblocking(nonblocking),
+	}
+    }
+
+    /// Enables TCP keepalive; noop for other kinds of connections
+    pub fn set_keepalive(&self, keepalive: Option<&Keepalive>) -> std::io::Result<()> {
+	match (self, keepalive) {
+	    (Self::Tcp(s), Some(ka))	=> ka.apply(s),
+	    _				=> Ok(()),
+	}
+    }
+
+    pub fn set_read_timeout(&self, tm: Option<Duration>) -> std::io::Result<()> {
+	match self {
+	    Self::Tcp(s)	=> s.set_read_timeout(tm),
+	    Self::Unix(s)	=> s.set_read_timeout(tm),
+	    Self::Vsock(s)	=> s.set_read_timeout(tm),
+	    Self::Tls(s)	=> s.set_read_timeout(tm),
+	}
+    }
+}
+
+impl AsFd for Stream {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+	match self {
+	    Self::Tcp(s)	=> s.as_fd(),
+	    Self::Unix(s)	=> s.as_fd(),
+	    Self::Vsock(s)	=> s.as_fd(),
+	    Self::Tls(s)	=> s.as_fd(),
+	}
+    }
+}
+
+impl Read for &Stream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+	match self {
+	    Stream::Tcp(s)	=> (&*s).read(buf),
+	    Stream::Unix(s)	=> (&*s).read(buf),
+	    Stream::Vsock(s)	=> (&*s).read(buf),
+	    Stream::Tls(s)	=> (&*s).read(buf),
+	}
+    }
+}
+
+impl Write for &Stream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+	match self {
+	    Stream::Tcp(s)	=> (&*s).write(buf),
+	    Stream::Unix(s)	=> (&*s).write(buf),
+	    Stream::Vsock(s)	=> (&*s).write(buf),
+	    Stream::Tls(s)	=> (&*s).write(buf),
+	}
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+	match self {
+	    Stream::Tcp(s)	=> (&*s).flush(),
+	    Stream::Unix(s)	=> (&*s).flush(),
+	    Stream::Vsock(s)	=> (&*s).flush(),
+	    Stream::Tls(s)	=> (&*s).flush(),
+	}
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+	(&*self).read(buf)
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+	(&*self).write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+	(&*self).flush()
+    }
+}
+
+/// Establishes connections to the server
+pub struct Connector {
+    addr:	Address,
+    tls:	Option<tls::Client>,
+    keepalive:	Option<Keepalive>,
+}
+
+impl std::fmt::Debug for Connector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	f.debug_struct("Connector")
+	    .field("addr", &self.addr)
+	    .field("tls", &self.tls.is_some())
+	    .field("keepalive", &self.keepalive)
+	    .finish()
+    }
+}
+
+impl Connector {
+    pub fn new(addr: Address, tls: Option<tls::Client>, keepalive: Option<Keepalive>) -> Self {
+	Self {
+	    addr:	addr,
+	    tls:	tls,
+	    keepalive:	keepalive,
+	}
+    }
+
+    pub fn addr(&self) -> &Address {
+	&self.addr
+    }
+
+    pub fn connect(&self, timeout: Duration) -> crate::Result<Stream> {
+	let conn = match &self.addr {
+	    Address::Tcp(addr)		=> {
+		let conn = TcpStream::connect_timeout(addr, timeout)?;
+
+		conn.set_nodelay(true)?;
+
+		if let Some(ka) = &self.keepalive {
+		    ka.apply(&conn)?;
+		}
+
+		Stream::Tcp(conn)
+	    }
+
+	    Address::Unix(path)		=> Stream::Unix(UnixStream::connect(path)?),
+	    Address::Vsock { cid, port }	=> Stream::Vsock(VsockStream::connect(*cid, *port)?),
+	};
+
+	match &self.tls {
+	    None	=> Ok(conn),
+	    Some(tls)	=> tls.connect(conn),
+	}
+    }
+}
+
+/// Accepts connections from clients
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+    Vsock(VsockListener),
+}
+
+impl Listener {
+    pub fn bind(addr: &Address) -> crate::Result<Self> {
+	Ok(match addr {
+	    Address::Tcp(addr)		=> Self::Tcp(TcpListener::bind(addr)?),
+
+	    Address::Unix(path)		=> {
+		// remove stale socket from a previous run
+		match std::fs::symlink_metadata(path) {
+		    Ok(m) if m.file_type().is_socket()	=> std::fs::remove_file(path)?,
+		    _					=> {},
+		}
+
+		Self::Unix(UnixListener::bind(path)?)
+	    }
+
+	    Address::Vsock { cid, port }	=> Self::Vsock(VsockListener::bind(*cid, *port)?),
+	})
+    }
+
+    /// Waits for the next connection; returns it together with a
+    /// description of the peer
+    pub fn accept(&self) -> crate::Result<(Stream, String)> {
+	Ok(match self {
+	    Self::Tcp(l)	=> {
+		let (conn, addr) = l.accept()?;
+
+		conn.set_nodelay(true)?;
+		(Stream::Tcp(conn), addr.to_string())
+	    }
+
+	    Self::Unix(l)	=> {
+		let (conn, _) = l.accept()?;
+
+		(Stream::Unix(conn), "unix".to_string())
+	    }
+
+	    Self::Vsock(l)	=> {
+		let conn = l.accept()?;
+		let peer = match conn.peer_addr() {
+		    Ok((cid, port))	=> format!("vsock:{cid}:{port}"),
+		    Err(_)		=> "vsock".to_string(),
+		};
+
+		(Stream::Vsock(conn), peer)
+	    }
+	})
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Address;
+
+    #[test]
+    fn test_address() {
+	assert_eq!("127.0.0.1:8000".parse(), Ok(Address::Tcp(([127, 0, 0, 1], 8000).into())));
+	assert_eq!("unix:/run/cuse2net.sock".parse(), Ok(Address::Unix("/run/cuse2net.sock".into())));
+	assert_eq!("vsock:3:8000".parse(), Ok(Address::Vsock { cid: 3, port: 8000 }));
+	assert_eq!("vsock:host:8000".parse(), Ok(Address::Vsock { cid: 2, port: 8000 }));
+	assert_eq!("vsock:any:8000".parse(), Ok(Address::Vsock { cid: u32::MAX, port: 8000 }));
+
+	assert!("unix:".parse::<Address>().is_err());
+	assert!("vsock:3".parse::<Address>().is_err());
+	assert!("vsock:foo:8000".parse::<Address>().is_err());
+	assert!("127.0.0.1".parse::<Address>().is_err());
+
+	for s in ["[::1]:8000", "unix:/tmp/x", "vsock:3:8000"] {
+	    assert_eq!(s.parse::<Address>().unwrap().to_string(), s);
+	}
+    }
+}
@@ -0,0 +1,541 @@
+//! TLS transport
+//!
+//! The protocol layer works directly on socket file descriptors (it uses
+//! `recv(2)` with timeouts and vectored `sendmsg(2)`) and is used
+//! concurrently by several threads.  Hence, TLS is not applied inline but
+//! by a relay thread which owns the network connection and the TLS state and
+//! which forwards the plaintext to a local unix socket pair.  The other end
+//! of this pair is returned as [`Stream::Tls`].
+
+use std::io::{Read, Write, ErrorKind};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use nix::poll::{PollFd, PollFlags};
+
+use rustls::{ClientConfig, ServerConfig, Connection, ClientConnection, ServerConnection,
+	     RootCertStore, DigitallySignedStruct, SignatureScheme, CertificateError};
+use rustls::client::danger::{ServerCertVerifier, ServerCertVerified, HandshakeSignatureValid};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::pki_types::pem::PemObject;
+use rustls::server::WebPkiClientVerifier;
+
+use super::{Stream, Address};
+use crate::Error;
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const BUF_SZ: usize = 16 * 1024;
+
+/// SHA-256 fingerprint of a DER encoded certificate
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Fingerprint([u8; 32]);
+
+impl Fingerprint {
+    pub fn of_cert(cert: &CertificateDer) -> Self {
+	let digest = ring::digest::digest(&ring::digest::SHA256, cert.as_ref());
+	let mut res = [0; 32];
+
+	res.copy_from_slice(digest.as_ref());
+
+	Self(res)
+    }
+}
+
+impl std::fmt::Debug for Fingerprint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	let s: Vec<_> = self.0.iter().map(|b| format!("{b:02X}")).collect();
+
+	f.write_str(&s.join(":"))
+    }
+}
+
+/// Parses the hex representation of a fingerprint; octets can be
+/// separated by ':' (like in the output of `openssl x509 -fingerprint`)
+impl std::str::FromStr for Fingerprint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+	// accept output of 'openssl x509 -fingerprint -sha256' too
+	let s = s.rsplit_once('=').map_or(s, |(_, v)| v);
+	let s: String = s.chars().filter(|c| *c != ':').collect();
+	let mut res = [0; 32];
+
+	if s.len() != 2 * res.len() || !s.is_ascii() {
+	    return Err(format!("fingerprint must consist of {} hex digits", 2 * res.len()));
+	}
+
+	for (idx, v) in res.iter_mut().enumerate() {
+	    *v = u8::from_str_radix(&s[2 * idx..2 * idx + 2], 16)
+		.map_err(|e| format!("bad fingerprint: {e}"))?;
+	}
+
+	Ok(Self(res))
+    }
+}
+
+fn config_err<E: std::fmt::Display>(what: &str, e: E) -> Error {
+    Error::TlsConfig(format!("{what}: {e}"))
+}
+
+fn load_certs(path: &Path) -> crate::Result<Vec<CertificateDer<'static>>> {
+    let res = CertificateDer::pem_file_iter(path)
+	.and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+	.map_err(|e| config_err(&format!("failed to load certificates from {path:?}"), e))?;
+
+    if res.is_empty() {
+	return Err(Error::TlsConfig(format!("no certificates in {path:?}")));
+    }
+
+    Ok(res)
+}
+
+fn load_key(path: &Path) -> crate::Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path)
+	.map_err(|e| config_err(&format!("failed to load key from {path:?}"), e))
+}
+
+fn load_roots(path: &Path) -> crate::Result<Arc<RootCertStore>> {
+    let mut roots = RootCertStore::empty();
+
+    for cert in load_certs(path)? {
+	roots.add(cert)?;
+    }
+
+    Ok(Arc::new(roots))
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+/// Verifies the server certificate against a list of pinned fingerprints
+/// and optionally against a CA
+#[derive(Debug)]
+struct PinnedVerifier {
+    pins:	Vec<Fingerprint>,
+    webpki:	Option<Arc<WebPkiServerVerifier>>,
+    provider:	Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(&self, end_entity: &CertificateDer<'_>,
+			  intermediates: &[CertificateDer<'_>],
+			  server_name: &ServerName<'_>, ocsp_response: &[u8],
+			  now: UnixTime) -> Result<ServerCertVerified, rustls::Error> {
+	if let Some(webpki) = &self.webpki {
+	    webpki.verify_server_cert(end_entity, intermediates, server_name,
+				      ocsp_response, now)?;
+	}
+
+	let fpr = Fingerprint::of_cert(end_entity);
+
+	if !self.pins.contains(&fpr) {
+	    warn!("server certificate {fpr:?} is not pinned");
+	    return Err(rustls::Error::InvalidCertificate(CertificateError::ApplicationVerificationFailure));
+	}
+
+	Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer<'_>,
+			      dss: &DigitallySignedStruct)
+			      -> Result<HandshakeSignatureValid, rustls::Error> {
+	rustls::crypto::verify_tls12_signature(message, cert, dss,
+					       &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>,
+			      dss: &DigitallySignedStruct)
+			      -> Result<HandshakeSignatureValid, rustls::Error> {
+	rustls::crypto::verify_tls13_signature(message, cert, dss,
+					       &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+	self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ClientParams {
+    /// CA which signed the server certificate
+    pub ca:		Option<PathBuf>,
+    /// accepted server certificates
+    pub fingerprints:	Vec<Fingerprint>,
+    /// client certificate chain and its key
+    pub cert:		Option<(PathBuf, PathBuf)>,
+    /// name which is expected in the server certificate; when not set,
+    /// the ip address is used.  Must be given when the server is
+    /// verified by a CA but is not reached by an ip address
+    pub server_name:	Option<String>,
+}
+
+pub struct Client {
+    config:		Arc<ClientConfig>,
+    server_name:	ServerName<'static>,
+}
+
+impl Client {
+    pub fn new(params: ClientParams, addr: &Address) -> crate::Result<Self> {
+	let provider = provider();
+	let builder = ClientConfig::builder_with_provider(provider.clone())
+	    .with_safe_default_protocol_versions()?;
+
+	let roots = params.ca.as_deref().map(load_roots).transpose()?;
+
+	let builder = match (roots, params.fingerprints.is_empty()) {
+	    (None, true)	=>
+		return Err(Error::TlsConfig("neither CA nor fingerprint given".to_string())),
+
+	    (Some(roots), true)	=> builder.with_root_certificates(roots),
+
+	    (roots, false)	=> {
+		let webpki = roots
+		    .map(|roots| WebPkiServerVerifier::builder_with_provider(roots, provider.clone())
+			 .build())
+		    .transpose()
+		    .map_err(|e| config_err("bad CA", e))?;
+
+		builder
+		    .dangerous()
+		    .with_custom_certificate_verifier(Arc::new(PinnedVerifier {
+			pins:		params.fingerprints,
+			webpki:		webpki,
+			provider:	provider,
+		    }))
+	    }
+	};
+
+	let config = match params.cert {
+	    None		=> builder.with_no_client_auth(),
+	    Some((cert, key))	=> builder.with_client_auth_cert(load_certs(&cert)?, load_key(&key)?)?,
+	};
+
+	let server_name = match (params.server_name, addr) {
+	    (Some(name), _)		=> ServerName::try_from(name)
+		.map_err(|e| config_err("bad server name", e))?,
+
+	    (None, Address::Tcp(addr))	=> ServerName::IpAddress(addr.ip().into()),
+
+	    // name is not checked when only fingerprints are used
+	    (None, _) if params.ca.is_none()	=> ServerName::try_from("localhost").unwrap(),
+
+	    (None, _)			=>
+		return Err(Error::TlsConfig(format!("server name required for {addr}"))),
+	};
+
+	Ok(Self {
+	    config:		Arc::new(config),
+	    server_name:	server_name,
+	})
+    }
+
+    pub fn connect(&self, sock: Stream) -> crate::Result<Stream> {
+	let conn = ClientConnection::new(self.config.clone(), self.server_name.clone())?;
+
+	spawn_relay(conn.into(), sock)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ServerParams {
+    /// server certificate chain and its key
+    pub cert:		PathBuf,
+    pub key:		PathBuf,
+    /// CA which signed the client certificates; when set, clients must
+    /// present a certificate
+    pub ca:		Option<PathBuf>,
+}
+
+pub struct Server {
+    config:	Arc<ServerConfig>,
+}
+
+impl Server {
+    pub fn new(params: ServerParams) -> crate::Result<Self> {
+	let provider = provider();
+	let builder = ServerConfig::builder_with_provider(provider.clone())
+	    .with_safe_default_protocol_versions()?;
+
+	let builder = match &params.ca {
+	    None	=> builder.with_no_client_auth(),
+	    Some(ca)	=> {
+		let verifier = WebPkiClientVerifier::builder_with_provider(load_roots(ca)?, provider)
+		    .build()
+		    .map_err(|e| config_err("bad client CA", e))?;
+
+		builder.with_client_cert_verifier(verifier)
+	    }
+	};
+
+	let config = builder.with_single_cert(load_certs(&params.cert)?,
+					      load_key(&params.key)?)?;
+
+	Ok(Self {
+	    config:	Arc::new(config),
+	})
+    }
+
+    pub fn accept(&self, sock: Stream) -> crate::Result<Stream> {
+	let conn = ServerConnection::new(self.config.clone())?;
+
+	spawn_relay(conn.into(), sock)
+    }
+}
+
+fn handshake(conn: &mut Connection, sock: &mut Stream) -> std::io::Result<()> {
+    sock.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+
+    while conn.is_handshaking() {
+	conn.complete_io(sock)?;
+    }
+
+    sock.set_read_timeout(None)?;
+
+    Ok(())
+}
+
+fn spawn_relay(mut conn: Connection, mut sock: Stream) -> crate::Result<Stream> {
+    handshake(&mut conn, &mut sock)?;
+
+    debug!("TLS handshake completed");
+
+    let (local, remote) = UnixStream::pair()?;
+
+    let relay = Relay {
+	conn:		conn,
+	sock:		sock,
+	local:		remote,
+	to_local:	Vec::new(),
+    };
+
+    std::thread::Builder::new()
+	.name("tls".to_string())
+	.spawn(move || {
+	    if let Err(e) = relay.run() {
+		warn!("TLS relay failed: {e:?}");
+	    }
+	})?;
+
+    Ok(Stream::Tls(local))
+}
+
+struct Relay {
+    conn:	Connection,
+    sock:	Stream,
+    local:	UnixStream,
+    /// plaintext which was received from the TLS peer but not yet passed
+    /// to the local socket
+    to_local:	Vec<u8>,
+}
+
+fn is_would_block(e: &std::io::Error) -> bool {
+    e.kind() == ErrorKind::WouldBlock
+}
+
+impl Relay {
+    fn run(mut self) -> std::io::Result<()> {
+	self.sock.set_nonblocking(true)?;
+	self.local.set_nonblocking(true)?;
+
+	let res = self.run_internal();
+
+	// terminates the protocol layer on the local side
+	let _ = self.local.shutdown(Shutdown::Both);
+
+	// best effort; the socket might be broken already
+	self.conn.send_close_notify();
+	let _ = self.flush_tls();
+	let _ = self.sock.shutdown(Shutdown::Both);
+
+	res
+    }
+
+    fn flush_tls(&mut self) -> std::io::Result<()> {
+	while self.conn.wants_write() {
+	    match self.conn.write_tls(&mut self.sock) {
+		Ok(_)				=> {},
+		Err(e) if is_would_block(&e)	=> break,
+		Err(e)				=> return Err(e),
+	    }
+	}
+
+	Ok(())
+    }
+
+    /// Reads TLS records from the network; returns `false` when peer
+    /// closed the connection
+    fn handle_tls_rx(&mut self, buf: &mut [u8]) -> std::io::Result<bool> {
+	match self.conn.read_tls(&mut self.sock) {
+	    Ok(0)				=> return Ok(false),
+	    Ok(_)				=> {},
+	    Err(e) if is_would_block(&e)	=> return Ok(true),
+	    Err(e)				=> return Err(e),
+	}
+
+	let state = self.conn.process_new_packets()
+	    .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+
+	let mut avail = state.plaintext_bytes_to_read();
+
+	while avail > 0 {
+	    let l = avail.min(buf.len());
+	    let l = self.conn.reader().read(&mut buf[..l])?;
+
+	    self.to_local.extend_from_slice(&buf[..l]);
+	    avail -= l;
+	}
+
+	Ok(!state.peer_has_closed())
+    }
+
+    /// Reads plaintext from the local socket; returns `false` when local
+    /// side closed the connection
+    fn handle_local_rx(&mut self, buf: &mut [u8]) -> std::io::Result<bool> {
+	match (&self.local).read(buf) {
+	    Ok(0)				=> Ok(false),
+	    Ok(l)				=> {
+		self.conn.writer().write_all(&buf[..l])?;
+		Ok(true)
+	    }
+	    Err(e) if is_would_block(&e)	=> Ok(true),
+	    Err(e)				=> Err(e),
+	}
+    }
+
+    fn handle_local_tx(&mut self) -> std::io::Result<()> {
+	match (&self.local).write(&self.to_local) {
+	    Ok(l)				=> {
+		self.to_local.drain(..l);
+		Ok(())
+	    }
+	    Err(e) if is_would_block(&e)	=> Ok(()),
+	    Err(e)				=> Err(e),
+	}
+    }
+
+    fn run_internal(&mut self) -> std::io::Result<()> {
+	let mut buf = vec![0_u8; BUF_SZ];
+	let mut tls_open = true;
+	let mut sock_open = true;
+	let ev_rx = PollFlags::POLLIN;
+	let ev_hup = PollFlags::POLLHUP | PollFlags::POLLERR;
+	let ev_closed = ev_rx | ev_hup;
+
+	while tls_open || !self.to_local.is_empty() {
+	    let mut ev_sock = PollFlags::empty();
+	    let mut ev_local = PollFlags::empty();
+
+	    // do not read more data than can be passed to the local side
+	    if tls_open && self.to_local.len() < BUF_SZ {
+		ev_sock |= ev_rx;
+	    }
+
+	    // do not accept more plaintext before previous one has been sent
+	    if self.conn.wants_write() {
+		if sock_open {
+		    ev_sock |= PollFlags::POLLOUT;
+		}
+	    } else if tls_open {
+		ev_local |= ev_rx;
+	    }
+
+	    if !self.to_local.is_empty() {
+		ev_local |= PollFlags::POLLOUT;
+	    }
+
+	    let (rev_sock, rev_local) = {
+		// poll(2) reports POLLHUP and POLLERR for an empty event mask
+		// too; such fds are left out to avoid busy looping.  At least
+		// one mask is never empty because either 'tls_open' is set or
+		// data for the local side is pending.
+		let mut fds = Vec::with_capacity(2);
+
+		if !ev_sock.is_empty() {
+		    fds.push(PollFd::new(&self.sock, ev_sock));
+		}
+
+		if !ev_local.is_empty() {
+		    fds.push(PollFd::new(&self.local, ev_local));
+		}
+
+		nix::poll::poll(&mut fds, -1)?;
+
+		let mut revents = fds.iter()
+		    .map(|fd| fd.revents().unwrap_or(PollFlags::empty()));
+
+		(if ev_sock.is_empty() { PollFlags::empty() } else { revents.next().unwrap() },
+		 if ev_local.is_empty() { PollFlags::empty() } else { revents.next().unwrap() })
+	    };
+
+	    if ev_sock.intersects(ev_rx) && rev_sock.intersects(ev_closed) {
+		tls_open = self.handle_tls_rx(&mut buf)?;
+	    } else if rev_sock.intersects(ev_hup) {
+		// the peer is gone; pass the already received data to the
+		// local side before exiting
+		tls_open = false;
+		sock_open = false;
+	    }
+
+	    if rev_local.intersects(PollFlags::POLLOUT) {
+		self.handle_local_tx()?;
+	    }
+
+	    if ev_local.intersects(ev_rx) && rev_local.intersects(ev_closed) {
+		if !self.handle_local_rx(&mut buf)? {
+		    return Ok(());
+		}
+	    } else if rev_local.intersects(ev_hup) {
+		// the local side is gone; nothing can be delivered anymore
+		return Ok(());
+	    }
+
+	    if sock_open {
+		self.flush_tls()?;
+	    }
+	}
+
+	Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn verify(verifier: &PinnedVerifier, cert: &CertificateDer) -> bool {
+	let name = ServerName::try_from("localhost").unwrap();
+
+	verifier.verify_server_cert(cert, &[], &name, &[], UnixTime::now()).is_ok()
+    }
+
+    #[test]
+    fn test_fingerprint() {
+	let cert = CertificateDer::from(vec![0x30, 0x03, 0x02, 0x01, 0x01]);
+	let fpr = Fingerprint::of_cert(&cert);
+
+	assert_eq!(format!("{fpr:?}").parse::<Fingerprint>(), Ok(fpr));
+	assert_eq!(format!("SHA256 Fingerprint={fpr:?}").parse::<Fingerprint>(), Ok(fpr));
+	assert!("00:11".parse::<Fingerprint>().is_err());
+    }
+
+    #[test]
+    fn test_pinned() {
+	let cert = CertificateDer::from(vec![0x30, 0x03, 0x02, 0x01, 0x01]);
+	let other = CertificateDer::from(vec![0x30, 0x03, 0x02, 0x01, 0x02]);
+
+	let verifier = PinnedVerifier {
+	    pins:	vec![Fingerprint::of_cert(&cert)],
+	    webpki:	None,
+	    provider:	provider(),
+	};
+
+	assert!(verify(&verifier, &cert));
+	assert!(!verify(&verifier, &other));
+    }
+}
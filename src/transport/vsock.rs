@@ -0,0 +1,121 @@
+//! AF_VSOCK sockets; they are not supported by the standard library
+
+use std::io::{Read, Write};
+use std::net::Shutdown;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
+use std::time::Duration;
+
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::sys::socket::{self, AddressFamily, SockType, SockFlag, MsgFlags, VsockAddr, sockopt};
+use nix::sys::time::TimeVal;
+
+const VMADDR_CID_ANY: u32 = u32::MAX;
+const VMADDR_CID_HYPERVISOR: u32 = 0;
+const VMADDR_CID_LOCAL: u32 = 1;
+const VMADDR_CID_HOST: u32 = 2;
+
+/// Parses a context id; besides numbers, the well known names `any`,
+/// `hypervisor`, `local` and `host` are accepted
+pub fn parse_cid(s: &str) -> Result<u32, String> {
+    match s {
+	"any"		=> Ok(VMADDR_CID_ANY),
+	"hypervisor"	=> Ok(VMADDR_CID_HYPERVISOR),
+	"local"		=> Ok(VMADDR_CID_LOCAL),
+	"host"		=> Ok(VMADDR_CID_HOST),
+	s		=> s.parse().map_err(|e| format!("bad vsock cid '{s}': {e}")),
+    }
+}
+
+fn new_socket() -> nix::Result<OwnedFd> {
+    socket::socket(AddressFamily::Vsock, SockType::Stream, SockFlag::SOCK_CLOEXEC, None)
+}
+
+#[derive(Debug)]
+pub struct VsockStream(OwnedFd);
+
+impl VsockStream {
+    pub fn connect(cid: u32, port: u32) -> std::io::Result<Self> {
+	let fd = new_socket()?;
+
+	socket::connect(fd.as_raw_fd(), &VsockAddr::new(cid, port))?;
+
+	Ok(Self(fd))
+    }
+
+    pub fn peer_addr(&self) -> std::io::Result<(u32, u32)> {
+	let addr: VsockAddr = socket::getpeername(self.0.as_raw_fd())?;
+
+	Ok((addr.cid(), addr.port()))
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> std::io::Result<()> {
+	let how = match how {
+	    Shutdown::Read	=> socket::Shutdown::Read,
+	    Shutdown::Write	=> socket::Shutdown::Write,
+	    Shutdown::Both	=> socket::Shutdown::Both,
+	};
+
+	Ok(socket::shutdown(self.0.as_raw_fd(), how)?)
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()> {
+	let fd = self.0.as_raw_fd();
+	let mut flags = OFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFL)?);
+
+	flags.set(OFlag::O_NONBLOCK, nonblocking);
+	fcntl(fd, FcntlArg::F_SETFL(flags))?;
+
+	Ok(())
+    }
+
+    pub fn set_read_timeout(&self, tm: Option<Duration>) -> std::io::Result<()> {
+	let tm = match tm {
+	    None	=> TimeVal::new(0, 0),
+	    Some(tm)	=> TimeVal::new(tm.as_secs() as _, tm.subsec_micros() as _),
+	};
+
+	Ok(socket::setsockopt(&self.0, sockopt::ReceiveTimeout, &tm)?)
+    }
+}
+
+impl AsFd for VsockStream {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+	self.0.as_fd()
+    }
+}
+
+impl Read for &VsockStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+	Ok(socket::recv(self.0.as_raw_fd(), buf, MsgFlags::empty())?)
+    }
+}
+
+impl Write for &VsockStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+	Ok(socket::send(self.0.as_raw_fd(), buf, MsgFlags::MSG_NOSIGNAL)?)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+	Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct VsockListener(OwnedFd);
+
+impl VsockListener {
+    pub fn bind(cid: u32, port: u32) -> std::io::Result<Self> {
+	let fd = new_socket()?;
+
+	socket::bind(fd.as_raw_fd(), &VsockAddr::new(cid, port))?;
+	socket::listen(&fd, 16)?;
+
+	Ok(Self(fd))
+    }
+
+    pub fn accept(&self) -> std::io::Result<VsockStream> {
+	let fd = socket::accept4(self.0.as_raw_fd(), SockFlag::SOCK_CLOEXEC)?;
+
+	Ok(VsockStream(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }
+}
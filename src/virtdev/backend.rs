@@ -0,0 +1,90 @@
+//! Interface between the CUSE front end and the remote side which
+//! executes the requests
+
+use std::sync::Arc;
+
+use ensc_cuse_ffi::ffi::{self as cuse_ffi, ioctl_flags, fh_flags};
+use ensc_cuse_ffi::{OpInInfo, WriteParams, ReadParams, PollParams};
+
+use ensc_ioctl_ffi::ffi::ioctl;
+
+use crate::CuseFileDevice;
+use crate::proto::{Handle, Sequence};
+use crate::proto::ioctl::Arg;
+
+#[derive(Clone, Debug)]
+pub(super) enum Pending {
+    Release,
+    Write(WriteParams, Vec<u8>),
+    Read(ReadParams),
+    Ioctl{cmd: ioctl, arg: Arg},
+    Poll(PollParams),
+    Interrupt(Sequence),
+}
+
+impl Pending {
+    pub fn is_nonblock(&self) -> bool {
+	match self {
+	    Self::Write(params, _)	=> params.flags.intersects(fh_flags::NONBLOCK),
+	    Self::Read(params)		=> params.flags.intersects(fh_flags::NONBLOCK),
+	    _				=> false,
+	}
+    }
+}
+
+pub(super) trait Backend: Send + Sync {
+    /// Opens the remote device for the file `fh`
+    fn open(self: Arc<Self>, fh: Handle, flags: fh_flags) -> nix::Result<()>;
+
+    /// Processes a request from CUSE; the backend has to answer it
+    fn handle_cuse(&self, fh: Handle, req: Pending, info: OpInInfo);
+
+    fn try_interrupt(&self, info: OpInInfo, unique: cuse_ffi::unique_t);
+
+    fn send_error(&self, info: &OpInInfo, rc: nix::Error);
+}
+
+/// Sends the result of an ioctl to CUSE
+pub(super) fn send_ioctl_response(cuse: &CuseFileDevice, info: OpInInfo, cmd: ioctl, arg: Arg)
+				  -> crate::Result<()> {
+    use ensc_cuse_ffi::AsBytes;
+
+    let data = arg.cuse_response(cmd)?;
+
+    let hdr = cuse_ffi::fuse_ioctl_out {
+	result:		0,
+	flags:		ioctl_flags::UNRESTRICTED,
+	in_iovs:	0,
+	out_iovs:	1,
+    };
+
+    let mut resp_data: [&[u8];2] = [
+	hdr.as_bytes(),
+	&[],
+    ];
+
+    let mut pos = 1;
+
+    if let Some(data) = &data {
+	resp_data[pos] = data.as_ref();
+	pos += 1;
+    }
+
+    debug!("IOCTL: iov={resp_data:?}");
+
+    info.send_response(cuse, &resp_data[..pos])?;
+
+    Ok(())
+}
+
+pub(super) fn send_poll_wakeup(cuse: &CuseFileDevice, kh: u64) -> crate::Result<()> {
+    use ensc_cuse_ffi::AsBytes;
+
+    let notify = cuse_ffi::fuse_notify_poll_wakeup_out {
+	kh:	kh
+    };
+
+    cuse.send_notify(cuse_ffi::fuse_notify_code::FUSE_NOTIFY_POLL, notify.as_bytes())?;
+
+    Ok(())
+}
@@ -4,15 +4,49 @@ use ensc_cuse_ffi::IoctlParams;
 use ensc_cuse_ffi::ffi as cuse_ffi;
 use cuse_ffi::ioctl_flags;
 
-use ensc_ioctl_ffi::BadIoctl;
+use ensc_ioctl_ffi::ffi::ioctl;
 
 use crate::Result;
+use crate::proto::ioctl::{registry, indirect};
+use registry::Dir;
 use crate::CuseDevice;
 
+/// Returns the memory of the caller which is accessed by an ioctl
+/// without embedded pointers
+fn direct_regions(cmd: ioctl, arg: u64) -> indirect::Regions {
+    let (dir, size) = match registry::lookup(cmd) {
+	Some(def)	=> (def.dir, def.size()),
+	None		=> match (cmd.is_read(), cmd.is_write()) {
+	    (true, true)	=> (Dir::ReadWrite, cmd.get_size()),
+	    (true, false)	=> (Dir::Read, cmd.get_size()),
+	    (false, true)	=> (Dir::Write, cmd.get_size()),
+	    (false, false)	=> (Dir::None, 0),
+	},
+    };
+
+    let list = match (dir.is_read() || dir.is_write()) && size > 0 {
+	true	=> vec![indirect::Region::new(arg, size, dir)],
+	false	=> Vec::new(),
+    };
+
+    indirect::Regions {
+	list:		list,
+	complete:	true,
+	skipped:	Vec::new(),
+    }
+}
+
+/// Implements the retry protocol of unrestricted FUSE ioctls.  Every
+/// stage requests the regions which are known with the data of the
+/// previous stage; the ioctl is complete when the kernel delivered
+/// exactly these regions.
+///
+/// Returns `true` when the ioctl can be processed with `data`; else, a
+/// retry or an error was sent.
 pub fn cuse_complete_ioctl<F: AsFd>(
     dev: &CuseDevice<F>, unique: cuse_ffi::unique_t,
     IoctlParams{ flags, cmd, arg, in_size, out_size, .. }: &IoctlParams,
-    _data: &[u8]) -> Result<bool>
+    data: &[u8]) -> Result<bool>
 {
     use ensc_cuse_ffi::AsBytes;
 
@@ -20,71 +54,89 @@ pub fn cuse_complete_ioctl<F: AsFd>(
 	return Ok(true);
     }
 
-    let cmd = BadIoctl::new((*cmd).into());
+    let cmd = ioctl::from(*cmd);
     let in_size = *in_size as usize;
     let out_size = *out_size as usize;
     let flags = *flags;
     let arg = *arg;
 
-    if in_size > 0 || out_size > 0 {
-	return Ok(true)
-    }
+    let regions = match indirect::lookup(cmd) {
+	// the descriptors use the native layout of pointers
+	Some(_) if flags.intersects(ioctl_flags::COMPAT)	=> {
+	    warn!("indirect ioctl {cmd:?} from compat task");
+	    Err(nix::Error::ENOTTY)
+	},
 
-    let info_in = match cmd.is_write() {
-	true		=> Some((arg, cmd.get_size())),
-	false		=> None,
+	Some(desc)	=> desc.regions(cmd, arg, &data[..in_size.min(data.len())])
+	    .map_err(|e| {
+		warn!("bad indirect ioctl {cmd:?}: {e:?}");
+		nix::Error::EINVAL
+	    }),
+
+	None		=> Ok(direct_regions(cmd, arg)),
     };
 
-    let info_out = match cmd.is_read() {
-	true		=> Some((arg, cmd.get_size())),
-	false		=> None,
+    let regions = match regions {
+	Ok(r)		=> r,
+	Err(e)		=> {
+	    dev.send_error(unique, e as u32)?;
+	    return Ok(false);
+	}
     };
 
-    trace!("ioctl-retry: cmd={cmd:?}, in={info_in:?}, out={info_out:?}");
+    let (need_in, need_out) = (regions.in_size(), regions.out_size());
 
-    if info_in.is_none() && info_out.is_none() {
+    if regions.complete && need_in == in_size && need_out == out_size {
 	return Ok(true);
     }
 
-    let info_in = info_in.map(|(base, len)| cuse_ffi::fuse_ioctl_iovec {
-	base:		base,
-	len:		len as u64,
-    });
+    // the caller changed its memory between the stages
+    if need_in < in_size || need_out < out_size {
+	warn!("inconsistent ioctl {cmd:?}: {in_size}/{out_size} vs. {need_in}/{need_out}");
+	dev.send_error(unique, nix::Error::EINVAL as u32)?;
+	return Ok(false);
+    }
 
-    let info_out = info_out.map(|(base, len)| cuse_ffi::fuse_ioctl_iovec {
-	base:		base,
-	len:		len as u64,
-    });
+    let iov_in = regions.list.iter()
+	.filter(|r| r.write)
+	.map(|r| cuse_ffi::fuse_ioctl_iovec {
+	    base:	r.addr,
+	    len:	r.len as u64,
+	});
+
+    let iov_out = regions.list.iter()
+	.filter(|r| r.read)
+	.map(|r| cuse_ffi::fuse_ioctl_iovec {
+	    base:	r.addr,
+	    len:	r.len as u64,
+	});
+
+    let iovs: Vec<_> = iov_in.chain(iov_out).collect();
+    let in_iovs = regions.list.iter().filter(|r| r.write).count();
+
+    if iovs.len() > indirect::MAX_REGIONS {
+	warn!("too much iovecs for ioctl {cmd:?}");
+	dev.send_error(unique, nix::Error::EINVAL as u32)?;
+	return Ok(false);
+    }
 
     let hdr = cuse_ffi::fuse_ioctl_out {
 	result:		0,
 	flags:		flags | ioctl_flags::RETRY,
-	in_iovs:	info_in.as_ref().map(|_| 1).unwrap_or(0),
-	out_iovs:	info_out.as_ref().map(|_| 1).unwrap_or(0),
+	in_iovs:	in_iovs as u32,
+	out_iovs:	(iovs.len() - in_iovs) as u32,
     };
 
-    let mut iov: [&[u8];3] = Default::default();
-    let mut pos = 0;
-
-    iov[pos] = hdr.as_bytes();
-    pos += 1;
+    trace!("ioctl-retry: cmd={cmd:?}, iovs={iovs:?}");
 
+    let mut iov: Vec<&[u8]> = Vec::with_capacity(iovs.len() + 1);
 
-    if let Some(info) = &info_in {
-	iov[pos] = info.as_bytes();
-	pos += 1;
-    }
-
-    if let Some(info) = &info_out {
-	iov[pos] = info.as_bytes();
-	pos += 1;
-    }
-
-    let iov = &iov[..pos];
+    iov.push(hdr.as_bytes());
+    iov.extend(iovs.iter().map(|i| i.as_bytes()));
 
-    debug!("retry {hdr:?} + {iov:?}");
+    debug!("retry {hdr:?} + {iovs:?}");
 
-    dev.send_response(unique, iov)?;
+    dev.send_response(unique, &iov)?;
 
     Ok(false)
 }
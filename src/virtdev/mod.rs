@@ -1,9 +1,18 @@
 //
 
+use std::sync::Arc;
 use std::time::Duration;
 
+use crate::proto;
+use crate::transport::Connector;
+
+mod backend;
 mod registry;
 mod registry_element;
+mod replay;
+mod session;
+mod tty;
+mod stream;
 
 pub mod device;
 mod device_open;
@@ -13,6 +22,30 @@ pub mod ioctl;
 pub use registry::DeviceRegistry;
 use registry_element::DeviceState;
 use device::Device;
+use session::Session;
+use stream::{StreamBackend, Framing};
 use device_open::DeviceOpen;
 
 const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const RECONNECT_DELAY_MIN: Duration = Duration::from_millis(500);
+const RECONNECT_DELAY_MAX: Duration = Duration::from_secs(30);
+
+/// The server to which the requests of a CUSE device are forwarded
+#[derive(Debug)]
+pub enum Remote {
+    /// a `cuse2net-dev` instance
+    Cuse2net {
+	connector:	Arc<Connector>,
+	heartbeat:	proto::Heartbeat,
+    },
+
+    /// a terminal server which speaks RFC 2217
+    Rfc2217 {
+	connector:	Arc<Connector>,
+    },
+
+    /// a TCP port which transports the bytes without further framing
+    Raw {
+	connector:	Arc<Connector>,
+    },
+}
@@ -1,5 +1,4 @@
 use std::collections::HashMap;
-use std::net::SocketAddr;
 use std::sync::Arc;
 use std::sync::atomic::AtomicU64;
 
@@ -9,14 +8,15 @@ use ensc_cuse_ffi::ffi as cuse_ffi;
 use parking_lot::RwLock;
 
 use crate::error::Error;
-use crate::CuseFileDevice;
-
-use super::{ DeviceState, DeviceOpen, Device };
+use crate::{CuseFileDevice, proto};
+use super::{ DeviceState, DeviceOpen, Device, Remote, Session, StreamBackend, Framing };
+use super::backend::Backend;
 
 pub struct DeviceRegistryInner {
     dev_hdl:	AtomicU64,
     devices:	HashMap<cuse_ffi::fh_t, DeviceState>,
     cuse:	Arc<CuseFileDevice>,
+    backend:	Arc<dyn Backend>,
 }
 
 impl DeviceRegistryInner {
@@ -62,30 +62,37 @@ impl std::ops::Drop for ManagedHdl<'_> {
 }
 
 impl DeviceRegistry {
-    fn new_managed_hdl(&self, hdl: cuse_ffi::fh_t) -> ManagedHdl {
+    fn new_managed_hdl(&self, hdl: cuse_ffi::fh_t) -> ManagedHdl<'_> {
 	ManagedHdl {
 	    registry:	self,
 	    hdl:	Some(hdl),
 	}
     }
 
-    pub fn new(cuse: Arc<CuseFileDevice>) -> Self {
+    pub fn new(cuse: Arc<CuseFileDevice>, remote: Remote) -> Self {
+	let backend: Arc<dyn Backend> = match remote {
+	    Remote::Cuse2net { connector, heartbeat }	=>
+		Arc::new(Session::new(cuse.clone(), connector, heartbeat)),
+
+	    Remote::Rfc2217 { connector }		=>
+		Arc::new(StreamBackend::new(cuse.clone(), connector, Framing::Rfc2217)),
+
+	    Remote::Raw { connector }			=>
+		Arc::new(StreamBackend::new(cuse.clone(), connector, Framing::Raw)),
+	};
+
 	Self(Arc::new(RwLock::new(DeviceRegistryInner {
 	    dev_hdl:	AtomicU64::new(1),
 	    devices:	HashMap::new(),
+	    backend:	backend,
 	    cuse:	cuse,
 	})))
     }
 
     pub fn interrupt(&self, info: OpInInfo, unique: cuse_ffi::unique_t) {
-	let this = self.0.write();
+	let backend = self.read().backend.clone();
 
-	for dev in this.devices.values() {
-	    match dev {
-		DeviceState::Opening(_)		=> {}, // noop
-		DeviceState::Running(dev)	=> dev.try_interrupt(info.clone(), unique),
-	    }
-	}
+	backend.try_interrupt(info, unique);
     }
 
     pub fn for_fh<F: FnOnce(&Device)>(&self, fh: cuse_ffi::fh_t, func: F) {
@@ -114,7 +121,7 @@ impl DeviceRegistry {
 	}
     }
 
-    pub fn create(&self, addr: SocketAddr, op_info: OpInInfo, params: OpenParams) -> Result<(), Error>
+    pub fn create(&self, op_info: OpInInfo, params: OpenParams) -> Result<(), Error>
     {
 	let registry = self.clone();
 
@@ -129,16 +136,16 @@ impl DeviceRegistry {
 	std::thread::Builder::new()
 	    .name("open".to_string())
 	    .spawn(move || -> Result<(), Error> {
-		let cuse = registry.read().cuse.clone();
-		let mngd_hdl = registry.new_managed_hdl(dev_hdl);
+		let (cuse, backend) = {
+		    let reg = registry.read();
 
-		let args = super::device::OpenArgs {
-		    addr:		addr,
-		    cuse:		cuse.clone(),
-		    flags:		params.flags,
+		    (reg.cuse.clone(), reg.backend.clone())
 		};
 
-		match Device::open(args) {
+		let mngd_hdl = registry.new_managed_hdl(dev_hdl);
+		let fh = proto::Handle::from_ffi(dev_hdl.as_ffi());
+
+		match Device::open(backend, fh, params.flags) {
 		    Ok(dev)		=> {
 			let hdr = cuse_ffi::fuse_open_out {
 			    fh:		dev_hdl,
@@ -160,9 +167,9 @@ impl DeviceRegistry {
 
 			drop(mngd_hdl);
 
-			let _ = op_info.send_error(&cuse, nix::Error::EIO);
+			let _ = op_info.send_error(&cuse, e);
 
-			Err(e)
+			Err(e.into())
 		    }
 		}
 	    })?;
@@ -0,0 +1,169 @@
+use ensc_ioctl_ffi::ffi as ioctl_ffi;
+use ioctl_ffi::ioctl;
+use ensc_ioctl_ffi::termbits;
+use nix::libc;
+
+use crate::proto::be32;
+use crate::proto::ioctl::Arg;
+
+/// Modem lines which can be controlled by the client
+const TIOCM_CTRL: u32 = (termbits::TIOCM_DTR | termbits::TIOCM_RTS) as u32;
+
+/// Device state which was set by the client and which must be restored
+/// on the remote side after a reconnect
+#[derive(Debug, Default, Clone)]
+pub struct Replay {
+    termios:	Option<(ioctl, Arg)>,
+    mctrl_set:	u32,
+    mctrl_clr:	u32,
+    exclusive:	bool,
+    /// line discipline other than `N_TTY`
+    ldisc:	Option<be32>,
+}
+
+impl Replay {
+    /// Records a successfully executed ioctl
+    pub fn record(&mut self, cmd: ioctl, arg: &Arg) {
+	match (cmd, arg) {
+	    (ioctl::TCSETS |
+	     ioctl::TCSETSW |
+	     ioctl::TCSETSF, Arg::TermIOs(_))	=>
+		self.termios = Some((ioctl::TCSETS, arg.clone())),
+
+	    (ioctl::TCSETS2 |
+	     ioctl::TCSETSW2 |
+	     ioctl::TCSETSF2, Arg::TermIOs(_))	=>
+		self.termios = Some((ioctl::TCSETS2, arg.clone())),
+
+	    // merged into recorded settings like by the kernel; else,
+	    // replayed as TCSETA so that the upper bits of the flags are
+	    // kept like on the original request
+	    (ioctl::TCSETA |
+	     ioctl::TCSETAW |
+	     ioctl::TCSETAF, Arg::TermIOs(ios))	=> {
+		self.termios = Some(match self.termios.take() {
+		    Some((cur_cmd, Arg::TermIOs(cur)))	=>
+			(cur_cmd, Arg::TermIOs(ios.merge_termio(&cur))),
+		    _					=> (ioctl::TCSETA, arg.clone()),
+		});
+	    }
+
+	    (ioctl::TIOCMSET, Arg::Int(v))	=> {
+		let v = v.as_native() & TIOCM_CTRL;
+
+		self.mctrl_set = v;
+		self.mctrl_clr = !v & TIOCM_CTRL;
+	    }
+
+	    (ioctl::TIOCMBIS, Arg::Int(v))	=> {
+		let v = v.as_native() & TIOCM_CTRL;
+
+		self.mctrl_set |= v;
+		self.mctrl_clr &= !v;
+	    }
+
+	    (ioctl::TIOCMBIC, Arg::Int(v))	=> {
+		let v = v.as_native() & TIOCM_CTRL;
+
+		self.mctrl_set &= !v;
+		self.mctrl_clr |= v;
+	    }
+
+	    (ioctl::TIOCEXCL, _)		=> self.exclusive = true,
+	    (ioctl::TIOCNXCL, _)		=> self.exclusive = false,
+
+	    (ioctl::TIOCSETD, Arg::Int(v))	=> {
+		self.ldisc = match v.as_native() as libc::c_int {
+		    ioctl_ffi::N_TTY	=> None,
+		    _			=> Some(*v),
+		};
+	    }
+
+	    _					=> {},
+	}
+    }
+
+    /// Returns the ioctls which restore the recorded state
+    pub fn ioctls(&self) -> Vec<(ioctl, Arg)> {
+	let mut res = Vec::new();
+
+	if let Some(termios) = &self.termios {
+	    res.push(termios.clone());
+	}
+
+	if self.mctrl_set != 0 {
+	    res.push((ioctl::TIOCMBIS, Arg::Int(be32::from_native(self.mctrl_set))));
+	}
+
+	if self.mctrl_clr != 0 {
+	    res.push((ioctl::TIOCMBIC, Arg::Int(be32::from_native(self.mctrl_clr))));
+	}
+
+	if self.exclusive {
+	    res.push((ioctl::TIOCEXCL, Arg::None));
+	}
+
+	if let Some(ldisc) = self.ldisc {
+	    res.push((ioctl::TIOCSETD, Arg::Int(ldisc)));
+	}
+
+	res
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::proto::ioctl::TermIOs;
+    use super::*;
+
+    fn cmds(replay: &Replay) -> Vec<ioctl> {
+	replay.ioctls().into_iter().map(|(cmd, _)| cmd).collect()
+    }
+
+    #[test]
+    fn test_exclusive() {
+	let mut replay = Replay::default();
+
+	replay.record(ioctl::TIOCEXCL, &Arg::None);
+	assert_eq!(cmds(&replay), [ioctl::TIOCEXCL]);
+	assert!(matches!(replay.ioctls()[0].1, Arg::None));
+
+	replay.record(ioctl::TIOCNXCL, &Arg::None);
+	assert!(cmds(&replay).is_empty());
+    }
+
+    fn termios2(cflag: libc::tcflag_t, speed: u32) -> Arg {
+	Arg::TermIOs(TermIOs::from_os2(&ioctl_ffi::termios2 {
+	    c_iflag:	0.into(),
+	    c_oflag:	0.into(),
+	    c_cflag:	cflag.into(),
+	    c_lflag:	0.into(),
+	    c_line:	0,
+	    c_cc:	[0; ioctl_ffi::NCCS],
+	    c_ispeed:	speed,
+	    c_ospeed:	speed,
+	}))
+    }
+
+    #[test]
+    fn test_termio() {
+	let mut replay = Replay::default();
+
+	replay.record(ioctl::TCSETA, &termios2(libc::CS7 | libc::B9600, 0));
+	assert_eq!(cmds(&replay), [ioctl::TCSETA]);
+
+	// TCSETA keeps the upper bits and the speed of TCSETS2
+	replay.record(ioctl::TCSETS2, &termios2(libc::CRTSCTS | libc::CS8 | libc::BOTHER, 12345));
+	replay.record(ioctl::TCSETAW, &termios2(libc::CS7 | libc::BOTHER, 0));
+
+	let res = replay.ioctls();
+	let Arg::TermIOs(ios) = &res[0].1 else {
+	    panic!("bad termios {res:?}");
+	};
+
+	assert_eq!(res[0].0, ioctl::TCSETS2);
+	assert_eq!(ios.cflag() & termbits::CRTSCTS, termbits::CRTSCTS);
+	assert_eq!(ios.cflag() & termbits::CSIZE, termbits::CS7);
+	assert_eq!(ios.ospeed(), 12345);
+    }
+}
@@ -0,0 +1,838 @@
+//! Connection to the server which is shared by all opened files of a
+//! CUSE device
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use parking_lot::RwLock;
+
+use ensc_cuse_ffi::ffi::{self as cuse_ffi, fh_flags};
+use ensc_cuse_ffi::{OpInInfo, ReadParams, PollParams};
+
+use ensc_ioctl_ffi::ffi as ioctl_ffi;
+use ioctl_ffi::ioctl;
+
+use crate::proto::{Handle, Sequence};
+use crate::proto::ioctl::Arg;
+use crate::{CuseFileDevice, Error, proto};
+use crate::transport::{Connector, Stream};
+
+use super::{CONNECT_TIMEOUT, RECONNECT_DELAY_MIN, RECONNECT_DELAY_MAX};
+use super::replay::Replay;
+use super::backend::{self, Backend, Pending};
+
+#[derive(Clone, Debug)]
+enum Request {
+    Release,
+    Write,
+    Read(ReadParams),
+    Ioctl(ioctl, Arg),
+    Poll(PollParams),
+}
+
+/// A file which is opened on the server
+#[derive(Debug)]
+struct Opened {
+    flags:	fh_flags,
+    replay:	Replay,
+}
+
+type OpenResult = mpsc::Sender<nix::Result<()>>;
+
+#[derive(Default)]
+struct State {
+    conn:		Option<Arc<Stream>>,
+    /// the rx thread is running; either the connection is established or
+    /// a reconnect is in progress
+    active:		bool,
+    /// connection to the server is lost and a reconnect is in progress
+    disconnected:	bool,
+    requests:		HashMap<Sequence, (Handle, Request, OpInInfo)>,
+    /// OPEN requests which wait for the response of the server
+    opening:		HashMap<Sequence, (Handle, fh_flags, OpenResult)>,
+    /// OPEN requests which will be sent after the reconnect
+    deferred_open:	Vec<(Handle, fh_flags, OpenResult)>,
+    /// requests which will be sent after the reconnect
+    deferred:		Vec<(Handle, Pending, OpInInfo)>,
+    handles:		HashMap<Handle, Opened>,
+    params:		proto::Session,
+}
+
+pub struct Session {
+    cuse:		Arc<CuseFileDevice>,
+    connector:		Arc<Connector>,
+    heartbeat:		proto::Heartbeat,
+    state:		RwLock<State>,
+}
+
+impl Session {
+    pub fn new(cuse: Arc<CuseFileDevice>, connector: Arc<Connector>,
+	       heartbeat: proto::Heartbeat) -> Self {
+	Self {
+	    cuse:	cuse,
+	    connector:	connector,
+	    heartbeat:	heartbeat,
+	    state:	RwLock::new(State::default()),
+	}
+    }
+
+    /// Returns whether files are open or wait for being opened
+    fn has_handles(&self) -> bool {
+	let state = self.state.read();
+
+	!state.handles.is_empty() || !state.deferred_open.is_empty()
+    }
+
+    fn remove_request(&self, seq: Sequence) -> Option<(Handle, Request, OpInInfo)> {
+	self.state.write().requests.remove(&seq)
+    }
+
+    /// Completes a pending OPEN request; returns `false` when `seq` does
+    /// not belong to such a request
+    fn complete_open(&self, seq: Sequence, res: nix::Result<()>) -> bool {
+	let mut state = self.state.write();
+
+	let Some((fh, flags, tx)) = state.opening.remove(&seq) else {
+	    return false;
+	};
+
+	if res.is_ok() {
+	    state.handles.insert(fh, Opened {
+		flags:	flags,
+		replay:	Replay::default(),
+	    });
+	}
+
+	let _ = tx.send(res);
+
+	true
+    }
+
+    fn handle_ioctl(&self, info: OpInInfo, cmd: ioctl, retval: u64, arg: Arg) -> crate::Result<()> {
+	debug!("IOCTL: {cmd:?}, {retval:?}, {arg:?}");
+
+	backend::send_ioctl_response(&self.cuse, info, cmd, arg)
+    }
+
+    fn handle_error(&self, seq: Sequence, rc: i32) -> crate::Result<()> {
+	if self.complete_open(seq, Err(nix::Error::from_i32(rc))) {
+	    return Ok(());
+	}
+
+	let (_, _, info) = match self.remove_request(seq) {
+	    None	=> {
+		warn!("no such request {seq:?}");
+		return Ok(());
+	    }
+
+	    Some(req)	=> req
+	};
+
+	info.send_error(&self.cuse, nix::Error::from_i32(rc))?;
+
+	Ok(())
+    }
+
+    fn handle_response(&self, seq: Sequence, resp: proto::Response) -> crate::Result<()> {
+	use ensc_cuse_ffi::AsBytes;
+	use proto::Response as R;
+
+	debug!("got response for seq {seq:?}");
+
+	let open_res = match &resp {
+	    R::Ok		=> Ok(()),
+	    R::Err(err)		=> Err(*err),
+	    _			=> Err(nix::Error::EIO),
+	};
+
+	if self.complete_open(seq, open_res) {
+	    return Ok(());
+	}
+
+	let (fh, req, info) = match self.remove_request(seq) {
+	    None	=> {
+		warn!("no such request {seq:?}");
+		return Ok(());
+	    }
+
+	    Some(req)	=> req
+	};
+
+	if let proto::Response::Err(err) = &resp {
+	    info.send_error(&self.cuse, *err)?;
+	    return Ok(());
+	}
+
+	match (req, resp) {
+	    (Request::Release, R::Ok)		=>
+		info.send_ok(&self.cuse)?,
+
+	    (Request::Write, R::Write(sz))	=> {
+		let write_resp = cuse_ffi::fuse_write_out {
+		    size:	sz,
+		    _padding:	0
+		};
+
+		info.send_response(&self.cuse, &[ write_resp.as_bytes() ])?;
+	    }
+
+	    (Request::Read(_), R::Read(data))	=>
+		info.send_response(&self.cuse, &[ &data ])?,
+
+	    (Request::Ioctl(cmd, req_arg), R::Ioctl(retval, arg)) => {
+		if let Some(opened) = self.state.write().handles.get_mut(&fh) {
+		    opened.replay.record(cmd, &req_arg);
+		}
+
+		self.handle_ioctl(info, cmd, retval, arg)?;
+	    }
+
+	    (Request::Poll(_), R::Poll(ev)) => {
+		let poll_resp = cuse_ffi::fuse_poll_out {
+		    revents:	cuse_ffi::poll_events::from_ffi(ev),
+		    padding:	0,
+		};
+
+		info.send_response(&self.cuse, &[ poll_resp.as_bytes() ])?;
+	    }
+
+	    (req, resp)				=> {
+		warn!("unexpected response {resp:?} for {req:?}");
+		return Err(proto::Error::BadResponse.into());
+	    }
+	}
+
+	Ok(())
+    }
+
+    fn handle_event(&self, resp: proto::Response) -> crate::Result<()> {
+	use proto::Response as R;
+
+	match resp {
+	    R::PollWakeup(khs)	=> {
+		for kh in khs {
+		    backend::send_poll_wakeup(&self.cuse, kh)?;
+		}
+	    }
+
+	    R::PollWakeup1(kh)	=>
+		backend::send_poll_wakeup(&self.cuse, kh)?,
+
+	    r			=> {
+		warn!("unexpected event {r:?}");
+		return Err(proto::Error::BadResponse.into());
+	    }
+	}
+
+	Ok(())
+    }
+
+    fn send_ping(&self, conn: &Stream) -> proto::Result<()> {
+	// serialize with the other senders
+	let _state = self.state.write();
+
+	trace!("sending heartbeat");
+	proto::Request::send_ping(conn)?;
+
+	Ok(())
+    }
+
+    /// Processes responses until the connection breaks or the server
+    /// stops answering the heartbeat
+    fn rx_loop(&self, conn: &Stream, params: &proto::Session) {
+	let timeout = self.heartbeat.timeout(params);
+	let mut last_rx = Instant::now();
+	let mut next_ping = params.heartbeat.map(|interval| last_rx + interval);
+
+	loop {
+	    let to = next_ping.map(|t| t.saturating_duration_since(Instant::now()));
+	    let op = proto::Response::recv_timeout(conn, to);
+
+	    match &op {
+		Err(proto::Error::Io(e)) if e.raw_os_error() == Some(nix::libc::ETIMEDOUT)	=> {},
+		_	=> last_rx = Instant::now(),
+	    }
+
+	    if let (Some(ping), Some(interval), Some(timeout)) = (next_ping, params.heartbeat, timeout) {
+		let now = Instant::now();
+
+		if now.duration_since(last_rx) >= timeout {
+		    warn!("server did not respond within {timeout:?}");
+		    break;
+		}
+
+		if now >= ping {
+		    if let Err(e) = self.send_ping(conn) {
+			warn!("failed to send heartbeat: {e:?}");
+			break;
+		    }
+
+		    next_ping = Some(now + interval);
+		}
+	    }
+
+	    match op {
+		Err(proto::Error::Io(e)) if e.raw_os_error() == Some(nix::libc::ETIMEDOUT)	=> {},
+
+		Ok((_, proto::Response::Pong))	=>
+		    trace!("got heartbeat"),
+
+		Ok((Some(seq), resp))	=>
+		    if let Err(e) = self.handle_response(seq, resp) {
+			warn!("failed to process request: {e:?}");
+		    }
+
+		Ok((None, ev))		=>
+		    if let Err(e) = self.handle_event(ev) {
+			warn!("failed to handle event: {e:?}");
+		    }
+
+		Err(proto::Error::RemoteError(Some(seq), rc))	=>
+		    if let Err(e) = self.handle_error(seq, rc) {
+			warn!("failed to handle error: {rc}@{seq:?}: {e:?}");
+		    }
+
+		Err(e)		=> {
+		    warn!("error {e:?}");
+		    break;
+		}
+	    };
+	}
+    }
+
+    /// Marks the connection as lost.  Pending opens, reads and polls are
+    /// queued for retransmission; all other requests are failed because it
+    /// is unknown whether the server executed them.
+    fn disconnect(&self) {
+	let mut state = self.state.write();
+
+	state.disconnected = true;
+	state.conn = None;
+
+	// the server closes the file together with the connection so that
+	// the OPEN can be repeated
+	let opening: Vec<_> = state.opening.drain().collect();
+
+	for (_, (fh, flags, tx)) in opening {
+	    Self::defer_open(&mut state, fh, flags, tx);
+	}
+
+	let requests: Vec<_> = state.requests.drain().collect();
+
+	for (seq, (fh, req, info)) in requests {
+	    match req {
+		Request::Read(params)	=> state.deferred.push((fh, Pending::Read(params), info)),
+		Request::Poll(params)	=> state.deferred.push((fh, Pending::Poll(params), info)),
+
+		// the file is closed on the server together with the
+		// connection
+		Request::Release	=> {
+		    let _ = info.send_ok(&self.cuse);
+		}
+
+		req			=> {
+		    debug!("failing pending request {req:?}@{seq:?}");
+		    self.send_error(&info, nix::Error::EIO);
+		}
+	    }
+	}
+
+	// a non-blocking request must not wait for the reconnect
+	state.deferred.retain(|(_, req, info)| match req.is_nonblock() {
+	    true	=> {
+		self.send_error(info, nix::Error::EAGAIN);
+		false
+	    }
+	    false	=> true,
+	});
+    }
+
+    /// Sleeps for the given duration; returns `false` when all files have
+    /// been closed in the meantime.
+    fn sleep_while_used(&self, mut delay: Duration) -> bool {
+	const STEP: Duration = Duration::from_millis(100);
+
+	while self.has_handles() {
+	    if delay.is_zero() {
+		return true;
+	    }
+
+	    let d = delay.min(STEP);
+
+	    std::thread::sleep(d);
+	    delay -= d;
+	}
+
+	false
+    }
+
+    fn reconnect(&self) -> bool {
+	let mut delay = RECONNECT_DELAY_MIN;
+
+	let (conn, params, mut reopened) = loop {
+	    if !self.sleep_while_used(delay) {
+		return false;
+	    }
+
+	    let files: Vec<_> = self.state.read().handles.iter()
+		.map(|(fh, opened)| (*fh, opened.flags, opened.replay.clone()))
+		.collect();
+
+	    match Self::connect(&self.connector, &self.heartbeat, &files) {
+		Ok((conn, params))	=> {
+		    let reopened: Vec<_> = files.into_iter().map(|(fh, _, _)| fh).collect();
+
+		    break (conn, params, reopened);
+		}
+
+		Err(e)			=> {
+		    warn!("failed to reconnect to {}: {e:?}", self.connector.addr());
+		    delay = (delay * 2).min(RECONNECT_DELAY_MAX);
+		}
+	    }
+	};
+
+	// files which were released while reconnecting must be closed on
+	// the server too; the rx thread is not running yet so that this
+	// can be done synchronously
+	let mut state = loop {
+	    let state = self.state.write();
+	    let stale: Vec<_> = reopened.iter()
+		.filter(|fh| !state.handles.contains_key(fh))
+		.copied()
+		.collect();
+
+	    if stale.is_empty() {
+		break state;
+	    }
+
+	    drop(state);
+
+	    for fh in stale {
+		if let Err(e) = Self::run_remote_release(&conn, fh) {
+		    warn!("failed to release {fh:?}: {e:?}");
+		}
+
+		reopened.retain(|h| *h != fh);
+	    }
+	};
+
+	info!("reconnected to {}", self.connector.addr());
+
+	let conn = Arc::new(conn);
+
+	state.conn = Some(conn.clone());
+	state.params = params;
+	state.disconnected = false;
+
+	// send deferred requests while holding the lock so that they are
+	// transmitted before new ones
+	for (fh, req, info) in core::mem::take(&mut state.deferred) {
+	    if let Err((info, e)) = self.send_pending(&mut state, &conn, fh, req, info) {
+		warn!("failed to send deferred request: {e:?}");
+		self.send_error(&info, nix::Error::EIO);
+	    }
+	}
+
+	for (fh, flags, tx) in core::mem::take(&mut state.deferred_open) {
+	    Self::send_open(&mut state, &conn, fh, flags, tx);
+	}
+
+	true
+    }
+
+    fn rx_thread(self: Arc<Self>) {
+	info!("rx_thread running");
+
+	loop {
+	    let (conn, params) = {
+		let state = self.state.read();
+
+		match &state.conn {
+		    Some(conn)	=> (conn.clone(), state.params),
+		    None	=> break,
+		}
+	    };
+
+	    self.rx_loop(&conn, &params);
+
+	    let _ = conn.shutdown(std::net::Shutdown::Both);
+
+	    warn!("connection to {} lost", self.connector.addr());
+
+	    self.disconnect();
+
+	    if !self.reconnect() {
+		break;
+	    }
+	}
+
+	let mut state = self.state.write();
+
+	// all files are closed; the next open establishes a new connection
+	state.active = false;
+	state.disconnected = false;
+	state.conn = None;
+
+	for info in state.deferred.drain(..) {
+	    debug!("sending INTR to deferred request {info:?}");
+	    self.send_error(&info.2, nix::Error::EINTR);
+	}
+
+	// opens which came in after the reconnect was given up
+	for (_, _, tx) in state.deferred_open.drain(..) {
+	    let _ = tx.send(Err(nix::Error::EIO));
+	}
+
+	info!("rx_thread terminated");
+    }
+
+    /// Queues an OPEN until the connection is reestablished; non-blocking
+    /// opens fail immediately
+    fn defer_open(state: &mut State, fh: Handle, flags: fh_flags, tx: OpenResult) {
+	if flags.intersects(fh_flags::NONBLOCK) {
+	    let _ = tx.send(Err(nix::Error::EAGAIN));
+	} else {
+	    debug!("deferring open of {fh:?} until reconnect");
+	    state.deferred_open.push((fh, flags, tx));
+	}
+    }
+
+    /// Sends an OPEN request; the result is reported by the rx thread
+    fn send_open(state: &mut State, conn: &Stream, fh: Handle, flags: fh_flags, tx: OpenResult) {
+	match proto::Request::send_open(conn, fh, flags) {
+	    Ok(seq)				=> {
+		state.opening.insert(seq, (fh, flags, tx));
+	    }
+
+	    // the rx thread will notice the broken connection and
+	    // retransmit the request after reconnecting
+	    Err(proto::Error::Io(e))		=> {
+		warn!("failed to send OPEN: {e:?}; deferring it");
+		let _ = conn.shutdown(std::net::Shutdown::Both);
+		Self::defer_open(state, fh, flags, tx);
+	    }
+
+	    Err(e)				=> {
+		warn!("failed to send OPEN: {e:?}");
+		let _ = tx.send(Err(nix::Error::EIO));
+	    }
+	}
+    }
+
+    fn send_pending(&self, state: &mut State, conn: &Stream, fh: Handle, req: Pending, info: OpInInfo)
+		    -> Result<(), (OpInInfo, Error)> {
+	let max_msg_size = state.params.max_msg_size;
+
+	let res = match &req {
+	    Pending::Release	=> {
+		proto::Request::send_release(conn, fh)
+		    .map(|seq| (seq, Request::Release))
+	    },
+
+	    Pending::Write(wrinfo, data)	=> {
+		// a short write is reported to the application which has to
+		// send the remaining data again
+		let max = max_msg_size - core::mem::size_of::<proto::request::Write>();
+		let data = &data[..data.len().min(max)];
+
+		proto::Request::send_write(conn, fh, wrinfo.clone(), data)
+		    .map(|seq| (seq, Request::Write))
+	    }
+
+	    Pending::Read(rdinfo)	=> {
+		let mut rdinfo = rdinfo.clone();
+
+		rdinfo.size = rdinfo.size.min(max_msg_size as u32);
+
+		proto::Request::send_read(conn, fh, rdinfo.clone())
+		    .map(|seq| (seq, Request::Read(rdinfo)))
+	    }
+
+	    Pending::Ioctl { cmd, arg }	=>
+		proto::Request::send_ioctl(conn, fh, *cmd, arg.clone())
+		.map(|seq| (seq, Request::Ioctl(*cmd, arg.clone()))),
+
+	    Pending::Poll(pollinfo)		=>
+		proto::Request::send_poll(conn, fh, pollinfo.clone())
+		.map(|seq| (seq, Request::Poll(pollinfo.clone()))),
+
+	    Pending::Interrupt(unique)		=> {
+		return proto::Request::send_interrupt(conn, fh, *unique)
+		    .map_err(|e| (info, e.into()));
+	    }
+	};
+
+	match res {
+	    Err(proto::Error::Io(e))	=> {
+		// the rx thread will notice the broken connection and
+		// retransmit the request after reconnecting
+		warn!("failed to send request: {e:?}; deferring it");
+		let _ = conn.shutdown(std::net::Shutdown::Both);
+		state.deferred.push((fh, req, info));
+		Ok(())
+	    }
+	    Err(e)		=> Err((info, e.into())),
+	    Ok((seq, pending))	=> {
+		state.requests.insert(seq, (fh, pending, info));
+		Ok(())
+	    }
+	}
+    }
+
+    fn handle_cuse_internal(&self, fh: Handle, req: Pending, info: OpInInfo) -> Result<(), (OpInInfo, Error)> {
+	debug!("tx thread: handle {req:?}@{fh:?}");
+
+	let mut state = self.state.write();
+
+	trace!("got state");
+
+	if let Pending::Release = req {
+	    state.handles.remove(&fh);
+	}
+
+	let conn = match (state.disconnected, &state.conn) {
+	    (false, Some(conn))	=> conn.clone(),
+
+	    _			=> {
+		match req {
+		    // the file is closed on the server together with the
+		    // connection
+		    Pending::Release		=> {
+			drop(state);
+			return info.send_ok(&self.cuse).map_err(|e| (info, e.into()));
+		    }
+
+		    Pending::Interrupt(_)	=> {
+			warn!("can not interrupt a request while being disconnected");
+		    }
+
+		    req if req.is_nonblock()	=> {
+			drop(state);
+			self.send_error(&info, nix::Error::EAGAIN);
+		    }
+
+		    req				=> {
+			debug!("deferring {req:?} until reconnect");
+			state.deferred.push((fh, req, info));
+		    }
+		}
+
+		return Ok(());
+	    }
+	};
+
+	self.send_pending(&mut state, &conn, fh, req, info)
+    }
+
+    fn run_remote_open(conn: &Stream, fh: Handle, flags: fh_flags) -> Result<(), Error> {
+	let seq = proto::Request::send_open(conn, fh, flags)?;
+
+	match proto::Response::recv_to(conn) {
+	    Err(proto::Error::RemoteError(r_seq, _)) |
+	    Ok((r_seq, _)) if r_seq != Some(seq)	=> {
+		warn!("bad protocol sequence: {r_seq:?} vs. {seq:?}");
+		return Err(proto::Error::BadSequence.into());
+	    },
+
+	    Ok((_, proto::Response::Ok))		=> {
+		debug!("remote side opened device");
+	    },
+
+	    #[allow(unreachable_patterns)]
+	    Ok((_, resp))				=> {
+		warn!("unexpected response {resp:?}");
+		return Err(proto::Error::BadResponse.into());
+	    }
+
+	    Err(proto::Error::RemoteError(_, err))	=> {
+		warn!("remote side failed to open device: {err}");
+		return Err(Error::Remote(err));
+	    }
+
+	    Err(e)					=> {
+		warn!("failed to receive response for OPEN: {e:?}");
+		return Err(e.into());
+	    }
+	}
+
+	Ok(())
+    }
+
+    fn run_remote_release(conn: &Stream, fh: Handle) -> Result<(), Error> {
+	let seq = proto::Request::send_release(conn, fh)?;
+
+	match proto::Response::recv_to(conn) {
+	    Err(proto::Error::RemoteError(r_seq, _)) |
+	    Ok((r_seq, _)) if r_seq != Some(seq)	=> {
+		warn!("bad protocol sequence: {r_seq:?} vs. {seq:?}");
+		Err(proto::Error::BadSequence.into())
+	    },
+
+	    Ok((_, proto::Response::Ok))		=> Ok(()),
+
+	    Ok((_, resp))				=> {
+		warn!("unexpected response {resp:?}");
+		Err(proto::Error::BadResponse.into())
+	    }
+
+	    Err(proto::Error::RemoteError(_, err))	=> Err(Error::Remote(err)),
+	    Err(e)					=> Err(e.into()),
+	}
+    }
+
+    fn run_remote_ioctl(conn: &Stream, fh: Handle, cmd: ioctl, arg: Arg) -> Result<(), Error> {
+	let seq = proto::Request::send_ioctl(conn, fh, cmd, arg)?;
+
+	match proto::Response::recv_to(conn) {
+	    Err(proto::Error::RemoteError(r_seq, _)) |
+	    Ok((r_seq, _)) if r_seq != Some(seq)	=> {
+		warn!("bad protocol sequence: {r_seq:?} vs. {seq:?}");
+		Err(proto::Error::BadSequence.into())
+	    },
+
+	    Ok((_, proto::Response::Ioctl(..)))		=> Ok(()),
+
+	    Ok((_, resp))				=> {
+		warn!("unexpected response {resp:?}");
+		Err(proto::Error::BadResponse.into())
+	    }
+
+	    Err(proto::Error::RemoteError(_, err))	=> Err(Error::Remote(err)),
+	    Err(e)					=> Err(e.into()),
+	}
+    }
+
+    /// Connects to the server, opens the given files and restores their
+    /// recorded state.
+    fn connect(connector: &Connector, heartbeat: &proto::Heartbeat,
+	       files: &[(Handle, fh_flags, Replay)]) -> Result<(Stream, proto::Session), Error> {
+	let conn = connector.connect(CONNECT_TIMEOUT)?;
+
+	let session = proto::Hello::connect(&conn, heartbeat)
+	    .inspect_err(|e| error!("handshake with {} failed: {e}", connector.addr()))?;
+
+	for (fh, flags, replay) in files {
+	    match Self::run_remote_open(&conn, *fh, *flags) {
+		Ok(_)			=> {},
+
+		// the file stays unusable but other ones might work
+		Err(Error::Remote(err))	=> {
+		    warn!("failed to reopen {fh:?}: {err}");
+		    continue;
+		}
+
+		Err(e)			=> return Err(e),
+	    }
+
+	    for (cmd, arg) in replay.ioctls() {
+		debug!("replaying {cmd:?} {arg:?} on {fh:?}");
+
+		// failure is not fatal; device is usable but might not be in
+		// the expected state
+		if let Err(e) = Self::run_remote_ioctl(&conn, *fh, cmd, arg) {
+		    warn!("failed to replay {cmd:?}: {e:?}");
+		}
+	    }
+	}
+
+	Ok((conn, session))
+    }
+}
+
+impl Backend for Session {
+    fn handle_cuse(&self, fh: Handle, req: Pending, info: OpInInfo)  {
+	match self.handle_cuse_internal(fh, req, info) {
+	    Ok(_)		=> {},
+	    Err((info, e))	=> {
+		warn!("failed to handle request: {e:?}");
+		let _ = info.send_error(&self.cuse, nix::Error::EIO);
+	    }
+	}
+    }
+
+    fn try_interrupt(&self, info: OpInInfo, unique: cuse_ffi::unique_t) {
+	let mut state = self.state.write();
+
+	let deferred = state.deferred.iter()
+	    .position(|(_, _, info)| info.unique == unique);
+
+	if let Some(pos) = deferred {
+	    let (_, req, info) = state.deferred.remove(pos);
+
+	    trace!("interrupting deferred request {req:?}");
+
+	    drop(state);
+	    self.send_error(&info, nix::Error::EINTR);
+
+	    return;
+	}
+
+	if state.disconnected {
+	    // pending requests are answered by the reconnect logic
+	    return;
+	}
+
+	let mut request = state.requests.iter()
+	    .filter(|(_, (_, _, info))| info.unique == unique);
+
+	if let Some((seq, (fh, req, _))) = request.next() {
+	    trace!("interrupting active request #{seq:?} {req:?}");
+
+	    assert!(request.next().is_none());
+
+	    let seq = *seq;
+	    let fh = *fh;
+
+	    drop(state);
+
+	    self.handle_cuse(fh, Pending::Interrupt(seq), info);
+	}
+    }
+
+    fn send_error(&self, info: &OpInInfo, rc: nix::Error) {
+	info.send_error(&self.cuse, rc)
+	    .unwrap_or_else(|e| error!("failed to send error {rc:?}: {e:?}"));
+    }
+
+    /// Opens the remote device; establishes a connection when there is
+    /// none yet
+    fn open(self: Arc<Self>, fh: Handle, flags: fh_flags) -> nix::Result<()> {
+	let (tx, rx) = mpsc::channel();
+
+	{
+	    let mut state = self.state.write();
+
+	    if state.disconnected {
+		Self::defer_open(&mut state, fh, flags, tx);
+	    } else {
+		if !state.active {
+		    let (conn, params) = Self::connect(&self.connector, &self.heartbeat, &[])
+			.map_err(|e| {
+			    error!("failed to connect to {}: {e:?}", self.connector.addr());
+			    nix::Error::EIO
+			})?;
+
+		    let session = self.clone();
+
+		    std::thread::Builder::new()
+			.name("rx".to_string())
+			.spawn(move || session.rx_thread())
+			.map_err(|_| nix::Error::EAGAIN)?;
+
+		    state.conn = Some(Arc::new(conn));
+		    state.params = params;
+		    state.active = true;
+		}
+
+		let conn = state.conn.clone().unwrap();
+
+		Self::send_open(&mut state, &conn, fh, flags, tx);
+	    }
+	}
+
+	rx.recv().unwrap_or(Err(nix::Error::EIO))
+    }
+}
@@ -0,0 +1,895 @@
+//! Backend which forwards a CUSE device over a plain byte stream; either
+//! to a terminal server speaking the Telnet Com Port Control Option (RFC
+//! 2217) or to a raw TCP port like the ones of `ser2net` or `socat`.
+//!
+//! The tty state is kept locally.  With RFC 2217, changes are translated
+//! into commands and restored after a reconnect; raw streams have no way
+//! to transport them.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{Read, Write};
+use std::net::Shutdown;
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::{Mutex, RwLock};
+use nix::libc;
+
+use ensc_cuse_ffi::ffi::{self as cuse_ffi, fh_flags, poll_flags, poll_events};
+use ensc_cuse_ffi::{OpInInfo, WriteParams, ReadParams, PollParams};
+
+use ensc_ioctl_ffi::ffi::{self as ioctl_ffi, ioctl};
+use ensc_ioctl_ffi::termbits;
+
+use crate::{CuseFileDevice, Error};
+use crate::proto::{be32, Handle};
+use crate::proto::ioctl::{Arg, ICounter};
+use crate::rfc2217::{self as rfc, Command, Origin, telnet};
+use crate::transport::{Connector, Stream};
+
+use super::{CONNECT_TIMEOUT, RECONNECT_DELAY_MIN, RECONNECT_DELAY_MAX};
+use super::backend::{self, Backend, Pending};
+use super::tty::TtyState;
+
+/// Received data which exceeds this limit is dropped like on an
+/// overrun of a real tty
+const RX_BUF_MAX: usize = 64 * 1024;
+
+/// Duration of `tcsendbreak()`; the same as used by the Linux tty layer
+const BREAK_DURATION: Duration = Duration::from_millis(250);
+
+/// Modem input lines which are reported while a raw stream is connected
+const RAW_MSTAT: u32 = (termbits::TIOCM_CAR | termbits::TIOCM_DSR | termbits::TIOCM_CTS) as u32;
+
+/// How data and tty settings are transported over the stream
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Framing {
+    /// bytes are passed unchanged; tty settings are only emulated
+    Raw,
+    /// Telnet with the Com Port Control Option
+    Rfc2217,
+}
+
+#[derive(Default)]
+struct State {
+    conn:	Option<Arc<Stream>>,
+    /// the rx thread is running; either the connection is established or
+    /// a reconnect is in progress
+    active:	bool,
+    handles:	HashMap<Handle, fh_flags>,
+    tty:	TtyState,
+    /// received data which was not read yet
+    rx_buf:	VecDeque<u8>,
+    /// blocking reads which wait for data
+    reads:	VecDeque<(ReadParams, OpInInfo)>,
+    /// `TIOCMIWAIT` requests; the mask of lines and the counters when the
+    /// request was made
+    mwaits:	Vec<(u32, ioctl_ffi::serial_icounter_struct, OpInInfo)>,
+    /// poll handles which wait for a wakeup
+    poll_khs:	Vec<u64>,
+    /// `TIOCEXCL` was set; further opens fail until the last file is
+    /// closed
+    exclusive:	bool,
+}
+
+pub struct StreamBackend {
+    cuse:	Arc<CuseFileDevice>,
+    connector:	Arc<Connector>,
+    framing:	Framing,
+    state:	RwLock<State>,
+    /// serializes writes to the connection
+    tx_lock:	Arc<Mutex<()>>,
+}
+
+impl StreamBackend {
+    pub fn new(cuse: Arc<CuseFileDevice>, connector: Arc<Connector>, framing: Framing) -> Self {
+	Self {
+	    cuse:	cuse,
+	    connector:	connector,
+	    framing:	framing,
+	    state:	RwLock::new(State::default()),
+	    tx_lock:	Arc::new(Mutex::new(())),
+	}
+    }
+
+    fn send(&self, mut conn: &Stream, data: &[u8]) -> std::io::Result<()> {
+	let _tx = self.tx_lock.lock();
+
+	conn.write_all(data)
+    }
+
+    /// Sends `data` when being connected; else, it will be sent as part
+    /// of the setup after the reconnect.
+    fn send_cmds(&self, conn: Option<Arc<Stream>>, data: &[u8]) {
+	let Some(conn) = conn else {
+	    return;
+	};
+
+	if data.is_empty() || self.framing == Framing::Raw {
+	    return;
+	}
+
+	if let Err(e) = self.send(&conn, data) {
+	    warn!("failed to send commands: {e:?}");
+	    let _ = conn.shutdown(Shutdown::Both);
+	}
+    }
+
+    fn encode_mctrl(out: &mut Vec<u8>, mctrl: u32, old: Option<u32>) {
+	for (bit, on, off) in [(termbits::TIOCM_DTR, rfc::CONTROL_DTR_ON, rfc::CONTROL_DTR_OFF),
+			       (termbits::TIOCM_RTS, rfc::CONTROL_RTS_ON, rfc::CONTROL_RTS_OFF)] {
+	    let v = mctrl & bit as u32;
+
+	    if old.map(|o| o & bit as u32) == Some(v) {
+		continue;
+	    }
+
+	    let cmd = match v {
+		0	=> off,
+		_	=> on,
+	    };
+
+	    Command::SetControl(cmd).encode(out, Origin::Client);
+	}
+    }
+
+    /// Returns the option negotiation and the commands which bring the
+    /// remote port into the state of `tty`
+    fn setup(&self, tty: &TtyState) -> Vec<u8> {
+	let mut out = Vec::new();
+
+	if self.framing == Framing::Raw {
+	    return out;
+	}
+
+	for (cmd, opt) in [(telnet::WILL, telnet::OPT_COM_PORT),
+			   (telnet::WILL, telnet::OPT_BINARY),
+			   (telnet::DO, telnet::OPT_BINARY),
+			   (telnet::WILL, telnet::OPT_SGA),
+			   (telnet::DO, telnet::OPT_SGA)] {
+	    telnet::negotiate(&mut out, cmd, opt);
+	}
+
+	Command::SetModemstateMask(0xff).encode(&mut out, Origin::Client);
+
+	for cmd in rfc::line_commands(tty.termios()) {
+	    cmd.encode(&mut out, Origin::Client);
+	}
+
+	Self::encode_mctrl(&mut out, tty.mctrl(), None);
+
+	out
+    }
+
+    /// Configures a new connection and makes it the active one
+    fn attach(&self, state: &mut State, conn: Stream) -> Result<(), Error> {
+	let setup = self.setup(&state.tty);
+
+	if !setup.is_empty() {
+	    self.send(&conn, &setup)?;
+	}
+
+	if self.framing == Framing::Raw {
+	    state.tty.set_mstat(RAW_MSTAT);
+	    self.wakeup_mwaits(state);
+	}
+
+	state.conn = Some(Arc::new(conn));
+
+	Ok(())
+    }
+
+    fn handle_event(state: &mut State, ev: telnet::Event, reply: &mut Vec<u8>) {
+	match ev {
+	    telnet::Event::Negotiate(cmd, opt)	=> rfc::answer_negotiation(reply, cmd, opt),
+
+	    telnet::Event::Subneg(telnet::OPT_COM_PORT, payload) => match Command::decode(&payload) {
+		Some((Origin::Server, Command::NotifyModemstate(v)))	=>
+		    state.tty.set_mstat(rfc::modemstate_to_tiocm(v)),
+
+		Some((Origin::Server, Command::NotifyLinestate(v)))	=>
+		    debug!("line state {v:#04x}"),
+
+		Some((Origin::Server, cmd))	=>
+		    trace!("server acknowledged {cmd:?}"),
+
+		_				=>
+		    warn!("unexpected com port command {payload:?}"),
+	    },
+
+	    telnet::Event::Subneg(opt, _)	=>
+		debug!("ignoring subnegotiation of option {opt}"),
+	}
+    }
+
+    fn serve_reads(&self, state: &mut State) {
+	while !state.rx_buf.is_empty() {
+	    let Some((params, info)) = state.reads.pop_front() else {
+		break;
+	    };
+
+	    let len = state.rx_buf.len().min(params.size as usize);
+	    let data: Vec<u8> = state.rx_buf.drain(..len).collect();
+
+	    if let Err(e) = info.send_response(&self.cuse, &[ &data ]) {
+		warn!("failed to send read response: {e:?}");
+	    }
+	}
+    }
+
+    fn wakeup_polls(&self, state: &mut State) {
+	for kh in state.poll_khs.drain(..) {
+	    if let Err(e) = backend::send_poll_wakeup(&self.cuse, kh) {
+		warn!("failed to send poll wakeup: {e:?}");
+	    }
+	}
+    }
+
+    /// Answers `TIOCMIWAIT` requests whose lines changed
+    fn wakeup_mwaits(&self, state: &mut State) {
+	let State { mwaits, tty, .. } = state;
+
+	mwaits.retain(|(mask, snapshot, info)| {
+	    if !tty.modem_changed(*mask, snapshot) {
+		return true;
+	    }
+
+	    if let Err(e) = backend::send_ioctl_response(&self.cuse, info.clone(), ioctl::TIOCMIWAIT, Arg::None) {
+		warn!("failed to answer TIOCMIWAIT: {e:?}");
+	    }
+
+	    false
+	});
+    }
+
+    fn rx_loop(&self, mut conn: &Stream) {
+	let mut decoder = telnet::Decoder::default();
+	let mut events = Vec::new();
+	let mut buf = [0u8; 4096];
+
+	loop {
+	    let len = match conn.read(&mut buf) {
+		Ok(0)		=> {
+		    info!("connection closed by server");
+		    break;
+		}
+
+		Ok(len)		=> len,
+
+		Err(e) if e.kind() == std::io::ErrorKind::Interrupted	=> continue,
+
+		Err(e)		=> {
+		    warn!("failed to read from server: {e:?}");
+		    break;
+		}
+	    };
+
+	    let mut reply = Vec::new();
+	    let mut state = self.state.write();
+	    let old_len = state.rx_buf.len();
+
+	    match self.framing {
+		Framing::Raw		=> state.rx_buf.extend(&buf[..len]),
+		Framing::Rfc2217	=> {
+		    decoder.feed(&buf[..len], &mut state.rx_buf, &mut events);
+
+		    for ev in events.drain(..) {
+			Self::handle_event(&mut state, ev, &mut reply);
+		    }
+		}
+	    }
+
+	    let rx_len = state.rx_buf.len() - old_len;
+
+	    state.tty.count_rx(rx_len);
+
+	    if state.rx_buf.len() > RX_BUF_MAX {
+		warn!("rx buffer overrun; dropping {} bytes", state.rx_buf.len() - RX_BUF_MAX);
+		state.rx_buf.truncate(RX_BUF_MAX);
+		state.tty.count_overrun();
+	    }
+
+	    self.wakeup_mwaits(&mut state);
+
+	    if state.rx_buf.len() > old_len {
+		self.serve_reads(&mut state);
+		self.wakeup_polls(&mut state);
+	    }
+
+	    drop(state);
+
+	    if !reply.is_empty() {
+		if let Err(e) = self.send(conn, &reply) {
+		    warn!("failed to send telnet reply: {e:?}");
+		    break;
+		}
+	    }
+	}
+    }
+
+    /// Sleeps for the given duration; returns `false` when all files have
+    /// been closed in the meantime.
+    fn sleep_while_used(&self, mut delay: Duration) -> bool {
+	const STEP: Duration = Duration::from_millis(100);
+
+	while !self.state.read().handles.is_empty() {
+	    if delay.is_zero() {
+		return true;
+	    }
+
+	    let d = delay.min(STEP);
+
+	    std::thread::sleep(d);
+	    delay -= d;
+	}
+
+	false
+    }
+
+    fn reconnect(&self) -> Result<(), Error> {
+	let conn = self.connector.connect(CONNECT_TIMEOUT)?;
+
+	self.attach(&mut self.state.write(), conn)?;
+
+	info!("reconnected to {}", self.connector.addr());
+
+	Ok(())
+    }
+
+    fn rx_thread(self: Arc<Self>) {
+	info!("rx_thread running");
+
+	let mut delay = RECONNECT_DELAY_MIN;
+
+	loop {
+	    let conn = self.state.read().conn.clone();
+
+	    if let Some(conn) = conn {
+		self.rx_loop(&conn);
+
+		let _ = conn.shutdown(Shutdown::Both);
+
+		delay = RECONNECT_DELAY_MIN;
+	    }
+
+	    {
+		let mut state = self.state.write();
+
+		state.conn = None;
+
+		if self.framing == Framing::Raw {
+		    state.tty.set_mstat(0);
+		    self.wakeup_mwaits(&mut state);
+		}
+
+		// all files are closed; the next open establishes a new
+		// connection
+		if state.handles.is_empty() {
+		    state.active = false;
+		    break;
+		}
+	    }
+
+	    warn!("not connected to {}; retrying in {delay:?}", self.connector.addr());
+
+	    if !self.sleep_while_used(delay) {
+		continue;
+	    }
+
+	    if let Err(e) = self.reconnect() {
+		warn!("failed to reconnect to {}: {e:?}", self.connector.addr());
+		delay = (delay * 2).min(RECONNECT_DELAY_MAX);
+	    }
+	}
+
+	info!("rx_thread terminated");
+    }
+
+    fn release(&self, fh: Handle, info: OpInInfo) -> crate::Result<()> {
+	let mut state = self.state.write();
+
+	state.handles.remove(&fh);
+
+	if state.handles.is_empty() {
+	    state.rx_buf.clear();
+	    state.exclusive = false;
+
+	    if let Some(conn) = state.conn.take() {
+		let _ = conn.shutdown(Shutdown::Both);
+	    }
+	}
+
+	drop(state);
+
+	info.send_ok(&self.cuse)?;
+
+	Ok(())
+    }
+
+    fn write(&self, params: WriteParams, data: &[u8], info: OpInInfo) -> crate::Result<()> {
+	use ensc_cuse_ffi::AsBytes;
+
+	let conn = self.state.read().conn.clone();
+
+	let Some(conn) = conn else {
+	    let rc = match params.flags.intersects(fh_flags::NONBLOCK) {
+		true	=> nix::Error::EAGAIN,
+		false	=> nix::Error::EIO,
+	    };
+
+	    info.send_error(&self.cuse, rc)?;
+	    return Ok(());
+	};
+
+	let mut buf = Vec::new();
+
+	let data_out = match self.framing {
+	    Framing::Raw	=> data,
+	    Framing::Rfc2217	=> {
+		buf.reserve(data.len() + 16);
+		telnet::escape_into(&mut buf, data);
+		&buf
+	    }
+	};
+
+	if let Err(e) = self.send(&conn, data_out) {
+	    warn!("failed to send data: {e:?}");
+	    let _ = conn.shutdown(Shutdown::Both);
+	    info.send_error(&self.cuse, nix::Error::EIO)?;
+	    return Ok(());
+	}
+
+	self.state.write().tty.count_tx(data.len());
+
+	let write_resp = cuse_ffi::fuse_write_out {
+	    size:	data.len() as u32,
+	    _padding:	0
+	};
+
+	info.send_response(&self.cuse, &[ write_resp.as_bytes() ])?;
+
+	Ok(())
+    }
+
+    fn read(&self, params: ReadParams, info: OpInInfo) -> crate::Result<()> {
+	let mut state = self.state.write();
+
+	if state.rx_buf.is_empty() && params.flags.intersects(fh_flags::NONBLOCK) {
+	    drop(state);
+	    info.send_error(&self.cuse, nix::Error::EAGAIN)?;
+	    return Ok(());
+	}
+
+	state.reads.push_back((params, info));
+	self.serve_reads(&mut state);
+
+	Ok(())
+    }
+
+    fn poll(&self, params: PollParams, info: OpInInfo) -> crate::Result<()> {
+	use ensc_cuse_ffi::AsBytes;
+
+	let mut state = self.state.write();
+	let mut revents = poll_events::OUT;
+
+	if !state.rx_buf.is_empty() {
+	    revents = revents | poll_events::IN;
+	} else if params.flags.intersects(poll_flags::SCHEDULE_NOTIFY) &&
+	    !state.poll_khs.contains(&params.kh) {
+	    state.poll_khs.push(params.kh);
+	}
+
+	drop(state);
+
+	let poll_resp = cuse_ffi::fuse_poll_out {
+	    revents:	revents & params.events,
+	    padding:	0,
+	};
+
+	info.send_response(&self.cuse, &[ poll_resp.as_bytes() ])?;
+
+	Ok(())
+    }
+
+    fn flush(state: &mut State, queue: u64, out: &mut Vec<u8>) -> nix::Result<Arg> {
+	let purge = match queue as libc::c_int {
+	    libc::TCIFLUSH	=> rfc::PURGE_RX,
+	    libc::TCOFLUSH	=> rfc::PURGE_TX,
+	    libc::TCIOFLUSH	=> rfc::PURGE_BOTH,
+	    _			=> return Err(nix::Error::EINVAL),
+	};
+
+	if purge != rfc::PURGE_TX {
+	    state.rx_buf.clear();
+	}
+
+	Command::PurgeData(purge).encode(out, Origin::Client);
+
+	Ok(Arg::None)
+    }
+
+    /// Returns the duration of a `TCSBRK` or `TCSBRKP` request which
+    /// sends a break; `None` for other requests
+    fn break_duration(cmd: ioctl, arg: &Arg) -> Option<Duration> {
+	match (cmd, arg) {
+	    (ioctl::TCSBRK, Arg::Arg(v)) if v.as_native() == 0	=> Some(BREAK_DURATION),
+	    (ioctl::TCSBRKP, Arg::Arg(v))	=> match v.as_native() {
+		0	=> Some(BREAK_DURATION),
+		// unit is 0.1 seconds
+		d	=> Some(Duration::from_millis(d.saturating_mul(100))),
+	    },
+	    _					=> None,
+	}
+    }
+
+    fn send_control(tx_lock: &Mutex<()>, mut conn: &Stream, v: u8) {
+	let mut out = Vec::new();
+
+	Command::SetControl(v).encode(&mut out, Origin::Client);
+
+	let _tx = tx_lock.lock();
+
+	if let Err(e) = conn.write_all(&out) {
+	    warn!("failed to send control {v}: {e:?}");
+	}
+    }
+
+    /// Asserts break for `duration`; the request is answered in a helper
+    /// thread after the break has been cleared
+    fn timed_break(&self, cmd: ioctl, duration: Duration, info: OpInInfo) -> crate::Result<()> {
+	let conn = match self.framing {
+	    Framing::Raw	=> None,
+	    Framing::Rfc2217	=> self.state.read().conn.clone(),
+	};
+
+	let cuse = self.cuse.clone();
+	let tx_lock = self.tx_lock.clone();
+
+	std::thread::Builder::new()
+	    .name("break".to_string())
+	    .spawn(move || {
+		if let Some(conn) = &conn {
+		    Self::send_control(&tx_lock, conn, rfc::CONTROL_BREAK_ON);
+		}
+
+		std::thread::sleep(duration);
+
+		if let Some(conn) = &conn {
+		    Self::send_control(&tx_lock, conn, rfc::CONTROL_BREAK_OFF);
+		}
+
+		if let Err(e) = backend::send_ioctl_response(&cuse, info, cmd, Arg::None) {
+		    warn!("failed to answer break request: {e:?}");
+		}
+	    })?;
+
+	Ok(())
+    }
+
+    fn ioctl(&self, cmd: ioctl, arg: Arg, info: OpInInfo) -> crate::Result<()> {
+	if let Some(duration) = Self::break_duration(cmd, &arg) {
+	    return self.timed_break(cmd, duration, info);
+	}
+
+	if let (ioctl::TIOCMIWAIT, Arg::Arg(mask)) = (cmd, &arg) {
+	    let mut state = self.state.write();
+	    let snapshot = *state.tty.icount();
+
+	    state.mwaits.push((mask.as_native() as u32, snapshot, info));
+
+	    return Ok(());
+	}
+
+	let mut out = Vec::new();
+	let mut state = self.state.write();
+
+	let res = match (cmd, &arg) {
+	    (ioctl::TCGETS |
+	     ioctl::TCGETS2 |
+	     ioctl::TCGETA, _)		=> Ok(Arg::TermIOs(state.tty.termios().clone())),
+
+	    (ioctl::TCSETS |
+	     ioctl::TCSETSW |
+	     ioctl::TCSETSF |
+	     ioctl::TCSETS2 |
+	     ioctl::TCSETSW2 |
+	     ioctl::TCSETSF2 |
+	     ioctl::TCSETA |
+	     ioctl::TCSETAW |
+	     ioctl::TCSETAF, Arg::TermIOs(ios))	=> {
+		let old = rfc::line_commands(state.tty.termios());
+
+		let ios = match cmd {
+		    ioctl::TCSETA |
+		    ioctl::TCSETAW |
+		    ioctl::TCSETAF	=> ios.merge_termio(state.tty.termios()),
+		    ioctl::TCSETS |
+		    ioctl::TCSETSW |
+		    ioctl::TCSETSF	=> ios.inherit_speed(state.tty.termios()),
+		    _			=> ios.clone(),
+		};
+
+		state.tty.set_termios(ios);
+
+		for (old, new) in old.iter().zip(rfc::line_commands(state.tty.termios())) {
+		    if *old != new {
+			new.encode(&mut out, Origin::Client);
+		    }
+		}
+
+		match cmd {
+		    ioctl::TCSETSF |
+		    ioctl::TCSETSF2 |
+		    ioctl::TCSETAF	=> Self::flush(&mut state, libc::TCIFLUSH as u64, &mut out),
+		    _			=> Ok(Arg::None),
+		}
+	    }
+
+	    (ioctl::TIOCMGET, _)		=> Ok(Arg::Int(be32::from_native(state.tty.modem()))),
+
+	    (ioctl::TIOCMSET |
+	     ioctl::TIOCMBIS |
+	     ioctl::TIOCMBIC, Arg::Int(v))	=> {
+		let old = state.tty.mctrl();
+
+		state.tty.set_mctrl(cmd, v.as_native());
+		Self::encode_mctrl(&mut out, state.tty.mctrl(), Some(old));
+
+		Ok(Arg::None)
+	    }
+
+	    // data is passed to the socket immediately
+	    (ioctl::TIOCSERGETLSR, _)		=>
+		Ok(Arg::Int(be32::from_native(ioctl_ffi::TIOCSER_TEMT as u32))),
+
+	    (ioctl::TIOCGICOUNT, _)		=> Ok(Arg::ICounter(ICounter::from_os(state.tty.icount()))),
+
+	    (ioctl::TIOCGWINSZ, _)		=> Ok(Arg::WinSize(state.tty.winsize().clone())),
+
+	    (ioctl::TIOCSWINSZ, Arg::WinSize(ws))	=> {
+		state.tty.set_winsize(ws.clone());
+		Ok(Arg::None)
+	    }
+
+	    (ioctl::TIOCSBRK, _)		=> {
+		Command::SetControl(rfc::CONTROL_BREAK_ON).encode(&mut out, Origin::Client);
+		Ok(Arg::None)
+	    }
+
+	    (ioctl::TIOCCBRK, _)		=> {
+		Command::SetControl(rfc::CONTROL_BREAK_OFF).encode(&mut out, Origin::Client);
+		Ok(Arg::None)
+	    }
+
+	    // tcdrain(); written data has already been passed to the socket
+	    (ioctl::TCSBRK, _)			=> Ok(Arg::None),
+
+	    (ioctl::TCFLSH, Arg::Arg(queue))	=> Self::flush(&mut state, queue.as_native(), &mut out),
+
+	    // data is passed to the socket immediately
+	    (ioctl::TIOCOUTQ, _)		=> Ok(Arg::Int(be32::from_native(0))),
+
+	    (ioctl::TCXONC, Arg::Arg(action))	=> match action.as_native() as libc::c_int {
+		libc::TCIOFF	=> {
+		    Command::FlowcontrolSuspend.encode(&mut out, Origin::Client);
+		    Ok(Arg::None)
+		}
+
+		libc::TCION	=> {
+		    Command::FlowcontrolResume.encode(&mut out, Origin::Client);
+		    Ok(Arg::None)
+		}
+
+		// output can not be suspended; data is passed to the socket
+		// immediately
+		_		=> Err(nix::Error::EINVAL),
+	    },
+
+	    (ioctl::TIOCEXCL |
+	     ioctl::TIOCNXCL, _)		=> {
+		state.exclusive = cmd == ioctl::TIOCEXCL;
+		Ok(Arg::None)
+	    }
+
+	    (ioctl::TIOCGEXCL, _)		=> Ok(Arg::Int(be32::from_native(state.exclusive as u32))),
+
+	    _					=> {
+		debug!("unsupported ioctl {cmd:?} {arg:?}");
+		Err(nix::Error::ENOTTY)
+	    }
+	};
+
+	let conn = state.conn.clone();
+
+	drop(state);
+
+	self.send_cmds(conn, &out);
+
+	match res {
+	    Ok(arg)	=> backend::send_ioctl_response(&self.cuse, info, cmd, arg),
+	    Err(e)	=> Ok(info.send_error(&self.cuse, e)?),
+	}
+    }
+}
+
+impl Backend for StreamBackend {
+    fn open(self: Arc<Self>, fh: Handle, flags: fh_flags) -> nix::Result<()> {
+	let mut state = self.state.write();
+
+	if state.handles.contains_key(&fh) {
+	    warn!("file {fh:?} already opened");
+	    return Err(nix::Error::EINVAL);
+	}
+
+	if state.exclusive {
+	    return Err(nix::Error::EBUSY);
+	}
+
+	if !state.active {
+	    self.connector.connect(CONNECT_TIMEOUT)
+		.and_then(|conn| self.attach(&mut state, conn))
+		.map_err(|e| {
+		    error!("failed to connect to {}: {e:?}", self.connector.addr());
+		    nix::Error::EIO
+		})?;
+
+	    let backend = self.clone();
+
+	    std::thread::Builder::new()
+		.name("rx".to_string())
+		.spawn(move || backend.rx_thread())
+		.map_err(|_| nix::Error::EAGAIN)?;
+
+	    state.active = true;
+	}
+
+	state.handles.insert(fh, flags);
+
+	Ok(())
+    }
+
+    fn handle_cuse(&self, fh: Handle, req: Pending, info: OpInInfo) {
+	debug!("handle {req:?}@{fh:?}");
+
+	let res = match req {
+	    Pending::Release		=> self.release(fh, info),
+	    Pending::Write(params, data)	=> self.write(params, &data, info),
+	    Pending::Read(params)	=> self.read(params, info),
+	    Pending::Ioctl { cmd, arg }	=> self.ioctl(cmd, arg, info),
+	    Pending::Poll(params)	=> self.poll(params, info),
+
+	    Pending::Interrupt(_)	=> {
+		warn!("unexpected interrupt request");
+		Ok(())
+	    }
+	};
+
+	if let Err(e) = res {
+	    warn!("failed to handle request: {e:?}");
+	}
+    }
+
+    fn try_interrupt(&self, _info: OpInInfo, unique: cuse_ffi::unique_t) {
+	let mut state = self.state.write();
+
+	let pos = state.reads.iter()
+	    .position(|(_, info)| info.unique == unique);
+
+	if let Some((_, info)) = pos.and_then(|pos| state.reads.remove(pos)) {
+	    drop(state);
+
+	    trace!("interrupting read {info:?}");
+	    self.send_error(&info, nix::Error::EINTR);
+	    return;
+	}
+
+	let pos = state.mwaits.iter()
+	    .position(|(_, _, info)| info.unique == unique);
+
+	if let Some(pos) = pos {
+	    let (_, _, info) = state.mwaits.remove(pos);
+
+	    drop(state);
+
+	    trace!("interrupting TIOCMIWAIT {info:?}");
+	    self.send_error(&info, nix::Error::EINTR);
+	}
+    }
+
+    fn send_error(&self, info: &OpInInfo, rc: nix::Error) {
+	info.send_error(&self.cuse, rc)
+	    .unwrap_or_else(|e| error!("failed to send error {rc:?}: {e:?}"));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+    use std::os::fd::OwnedFd;
+    use std::os::unix::net::UnixStream;
+
+    use ensc_cuse_ffi::ffi::{fuse_opcode, unique_t};
+
+    use crate::proto::be64;
+    use crate::transport::Address;
+    use super::*;
+
+    /// Creates an unconnected backend; its CUSE responses can be read
+    /// from the returned socket
+    fn backend() -> (Arc<StreamBackend>, UnixStream) {
+	let (cuse, resp) = UnixStream::pair().unwrap();
+	let cuse = CuseFileDevice::new(File::from(OwnedFd::from(cuse)));
+	let connector = Connector::new(Address::Unix("/nonexisting".into()), None, None);
+
+	(Arc::new(StreamBackend::new(Arc::new(cuse), Arc::new(connector), Framing::Rfc2217)),
+	 resp)
+    }
+
+    fn info() -> OpInInfo {
+	OpInInfo {
+	    opcode:	fuse_opcode::FUSE_IOCTL,
+	    unique:	unique_t::from_ffi(1),
+	    nodeid:	0,
+	    uid:	0,
+	    gid:	0,
+	    pid:	0,
+	}
+    }
+
+    /// Returns the error code and the ioctl result of a CUSE response
+    fn recv(mut resp: &UnixStream) -> (i32, Vec<u8>) {
+	let mut hdr = [0u8; 16];
+
+	resp.read_exact(&mut hdr).unwrap();
+
+	let len = u32::from_ne_bytes(hdr[0..4].try_into().unwrap()) as usize;
+	let err = i32::from_ne_bytes(hdr[4..8].try_into().unwrap());
+	let mut data = vec![0; len - hdr.len()];
+
+	resp.read_exact(&mut data).unwrap();
+
+	// skip 'struct fuse_ioctl_out'
+	(err, data.get(16..).unwrap_or_default().to_vec())
+    }
+
+    #[test]
+    fn test_exclusive() {
+	let (backend, resp) = backend();
+
+	backend.ioctl(ioctl::TIOCGEXCL, Arg::None, info()).unwrap();
+	assert_eq!(recv(&resp), (0, 0i32.to_ne_bytes().to_vec()));
+
+	backend.ioctl(ioctl::TIOCEXCL, Arg::None, info()).unwrap();
+	assert_eq!(recv(&resp), (0, vec![]));
+
+	backend.ioctl(ioctl::TIOCGEXCL, Arg::None, info()).unwrap();
+	assert_eq!(recv(&resp), (0, 1i32.to_ne_bytes().to_vec()));
+
+	// fails before a connection is attempted
+	assert_eq!(backend.clone().open(Handle::from_ffi(1), fh_flags::empty()),
+		   Err(nix::Error::EBUSY));
+
+	backend.ioctl(ioctl::TIOCNXCL, Arg::None, info()).unwrap();
+	assert_eq!(recv(&resp), (0, vec![]));
+
+	backend.ioctl(ioctl::TIOCGEXCL, Arg::None, info()).unwrap();
+	assert_eq!(recv(&resp), (0, 0i32.to_ne_bytes().to_vec()));
+    }
+
+    #[test]
+    fn test_tcxonc() {
+	let (backend, resp) = backend();
+	let action = |v: libc::c_int| Arg::Arg(be64::from_native(v as u64));
+
+	for (v, err) in [(libc::TCIOFF, 0),
+			 (libc::TCION, 0),
+			 (libc::TCOOFF, -libc::EINVAL),
+			 (libc::TCOON, -libc::EINVAL)] {
+	    backend.ioctl(ioctl::TCXONC, action(v), info()).unwrap();
+	    assert_eq!(recv(&resp).0, err);
+	}
+
+	backend.ioctl(ioctl::TIOCOUTQ, Arg::None, info()).unwrap();
+	assert_eq!(recv(&resp), (0, 0i32.to_ne_bytes().to_vec()));
+    }
+}
@@ -0,0 +1,174 @@
+//! Locally emulated tty state for backends which do not forward ioctls
+//! to a real device
+
+use ensc_ioctl_ffi::ffi as ioctl_ffi;
+use ioctl_ffi::ioctl;
+use ensc_ioctl_ffi::termbits;
+use nix::libc;
+
+use crate::proto::ioctl::{TermIOs, WinSize};
+
+/// Modem lines which can be set by the application
+const TIOCM_CTRL: u32 = (termbits::TIOCM_DTR | termbits::TIOCM_RTS) as u32;
+
+#[derive(Debug, Clone)]
+pub struct TtyState {
+    termios:	TermIOs,
+    /// DTR and RTS as set by the application
+    mctrl:	u32,
+    /// input lines as reported by the remote side
+    mstat:	u32,
+    winsize:	WinSize,
+    /// counters like reported by `TIOCGICOUNT`
+    icount:	ioctl_ffi::serial_icounter_struct,
+}
+
+impl Default for TtyState {
+    fn default() -> Self {
+	let mut c_cc: [ioctl_ffi::cc_t; ioctl_ffi::NCCS] = Default::default();
+
+	c_cc[libc::VMIN] = 1;
+
+	let termios = ioctl_ffi::termios2 {
+	    c_iflag:	0.into(),
+	    c_oflag:	0.into(),
+	    c_cflag:	(libc::B9600 | libc::CS8 | libc::CREAD | libc::CLOCAL | libc::HUPCL).into(),
+	    c_lflag:	0.into(),
+	    c_line:	0,
+	    c_cc:	c_cc,
+	    c_ispeed:	9600,
+	    c_ospeed:	9600,
+	};
+
+	Self {
+	    termios:	TermIOs::from_os2(&termios),
+	    mctrl:	TIOCM_CTRL,
+	    mstat:	0,
+	    winsize:	WinSize::default(),
+	    icount:	Default::default(),
+	}
+    }
+}
+
+impl TtyState {
+    pub fn termios(&self) -> &TermIOs {
+	&self.termios
+    }
+
+    /// Sets new termios; their `CBAUD` bits and speed fields were made
+    /// consistent by the conversion from the os representation
+    pub fn set_termios(&mut self, termios: TermIOs) {
+	self.termios = termios;
+    }
+
+    pub fn winsize(&self) -> &WinSize {
+	&self.winsize
+    }
+
+    pub fn set_winsize(&mut self, winsize: WinSize) {
+	self.winsize = winsize;
+    }
+
+    /// Returns the `TIOCM_*` bits of all modem lines
+    pub fn modem(&self) -> u32 {
+	self.mctrl | self.mstat
+    }
+
+    pub fn mctrl(&self) -> u32 {
+	self.mctrl
+    }
+
+    pub fn set_mstat(&mut self, mstat: u32) {
+	let mstat = mstat & !TIOCM_CTRL;
+	let changed = self.mstat ^ mstat;
+	let cnt = &mut self.icount;
+
+	for (bit, counter) in [(termbits::TIOCM_CTS, &mut cnt.cts),
+			       (termbits::TIOCM_DSR, &mut cnt.dsr),
+			       (termbits::TIOCM_CAR, &mut cnt.dcd)] {
+	    if changed & bit as u32 != 0 {
+		*counter = counter.wrapping_add(1);
+	    }
+	}
+
+	// like serial drivers, count only the trailing edge of RI
+	if self.mstat & !mstat & termbits::TIOCM_RNG as u32 != 0 {
+	    cnt.rng = cnt.rng.wrapping_add(1);
+	}
+
+	self.mstat = mstat;
+    }
+
+    pub fn icount(&self) -> &ioctl_ffi::serial_icounter_struct {
+	&self.icount
+    }
+
+    pub fn count_rx(&mut self, len: usize) {
+	self.icount.rx = self.icount.rx.wrapping_add(len as _);
+    }
+
+    pub fn count_tx(&mut self, len: usize) {
+	self.icount.tx = self.icount.tx.wrapping_add(len as _);
+    }
+
+    pub fn count_overrun(&mut self) {
+	self.icount.buf_overrun = self.icount.buf_overrun.wrapping_add(1);
+    }
+
+    /// Returns whether one of the lines in `mask` changed since
+    /// `snapshot` was taken; this is the condition for `TIOCMIWAIT`
+    pub fn modem_changed(&self, mask: u32, snapshot: &ioctl_ffi::serial_icounter_struct) -> bool {
+	let cnt = &self.icount;
+
+	[(termbits::TIOCM_RNG, cnt.rng, snapshot.rng),
+	 (termbits::TIOCM_DSR, cnt.dsr, snapshot.dsr),
+	 (termbits::TIOCM_CAR, cnt.dcd, snapshot.dcd),
+	 (termbits::TIOCM_CTS, cnt.cts, snapshot.cts)]
+	    .iter()
+	    .any(|(bit, new, old)| mask & *bit as u32 != 0 && new != old)
+    }
+
+    /// Applies `TIOCMSET`, `TIOCMBIS` or `TIOCMBIC` to the output lines
+    pub fn set_mctrl(&mut self, cmd: ioctl, v: u32) {
+	let v = v & TIOCM_CTRL;
+
+	match cmd {
+	    ioctl::TIOCMSET	=> self.mctrl = v,
+	    ioctl::TIOCMBIS	=> self.mctrl |= v,
+	    ioctl::TIOCMBIC	=> self.mctrl &= !v,
+	    _			=> warn!("unexpected modem ioctl {cmd:?}"),
+	}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_icount() {
+	let mut tty = TtyState::default();
+	let snapshot = *tty.icount();
+
+	tty.set_mstat((termbits::TIOCM_CTS | termbits::TIOCM_RNG | termbits::TIOCM_DTR) as u32);
+	assert_eq!(tty.icount().cts, 1);
+	assert_eq!(tty.icount().rng, 0);
+	assert!(tty.modem_changed(termbits::TIOCM_CTS as u32, &snapshot));
+	assert!(!tty.modem_changed((termbits::TIOCM_DSR | termbits::TIOCM_CAR | termbits::TIOCM_RNG) as u32,
+				   &snapshot));
+
+	// only the trailing edge of RI is counted
+	let snapshot = *tty.icount();
+
+	tty.set_mstat(termbits::TIOCM_CTS as u32);
+	assert_eq!(tty.icount().cts, 1);
+	assert_eq!(tty.icount().rng, 1);
+	assert!(tty.modem_changed(termbits::TIOCM_RNG as u32, &snapshot));
+	assert!(!tty.modem_changed(termbits::TIOCM_CTS as u32, &snapshot));
+
+	tty.count_rx(3);
+	tty.count_tx(5);
+	tty.count_overrun();
+	assert_eq!((tty.icount().rx, tty.icount().tx, tty.icount().buf_overrun), (3, 5, 1));
+    }
+}